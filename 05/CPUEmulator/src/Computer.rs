@@ -0,0 +1,173 @@
+use super::Screen::Screen;
+
+pub const ROM_SIZE: usize = 32768;
+pub const RAM_SIZE: usize = 32768;
+pub const KBD: usize = 24576;
+
+// The Hack computer: 32K words of ROM and RAM, the A, D and PC registers.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Computer {
+    rom: Vec<u16>,
+    pub ram: Vec<u16>,
+    pub a: u16,
+    pub d: u16,
+    pub pc: u16,
+    pub cycles: u64,
+}
+
+// Reads the text of a .hack file, one 16 digit binary word per line.
+pub fn parse_hack(text: &str) -> Result<Vec<u16>, String> {
+    let mut rom = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.len() != 16 {
+            return Err(format!("line {}: not a 16 bit instruction", i + 1));
+        }
+        let word = u16::from_str_radix(line, 2).map_err(|_| format!("line {}: not a binary number", i + 1))?;
+        rom.push(word);
+    }
+    if rom.len() > ROM_SIZE {
+        return Err("the program does not fit in ROM".to_string());
+    }
+    Ok(rom)
+}
+
+impl Computer {
+    pub fn new(program: &[u16]) -> Self {
+        let mut rom = program.to_vec();
+        // the rest of ROM is @0
+        rom.resize(ROM_SIZE, 0);
+        Computer { rom: rom, ram: vec![0; RAM_SIZE], a: 0, d: 0, pc: 0, cycles: 0 }
+    }
+
+    pub fn rom(&self) -> &[u16] {
+        &self.rom
+    }
+
+    // Executes the instruction at PC.
+    pub fn step(&mut self) {
+        let i = self.rom[self.pc as usize & (ROM_SIZE - 1)];
+        self.cycles += 1;
+        if i & 0x8000 == 0 {
+            self.a = i;
+            self.pc = self.pc.wrapping_add(1);
+            return;
+        }
+        let address = self.a as usize & (RAM_SIZE - 1);
+        let y = if i & 0x1000 != 0 { self.ram[address] } else { self.a };
+        let out = alu(self.d, y, (i >> 6) & 0x3f);
+        // M is written at the address A held before this instruction
+        if i & 0x08 != 0 {
+            self.ram[address] = out;
+        }
+        let jump = i & 0x07;
+        let target = self.a;
+        if i & 0x20 != 0 {
+            self.a = out;
+        }
+        if i & 0x10 != 0 {
+            self.d = out;
+        }
+        let negative = out & 0x8000 != 0;
+        let zero = out == 0;
+        let taken = (jump & 4 != 0 && negative) || (jump & 2 != 0 && zero) || (jump & 1 != 0 && !negative && !zero);
+        self.pc = if taken { target } else { self.pc.wrapping_add(1) };
+    }
+
+    // Runs until the program halts or the cycle count reaches limit.
+    pub fn run(&mut self, limit: u64) {
+        while self.cycles < limit && !self.is_halted() {
+            self.step();
+        }
+    }
+
+    // True at the usual end of a Hack program, an @X / 0;JMP loop on
+    // itself, e.g. (END) @END 0;JMP.
+    pub fn is_halted(&self) -> bool {
+        let pc = self.pc as usize;
+        is_halt_loop(&self.rom, pc) || (pc > 0 && is_halt_loop(&self.rom, pc - 1) && self.a as usize == pc - 1)
+    }
+
+    pub fn screen(&self) -> Screen {
+        Screen::from_ram(&self.ram)
+    }
+}
+
+// An @X / 0;JMP loop at address X.
+pub fn is_halt_loop(rom: &[u16], at: usize) -> bool {
+    at + 1 < rom.len() && rom[at] as usize == at && is_unconditional_jump(rom[at + 1])
+}
+
+// A C instruction that always jumps and writes nothing.
+fn is_unconditional_jump(i: u16) -> bool {
+    i & 0xe000 == 0xe000 && i & 0x38 == 0 && i & 0x07 == 0x07
+}
+
+// The Hack ALU: control bits zx nx zy ny f no, from the most significant.
+pub fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let mut x = x;
+    let mut y = y;
+    if control & 0x20 != 0 {
+        x = 0;
+    }
+    if control & 0x10 != 0 {
+        x = !x;
+    }
+    if control & 0x08 != 0 {
+        y = 0;
+    }
+    if control & 0x04 != 0 {
+        y = !y;
+    }
+    let out = if control & 0x02 != 0 { x.wrapping_add(y) } else { x & y };
+    if control & 0x01 != 0 { !out } else { out }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn load(path: &str) -> Computer {
+        let text = std::fs::read_to_string(path).unwrap();
+        Computer::new(&parse_hack(&text).unwrap())
+    }
+
+    #[test]
+    fn add_and_max() {
+        let mut c = load("../Add.hack");
+        c.run(1000);
+        assert_eq!(c.ram[0], 5);
+
+        let mut c = load("../Max.hack");
+        c.ram[0] = 3;
+        c.ram[1] = 8;
+        c.run(1000);
+        assert_eq!(c.ram[2], 8);
+    }
+
+    #[test]
+    fn rect() {
+        let mut c = load("../Rect.hack");
+        c.ram[0] = 4;
+        c.run(10000);
+        assert!(c.is_halted());
+        let s = c.screen();
+        // a 16 pixel wide rectangle, 4 rows high
+        assert!((0..4).all(|y| (0..16).all(|x| s.pixel(x, y))));
+        assert!(!s.pixel(16, 0) && !s.pixel(0, 4));
+    }
+
+    #[test]
+    fn alu_functions() {
+        // D+A, D-A, !D, D&A
+        assert_eq!(alu(5, 3, 0b000010), 8);
+        assert_eq!(alu(5, 3, 0b010011), 2);
+        assert_eq!(alu(5, 3, 0b001101), !5);
+        assert_eq!(alu(5, 3, 0b000000), 1);
+        assert!(parse_hack("0101\n").is_err());
+    }
+}
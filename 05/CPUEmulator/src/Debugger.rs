@@ -0,0 +1,627 @@
+use super::Computer::{Computer, RAM_SIZE, ROM_SIZE};
+use super::SymbolMap::SymbolMap;
+use super::Disassembler;
+use super::Snapshot::Snapshot;
+use super::Trace::{ram_location, view_entry, Trace};
+
+// RAM[0], the stack pointer of the VM, and where its stack starts.
+const SP: usize = 0;
+const STACK_BASE: u16 = 256;
+// continue stops after this many cycles without a break
+const RUN_LIMIT: u64 = 100_000_000;
+// the instructions kept for going back, about 16 bytes each
+const TRACE_SIZE: usize = 1 << 20;
+
+// A break on a RAM word: whenever it changes, or when a condition on it
+// becomes true, e.g. SP < 256.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Watchpoint {
+    pub address: u16,
+    pub condition: Option<(Comparison, i16)>,
+}
+
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Comparison {
+    LT,
+    LE,
+    GT,
+    GE,
+    EQ,
+    NE,
+}
+
+impl Comparison {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "<" => Some(Comparison::LT),
+            "<=" => Some(Comparison::LE),
+            ">" => Some(Comparison::GT),
+            ">=" => Some(Comparison::GE),
+            "==" | "=" => Some(Comparison::EQ),
+            "!=" => Some(Comparison::NE),
+            _ => None,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::LT => "<",
+            Comparison::LE => "<=",
+            Comparison::GT => ">",
+            Comparison::GE => ">=",
+            Comparison::EQ => "==",
+            Comparison::NE => "!=",
+        }
+    }
+
+    // RAM words compare as signed numbers
+    fn holds(&self, value: u16, operand: i16) -> bool {
+        let value = value as i16;
+        match self {
+            Comparison::LT => value < operand,
+            Comparison::LE => value <= operand,
+            Comparison::GT => value > operand,
+            Comparison::GE => value >= operand,
+            Comparison::EQ => value == operand,
+            Comparison::NE => value != operand,
+        }
+    }
+}
+
+impl Watchpoint {
+    fn triggered(&self, before: u16, after: u16) -> bool {
+        match self.condition {
+            Some((c, operand)) => c.holds(after, operand) && !c.holds(before, operand),
+            None => before != after,
+        }
+    }
+}
+
+// A line-mode debugger for a Hack program. execute() runs one command and
+// returns what to print.
+pub struct Debugger {
+    pub computer: Computer,
+    pub symbols: SymbolMap,
+    // by number, which is never reused
+    breakpoints: Vec<(usize, u16)>,
+    watchpoints: Vec<(usize, Watchpoint)>,
+    next_number: usize,
+    // the last instructions run, to undo them
+    trace: Trace,
+}
+
+const HELP: &str = "\
+break LOCATION      break at a ROM address or label, e.g. (Main.main)
+watch ADDRESS [OP VALUE]
+                    break when a RAM word changes, or when e.g. SP < 256
+delete [N]          delete breakpoint or watchpoint N, or all of them
+info                list breakpoints and watchpoints
+step [N]            execute N instructions
+continue            run to a breakpoint, a watchpoint or the end
+until LOCATION      run to a ROM address or label
+regs                show the registers, the instruction and the stack
+x ADDRESS [N]       show N RAM words
+set ADDRESS VALUE   write a RAM word, or A, D or PC
+list [LOCATION]     disassemble around PC or a location
+stack [N]           show the top N words of the VM stack
+reverse-step [N]    undo N instructions
+reverse-continue    run backward to a breakpoint, a watchpoint or as far
+                    as the trace goes
+last-write ADDRESS  show the last instruction that wrote a RAM word
+reverse-write ADDRESS
+                    run backward to just before that instruction
+trace [N]           show the last N instructions run
+save FILE           write a snapshot of RAM and the registers
+restore FILE        go back to a snapshot of this program
+quit";
+
+impl Debugger {
+    pub fn new(computer: Computer, symbols: SymbolMap) -> Self {
+        Debugger { computer: computer, symbols: symbols, breakpoints: vec![], watchpoints: vec![], next_number: 1, trace: Trace::new(TRACE_SIZE) }
+    }
+
+    pub fn execute(&mut self, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            return String::new();
+        }
+        let r = match words[0] {
+            "break" | "b" => self.add_breakpoint(&words[1..]),
+            "watch" | "w" => self.add_watchpoint(&words[1..]),
+            "delete" | "d" => self.delete(&words[1..]),
+            "info" | "i" => Ok(self.info()),
+            "step" | "s" => self.step(&words[1..]),
+            "continue" | "c" => Ok(self.run(None)),
+            "until" | "u" => match words.get(1) {
+                Some(l) => self.rom_location(l).map(|a| self.run(Some(a))),
+                None => Err("until takes a location".to_string()),
+            },
+            "regs" | "r" => Ok(self.status()),
+            "x" => self.examine(&words[1..]),
+            "set" => self.set(&words[1..]),
+            "list" | "l" => self.list(&words[1..]),
+            "stack" => self.stack(&words[1..]),
+            "reverse-step" | "rs" => self.reverse_step(&words[1..]),
+            "reverse-continue" | "rc" => Ok(self.reverse_continue()),
+            "last-write" | "lw" => self.last_write(&words[1..], false),
+            "reverse-write" | "rw" => self.last_write(&words[1..], true),
+            "trace" => self.show_trace(&words[1..]),
+            "save" => self.save(&words[1..]),
+            "restore" => self.restore(&words[1..]),
+            "help" | "h" => Ok(HELP.to_string()),
+            w => Err(format!("unknown command: {}", w)),
+        };
+        r.unwrap_or_else(|e| e)
+    }
+
+    fn add_breakpoint(&mut self, args: &[&str]) -> Result<String, String> {
+        let address = self.rom_location(args.get(0).ok_or("break takes a location")?)?;
+        let n = match self.breakpoint_at(address) {
+            Some(n) => n,
+            None => {
+                let n = self.take_number();
+                self.breakpoints.push((n, address));
+                n
+            },
+        };
+        Ok(format!("breakpoint {} at {} ({})", n, address, self.symbols.location(address)))
+    }
+
+    fn take_number(&mut self) -> usize {
+        self.next_number += 1;
+        self.next_number - 1
+    }
+
+    fn breakpoint_at(&self, pc: u16) -> Option<usize> {
+        self.breakpoints.iter().find(|(_, b)| *b == pc).map(|(n, _)| *n)
+    }
+
+    fn add_watchpoint(&mut self, args: &[&str]) -> Result<String, String> {
+        let address = self.ram_location(args.get(0).ok_or("watch takes an address")?)?;
+        let condition = match args.len() {
+            1 => None,
+            3 => {
+                let c = Comparison::parse(args[1]).ok_or_else(|| format!("unknown comparison: {}", args[1]))?;
+                let operand = parse_number(args[2]).ok_or_else(|| format!("not a number: {}", args[2]))?;
+                Some((c, operand as u16 as i16))
+            },
+            _ => return Err("watch takes ADDRESS [OP VALUE]".to_string()),
+        };
+        let w = Watchpoint { address: address, condition: condition };
+        let n = self.take_number();
+        let s = format!("watchpoint {} on {}", n, self.describe(&w));
+        self.watchpoints.push((n, w));
+        Ok(s)
+    }
+
+    // Breakpoints and watchpoints share one numbering.
+    fn delete(&mut self, args: &[&str]) -> Result<String, String> {
+        let n = match args.get(0) {
+            Some(n) => n.parse::<usize>().map_err(|_| format!("not a number: {}", n))?,
+            None => {
+                self.breakpoints.clear();
+                self.watchpoints.clear();
+                return Ok("deleted all breakpoints and watchpoints".to_string());
+            },
+        };
+        let (breakpoints, watchpoints) = (self.breakpoints.len(), self.watchpoints.len());
+        self.breakpoints.retain(|(b, _)| *b != n);
+        self.watchpoints.retain(|(w, _)| *w != n);
+        if self.breakpoints.len() == breakpoints && self.watchpoints.len() == watchpoints {
+            return Err(format!("no breakpoint or watchpoint {}", n));
+        }
+        Ok(format!("deleted {}", n))
+    }
+
+    fn info(&self) -> String {
+        let mut lines = vec![];
+        let mut numbered: Vec<(usize, String)> = self.breakpoints.iter()
+            .map(|(n, b)| (*n, format!("{}: break {} ({})", n, b, self.symbols.location(*b))))
+            .chain(self.watchpoints.iter().map(|(n, w)| (*n, format!("{}: watch {}", n, self.describe(w)))))
+            .collect();
+        numbered.sort();
+        lines.extend(numbered.into_iter().map(|(_, l)| l));
+        if lines.is_empty() {
+            return "no breakpoints or watchpoints".to_string();
+        }
+        lines.join("\n")
+    }
+
+    fn describe(&self, w: &Watchpoint) -> String {
+        let name = match self.symbols.variables_at(w.address).first() {
+            Some(n) => format!("{} ({})", w.address, n),
+            None => w.address.to_string(),
+        };
+        match w.condition {
+            Some((c, operand)) => format!("{} {} {}", name, c.symbol(), operand),
+            None => name,
+        }
+    }
+
+    fn step(&mut self, args: &[&str]) -> Result<String, String> {
+        let n = match args.get(0) {
+            Some(n) => n.parse::<u64>().map_err(|_| format!("not a number: {}", n))?,
+            None => 1,
+        };
+        let mut stop = None;
+        for _ in 0..n {
+            stop = self.step_watched();
+            if stop.is_some() {
+                break;
+            }
+        }
+        Ok(self.report(stop))
+    }
+
+    // Runs to target, a breakpoint or a watchpoint. The instruction at PC
+    // runs first, so that continue leaves a breakpoint.
+    fn run(&mut self, target: Option<u16>) -> String {
+        let start = self.computer.cycles;
+        let mut stop = None;
+        loop {
+            if self.computer.is_halted() {
+                stop = Some("halted".to_string());
+                break;
+            }
+            if let Some(s) = self.step_watched() {
+                stop = Some(s);
+                break;
+            }
+            let pc = self.computer.pc;
+            if target == Some(pc) {
+                break;
+            }
+            if let Some(n) = self.breakpoint_at(pc) {
+                stop = Some(format!("breakpoint {}, {}", n, self.symbols.location(pc)));
+                break;
+            }
+            if self.computer.cycles - start >= RUN_LIMIT {
+                stop = Some(format!("stopped after {} cycles", RUN_LIMIT));
+                break;
+            }
+        }
+        self.report(stop)
+    }
+
+    // One instruction, and the watchpoint it triggers, if any.
+    fn step_watched(&mut self) -> Option<String> {
+        if self.watchpoints.is_empty() {
+            self.trace.record(&mut self.computer);
+            return None;
+        }
+        let before: Vec<u16> = self.watchpoints.iter().map(|(_, w)| self.computer.ram[w.address as usize]).collect();
+        self.trace.record(&mut self.computer);
+        for ((n, w), b) in self.watchpoints.iter().zip(before) {
+            let after = self.computer.ram[w.address as usize];
+            if w.triggered(b, after) {
+                return Some(format!("watchpoint {}, {}: {} -> {}", n, self.describe(w), b as i16, after as i16));
+            }
+        }
+        None
+    }
+
+    fn reverse_step(&mut self, args: &[&str]) -> Result<String, String> {
+        let n = match args.get(0) {
+            Some(n) => n.parse::<u64>().map_err(|_| format!("not a number: {}", n))?,
+            None => 1,
+        };
+        for _ in 0..n {
+            if self.trace.step_back(&mut self.computer).is_none() {
+                return Ok(self.report(Some("at the start of the trace".to_string())));
+            }
+        }
+        Ok(self.report(None))
+    }
+
+    // Undoes instructions until PC is at a breakpoint or an undone write
+    // triggers a watchpoint, backward. The instruction before PC is undone
+    // first, so that reverse-continue leaves a breakpoint.
+    fn reverse_continue(&mut self) -> String {
+        let stop = loop {
+            let entry = match self.trace.step_back(&mut self.computer) {
+                Some(e) => e,
+                None => break "at the start of the trace".to_string(),
+            };
+            if let Some(w) = entry.write {
+                if let Some((n, watch)) = self.watchpoints.iter().find(|(_, x)| x.address == w.address && x.triggered(w.old, w.new)) {
+                    break format!("watchpoint {}, {}: {} -> {}", n, self.describe(watch), w.old as i16, w.new as i16);
+                }
+            }
+            if let Some(n) = self.breakpoint_at(self.computer.pc) {
+                break format!("breakpoint {}, {}", n, self.symbols.location(self.computer.pc));
+            }
+        };
+        self.report(Some(stop))
+    }
+
+    // The last write of a RAM word in the trace, and with back, a reverse
+    // run to just before it.
+    fn last_write(&mut self, args: &[&str], back: bool) -> Result<String, String> {
+        let address = self.ram_location(args.get(0).ok_or("last-write takes an address")?)?;
+        let found = if back { self.trace.back_to_write(&mut self.computer, address) } else { self.trace.last_write(address) };
+        let (cycle, entry) = found.ok_or_else(|| format!("{} was not written in the last {} cycles",
+            ram_location(&self.symbols, address), self.trace.len()))?;
+        let line = view_entry(self.computer.rom(), &self.symbols, cycle, &entry);
+        Ok(if back { format!("{}\n{}", line.trim_start(), self.status()) } else { line.trim_start().to_string() })
+    }
+
+    fn show_trace(&self, args: &[&str]) -> Result<String, String> {
+        let n = match args.get(0) {
+            Some(n) => n.parse::<u64>().map_err(|_| format!("not a number: {}", n))?,
+            None => 10,
+        };
+        Ok(self.trace.view(self.computer.rom(), &self.symbols, self.computer.cycles.saturating_sub(n)).trim_end().to_string())
+    }
+
+    fn report(&self, stop: Option<String>) -> String {
+        match stop {
+            Some(s) => format!("{}\n{}", s, self.status()),
+            None => self.status(),
+        }
+    }
+
+    // PC=145 (ball.new) A=3 D=0 SP=261 cycles=1200
+    // 145: @3      // THIS
+    // stack: 7 0 -1
+    pub fn status(&self) -> String {
+        let c = &self.computer;
+        let label = match self.symbols.enclosing_label(c.pc) {
+            Some(_) => format!(" ({})", self.symbols.location(c.pc)),
+            None => String::new(),
+        };
+        format!("PC={}{} A={} D={} SP={} cycles={}\n{}\n{}",
+            c.pc, label, c.a, c.d as i16, c.ram[SP], c.cycles,
+            self.instruction(c.pc as usize), self.stack_line(5))
+    }
+
+    fn instruction(&self, address: usize) -> String {
+        let address = address & (ROM_SIZE - 1);
+        format!("{}: {}", address, Disassembler::disassemble_at(self.computer.rom(), address, &self.symbols))
+    }
+
+    // The top of the VM stack, the most recent push first.
+    fn stack_line(&self, n: usize) -> String {
+        let sp = self.computer.ram[SP];
+        let mut values = vec![];
+        let mut a = sp;
+        while a > STACK_BASE && a as usize <= RAM_SIZE && values.len() < n {
+            a -= 1;
+            values.push((self.computer.ram[a as usize] as i16).to_string());
+        }
+        if sp < STACK_BASE || sp as usize > RAM_SIZE {
+            return format!("stack: SP={} is outside the stack", sp);
+        }
+        if values.is_empty() {
+            return "stack: empty".to_string();
+        }
+        if (a as usize) > STACK_BASE as usize {
+            values.push("...".to_string());
+        }
+        format!("stack: {}", values.join(" "))
+    }
+
+    fn stack(&self, args: &[&str]) -> Result<String, String> {
+        let n = match args.get(0) {
+            Some(n) => n.parse::<usize>().map_err(|_| format!("not a number: {}", n))?,
+            None => 10,
+        };
+        Ok(self.stack_line(n))
+    }
+
+    fn save(&self, args: &[&str]) -> Result<String, String> {
+        let path = args.get(0).ok_or("save takes a file name")?;
+        std::fs::write(path, Snapshot::take(&self.computer).to_bytes()).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(format!("saved at cycle {}", self.computer.cycles))
+    }
+
+    fn restore(&mut self, args: &[&str]) -> Result<String, String> {
+        let path = args.get(0).ok_or("restore takes a file name")?;
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Snapshot::from_bytes(&data).and_then(|s| s.restore(&mut self.computer)).map_err(|e| format!("{}: {}", path, e))?;
+        self.trace.clear();
+        Ok(self.status())
+    }
+
+    fn examine(&self, args: &[&str]) -> Result<String, String> {
+        let address = self.ram_location(args.get(0).ok_or("x takes an address")?)? as usize;
+        let n = match args.get(1) {
+            Some(n) => n.parse::<usize>().map_err(|_| format!("not a number: {}", n))?,
+            None => 1,
+        };
+        let lines: Vec<String> = (address..(address + n).min(RAM_SIZE))
+            .map(|a| {
+                let v = self.computer.ram[a];
+                match self.symbols.variables_at(a as u16).first() {
+                    Some(name) => format!("{}: {} ({})", a, v as i16, name),
+                    None => format!("{}: {}", a, v as i16),
+                }
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
+    fn set(&mut self, args: &[&str]) -> Result<String, String> {
+        if args.len() != 2 {
+            return Err("set takes ADDRESS VALUE".to_string());
+        }
+        let value = parse_number(args[1]).ok_or_else(|| format!("not a number: {}", args[1]))? as u16;
+        match args[0] {
+            "A" => self.computer.a = value,
+            "D" => self.computer.d = value,
+            "PC" => self.computer.pc = value,
+            a => {
+                let address = self.ram_location(a)?;
+                self.computer.ram[address as usize] = value;
+            },
+        }
+        // which can't be undone
+        self.trace.clear();
+        Ok(self.status())
+    }
+
+    // 5 instructions either side, the current one marked with >.
+    fn list(&self, args: &[&str]) -> Result<String, String> {
+        let center = match args.get(0) {
+            Some(l) => self.rom_location(l)?,
+            None => self.computer.pc,
+        } as usize;
+        let mut lines = vec![];
+        for a in center.saturating_sub(5)..(center + 6).min(ROM_SIZE) {
+            for label in self.symbols.labels_at(a as u16) {
+                lines.push(format!("      ({})", label));
+            }
+            let mark = if a == self.computer.pc as usize { ">" } else { " " };
+            lines.push(format!("{} {}", mark, self.instruction(a)));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    // A number or a label, with or without parentheses.
+    fn rom_location(&self, s: &str) -> Result<u16, String> {
+        if let Some(n) = parse_number(s) {
+            if n >= 0 && (n as usize) < ROM_SIZE {
+                return Ok(n as u16);
+            }
+        }
+        self.symbols.rom_address(s).ok_or_else(|| format!("no label {}", s))
+    }
+
+    // A number, a variable or a predefined symbol such as SP.
+    fn ram_location(&self, s: &str) -> Result<u16, String> {
+        if let Some(n) = parse_number(s) {
+            if n >= 0 && (n as usize) < RAM_SIZE {
+                return Ok(n as u16);
+            }
+        }
+        self.symbols.ram_address(s).ok_or_else(|| format!("no variable {}", s))
+    }
+}
+
+// 17, -1 or 0x4000
+fn parse_number(s: &str) -> Option<i64> {
+    let n = match s.strip_prefix("0x") {
+        Some(h) => i64::from_str_radix(h, 16).ok()?,
+        None => s.parse::<i64>().ok()?,
+    };
+    if !(-32768..=65535).contains(&n) {
+        return None;
+    }
+    Some(n)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use super::super::Computer::parse_hack;
+
+    // Max.asm with its labels
+    fn max() -> Debugger {
+        let rom = parse_hack(&std::fs::read_to_string("../Max.hack").unwrap()).unwrap();
+        let symbols = SymbolMap::parse("rom 10 OUTPUT_FIRST\nrom 12 OUTPUT_D\nrom 14 INFINITE_LOOP\n").unwrap();
+        let mut d = Debugger::new(Computer::new(&rom), symbols);
+        d.computer.ram[0] = 3;
+        d.computer.ram[1] = 8;
+        d
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut d = max();
+        assert_eq!(d.execute("break (OUTPUT_D)"), "breakpoint 1 at 12 (OUTPUT_D)");
+        let s = d.execute("continue");
+        assert!(s.starts_with("breakpoint 1, OUTPUT_D\nPC=12 (OUTPUT_D) A=12 D=8"), "{}", s);
+        assert!(d.execute("step 2").starts_with("PC=14 (INFINITE_LOOP)"));
+        assert_eq!(d.computer.ram[2], 8);
+        assert!(d.execute("continue").starts_with("halted"));
+        assert_eq!(d.execute("info"), "1: break 12 (OUTPUT_D)");
+        assert!(d.execute("break NOWHERE").starts_with("no label"));
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut d = max();
+        d.execute("watch R2");
+        let s = d.execute("c");
+        assert!(s.starts_with("watchpoint 1, 2: 0 -> 8"), "{}", s);
+
+        // a condition breaks when it becomes true, not while it holds
+        let mut d = max();
+        assert_eq!(d.execute("watch R2 > 5"), "watchpoint 1 on 2 > 5");
+        assert!(d.execute("c").starts_with("watchpoint 1, 2 > 5: 0 -> 8\nPC=14"));
+        let mut d = max();
+        d.execute("set R2 7");
+        d.execute("watch 2 > 5");
+        assert!(d.execute("c").starts_with("halted"));
+        assert_eq!(d.execute("x R0 3"), "0: 3\n1: 8\n2: 8");
+        assert_eq!(d.execute("delete 1"), "deleted 1");
+        assert_eq!(d.execute("info"), "no breakpoints or watchpoints");
+    }
+
+    #[test]
+    fn numbers() {
+        // one numbering for both, kept when others come and go
+        let mut d = max();
+        assert_eq!(d.execute("break OUTPUT_FIRST"), "breakpoint 1 at 10 (OUTPUT_FIRST)");
+        assert_eq!(d.execute("watch SP < 256"), "watchpoint 2 on 0 < 256");
+        assert_eq!(d.execute("break OUTPUT_D"), "breakpoint 3 at 12 (OUTPUT_D)");
+        assert_eq!(d.execute("break OUTPUT_FIRST"), "breakpoint 1 at 10 (OUTPUT_FIRST)");
+        assert_eq!(d.execute("info"), "1: break 10 (OUTPUT_FIRST)\n2: watch 0 < 256\n3: break 12 (OUTPUT_D)");
+        assert_eq!(d.execute("delete 1"), "deleted 1");
+        assert_eq!(d.execute("delete 1"), "no breakpoint or watchpoint 1");
+        assert_eq!(d.execute("info"), "2: watch 0 < 256\n3: break 12 (OUTPUT_D)");
+        assert!(d.execute("c").starts_with("breakpoint 3, OUTPUT_D"));
+        d.execute("delete");
+        assert!(d.execute("watch R2").starts_with("watchpoint 4 on "));
+    }
+
+    #[test]
+    fn status_and_list() {
+        let mut d = max();
+        d.execute("set SP 258");
+        d.execute("set 256 7");
+        d.execute("set 257 -1");
+        assert_eq!(d.execute("regs"), "PC=0 A=0 D=0 SP=258 cycles=0\n0: @0\nstack: -1 7");
+        let l = d.execute("list OUTPUT_D");
+        assert!(l.contains("      (OUTPUT_D)\n  12: @2\n  13: M=D\n      (INFINITE_LOOP)\n  14: @14"), "{}", l);
+    }
+
+    #[test]
+    fn reverse() {
+        let mut d = max();
+        assert!(d.execute("continue").starts_with("halted\nPC=14"));
+        assert_eq!(d.execute("trace 3").lines().count(), 3);
+        let s = d.execute("last-write R2");
+        assert!(s.starts_with("11  OUTPUT_D+1") && s.ends_with("RAM[2] (ARG) 0 -> 8"), "{}", s);
+        assert!(d.execute("reverse-write R2").contains("\nPC=13 (OUTPUT_D+1) A=2 D=8 SP=3 cycles=11"));
+        assert_eq!(d.computer.ram[2], 0);
+        assert!(d.execute("last-write 100").ends_with("was not written in the last 11 cycles"));
+
+        d.execute("break OUTPUT_D");
+        assert!(d.execute("reverse-continue").starts_with("breakpoint 1, OUTPUT_D\nPC=12"));
+        assert!(d.execute("rs 3").starts_with("PC=7 "));
+        assert!(d.execute("rc").starts_with("at the start of the trace\nPC=0 A=0 D=0 SP=3 cycles=0"));
+        // and forward again the same way
+        assert!(d.execute("c").starts_with("breakpoint 1, OUTPUT_D\nPC=12 (OUTPUT_D) A=12 D=8"));
+        d.execute("set R2 1");
+        assert!(d.execute("rs").starts_with("at the start of the trace"));
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let path = std::env::temp_dir().join(format!("hackdbg-{}.snap", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut d = max();
+        d.execute("step 3");
+        assert_eq!(d.execute(&format!("save {}", path)), "saved at cycle 3");
+        d.execute("continue");
+        assert_eq!(d.computer.ram[2], 8);
+        assert!(d.execute(&format!("restore {}", path)).starts_with("PC=3 A=1 D=3"));
+        assert_eq!((d.computer.ram[2], d.computer.cycles), (0, 3));
+        std::fs::remove_file(path).unwrap();
+    }
+}
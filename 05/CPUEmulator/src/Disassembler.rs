@@ -0,0 +1,67 @@
+use assembler::code;
+use super::SymbolMap::SymbolMap;
+
+// The assembly of one instruction, e.g. @17 or AM=M-1 or 0;JMP.
+pub fn disassemble(i: u16) -> String {
+    if i & 0x8000 == 0 {
+        return format!("@{}", i);
+    }
+    let bits = |value: u16, count: usize| -> Vec<char> {
+        (0..count).rev().map(|b| if value & (1 << b) != 0 { '1' } else { '0' }).collect()
+    };
+    // the tables of the assembler, searched backwards
+    let comp = code::COMPS.iter().find(|m| code::comp(m).map(|c| c.to_vec()) == Some(bits(i >> 6, 7)));
+    let dest = code::DESTS.iter().find(|m| code::dest(m).map(|c| c.to_vec()) == Some(bits(i >> 3, 3))).unwrap();
+    let jump = code::JUMPS.iter().find(|m| code::jump(m).map(|c| c.to_vec()) == Some(bits(i, 3))).unwrap();
+    let mut s = String::new();
+    if !dest.is_empty() {
+        s.push_str(dest);
+        s.push('=');
+    }
+    match comp {
+        Some(c) => s.push_str(c),
+        None => s.push_str(&format!("comp?{:07b}", (i >> 6) & 0x7f)),
+    }
+    if !jump.is_empty() {
+        s.push(';');
+        s.push_str(jump);
+    }
+    s
+}
+
+// The instruction at address with the symbols it refers to: an A
+// instruction followed by a jump names a label, otherwise a variable.
+pub fn disassemble_at(rom: &[u16], address: usize, symbols: &SymbolMap) -> String {
+    let i = rom[address];
+    let s = disassemble(i);
+    if i & 0x8000 != 0 {
+        return s;
+    }
+    let jumps = rom.get(address + 1).map_or(false, |n| n & 0x8000 != 0 && n & 0x07 != 0);
+    let names = if jumps { symbols.labels_at(i) } else { symbols.variables_at(i) };
+    if names.is_empty() {
+        s
+    }
+    else {
+        format!("{:<8}// {}", s, names.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn instructions() {
+        assert_eq!(disassemble(0b0000_0000_0001_0001), "@17");
+        assert_eq!(disassemble(0b1111_1100_1010_1000), "AM=M-1");
+        assert_eq!(disassemble(0b1110_1010_1000_0111), "0;JMP");
+        assert_eq!(disassemble(0b1110_0011_0000_1000), "M=D");
+        assert_eq!(disassemble(0b1110_0000_1001_0000), "D=D+A");
+
+        let map = SymbolMap::parse("rom 2 LOOP\nram 2 x\n").unwrap();
+        let rom = [2, 0b1110_1010_1000_0111, 2, 0b1110_0011_0000_1000];
+        assert_eq!(disassemble_at(&rom, 0, &map), "@2      // LOOP");
+        assert_eq!(disassemble_at(&rom, 2, &map), "@2      // x");
+    }
+}
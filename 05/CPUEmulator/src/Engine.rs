@@ -0,0 +1,248 @@
+use super::Computer::{self, Computer as Hack, RAM_SIZE, ROM_SIZE};
+
+// One instruction, or an A instruction and the C instruction after it.
+type Op = Box<dyn Fn(&mut Hack)>;
+// The last instruction of a block, which returns the next PC.
+type Exit = Box<dyn Fn(&mut Hack) -> u16>;
+
+const MASK: usize = RAM_SIZE - 1;
+// so that long runs of straight code, e.g. unused ROM, make several blocks
+const MAX_BLOCK: usize = 256;
+
+// Straight-line code from one address up to and including a jump.
+struct Block {
+    ops: Vec<Op>,
+    exit: Exit,
+    // instructions
+    len: u64,
+}
+
+// Runs a Computer a block at a time. Each block is translated into
+// closures the first time it is reached, and kept: ROM never changes. The
+// result is the same as Computer::run, cycle for cycle.
+pub struct Engine {
+    blocks: Vec<Option<Block>>,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine { blocks: (0..ROM_SIZE).map(|_| None).collect() }
+    }
+
+    // Runs until the program halts or the cycle count reaches limit.
+    pub fn run(&mut self, computer: &mut Hack, limit: u64) {
+        while computer.cycles < limit && !computer.is_halted() {
+            let pc = computer.pc as usize;
+            if pc >= ROM_SIZE {
+                computer.step();
+                continue;
+            }
+            if self.blocks[pc].is_none() {
+                self.blocks[pc] = Some(compile(computer.rom(), pc));
+            }
+            let block = self.blocks[pc].as_ref().unwrap();
+            // the last few cycles one at a time
+            if computer.cycles + block.len > limit {
+                computer.step();
+                continue;
+            }
+            for op in &block.ops {
+                op(computer);
+            }
+            computer.pc = (block.exit)(computer);
+            computer.cycles += block.len;
+        }
+    }
+
+    // The number of blocks translated so far.
+    pub fn blocks(&self) -> usize {
+        self.blocks.iter().filter(|b| b.is_some()).count()
+    }
+}
+
+// The block at start. It ends at a jump, at the end of ROM, after
+// MAX_BLOCK instructions, or before a halt loop, where Computer::run would
+// stop.
+fn compile(rom: &[u16], start: usize) -> Block {
+    let mut ops = vec![];
+    let mut pc = start;
+    let exit = loop {
+        if pc >= ROM_SIZE || pc - start >= MAX_BLOCK || (pc != start && Computer::is_halt_loop(rom, pc)) {
+            let next = pc as u16;
+            break Box::new(move |_: &mut Hack| next) as Exit;
+        }
+        let i = rom[pc];
+        // @value with the C instruction after it, which then knows A
+        let (constant, i, width) = if i & 0x8000 == 0 && pc + 1 < ROM_SIZE && rom[pc + 1] & 0x8000 != 0 {
+            (Some(i), rom[pc + 1], 2)
+        }
+        else {
+            (None, i, 1)
+        };
+        pc += width;
+        if i & 0x8000 == 0 {
+            ops.push(Box::new(move |c: &mut Hack| c.a = i) as Op);
+        }
+        else if i & 0x07 != 0 {
+            break jump(i, constant, pc as u16);
+        }
+        else {
+            ops.push(instruction(i, constant));
+        }
+    };
+    Block { ops: ops, exit: exit, len: (pc - start) as u64 }
+}
+
+// A jump, the way Computer::step runs it.
+fn jump(i: u16, constant: Option<u16>, next: u16) -> Exit {
+    Box::new(move |c: &mut Hack| {
+        if let Some(v) = constant {
+            c.a = v;
+        }
+        let address = c.a as usize & MASK;
+        let y = if i & 0x1000 != 0 { c.ram[address] } else { c.a };
+        let out = Computer::alu(c.d, y, (i >> 6) & 0x3f);
+        if i & 0x08 != 0 {
+            c.ram[address] = out;
+        }
+        let target = c.a;
+        if i & 0x20 != 0 {
+            c.a = out;
+        }
+        if i & 0x10 != 0 {
+            c.d = out;
+        }
+        let negative = out & 0x8000 != 0;
+        let zero = out == 0;
+        let taken = (i & 4 != 0 && negative) || (i & 2 != 0 && zero) || (i & 1 != 0 && !negative && !zero);
+        if taken { target } else { next }
+    })
+}
+
+// A C instruction without a jump, specialized for its computation.
+fn instruction(i: u16, constant: Option<u16>) -> Op {
+    let control = (i >> 6) & 0x3f;
+    match control {
+        0b101010 => select(|_, _| 0, i, constant),
+        0b111111 => select(|_, _| 1, i, constant),
+        0b111010 => select(|_, _| 0xffff, i, constant),
+        0b001100 => select(|d, _| d, i, constant),
+        0b110000 => select(|_, y| y, i, constant),
+        0b001101 => select(|d, _| !d, i, constant),
+        0b110001 => select(|_, y| !y, i, constant),
+        0b001111 => select(|d, _| d.wrapping_neg(), i, constant),
+        0b110011 => select(|_, y| y.wrapping_neg(), i, constant),
+        0b011111 => select(|d, _| d.wrapping_add(1), i, constant),
+        0b110111 => select(|_, y| y.wrapping_add(1), i, constant),
+        0b001110 => select(|d, _| d.wrapping_sub(1), i, constant),
+        0b110010 => select(|_, y| y.wrapping_sub(1), i, constant),
+        0b000010 => select(|d, y| d.wrapping_add(y), i, constant),
+        0b010011 => select(|d, y| d.wrapping_sub(y), i, constant),
+        0b000111 => select(|d, y| y.wrapping_sub(d), i, constant),
+        0b000000 => select(|d, y| d & y, i, constant),
+        0b010101 => select(|d, y| d | y, i, constant),
+        _ => select(move |d, y| Computer::alu(d, y, control), i, constant),
+    }
+}
+
+// One closure per computation, operand and destination, so that each
+// compiles to straight code.
+fn select<F: Fn(u16, u16) -> u16 + 'static>(f: F, i: u16, constant: Option<u16>) -> Op {
+    match (i & 0x1000 != 0, (i >> 3) & 0x07) {
+        (false, 0) => op::<F, false, 0>(f, constant),
+        (false, 1) => op::<F, false, 1>(f, constant),
+        (false, 2) => op::<F, false, 2>(f, constant),
+        (false, 3) => op::<F, false, 3>(f, constant),
+        (false, 4) => op::<F, false, 4>(f, constant),
+        (false, 5) => op::<F, false, 5>(f, constant),
+        (false, 6) => op::<F, false, 6>(f, constant),
+        (false, 7) => op::<F, false, 7>(f, constant),
+        (true, 0) => op::<F, true, 0>(f, constant),
+        (true, 1) => op::<F, true, 1>(f, constant),
+        (true, 2) => op::<F, true, 2>(f, constant),
+        (true, 3) => op::<F, true, 3>(f, constant),
+        (true, 4) => op::<F, true, 4>(f, constant),
+        (true, 5) => op::<F, true, 5>(f, constant),
+        (true, 6) => op::<F, true, 6>(f, constant),
+        _ => op::<F, true, 7>(f, constant),
+    }
+}
+
+// M reads RAM[A] rather than A; DEST is the d1 d2 d3 bits, A D M.
+fn op<F: Fn(u16, u16) -> u16 + 'static, const M: bool, const DEST: u16>(f: F, constant: Option<u16>) -> Op {
+    match constant {
+        Some(v) => {
+            let address = v as usize & MASK;
+            Box::new(move |c: &mut Hack| {
+                let out = f(c.d, if M { c.ram[address] } else { v });
+                c.a = v;
+                write::<DEST>(c, address, out);
+            })
+        },
+        None => Box::new(move |c: &mut Hack| {
+            let address = c.a as usize & MASK;
+            let out = f(c.d, if M { c.ram[address] } else { c.a });
+            write::<DEST>(c, address, out);
+        }),
+    }
+}
+
+#[inline(always)]
+fn write<const DEST: u16>(c: &mut Hack, address: usize, out: u16) {
+    if DEST & 1 != 0 {
+        c.ram[address] = out;
+    }
+    if DEST & 4 != 0 {
+        c.a = out;
+    }
+    if DEST & 2 != 0 {
+        c.d = out;
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    // Runs a copy with each engine and compares every register and word.
+    fn compare(c: &Hack, limit: u64) {
+        let mut reference = c.clone();
+        reference.run(limit);
+        let mut fast = c.clone();
+        Engine::new().run(&mut fast, limit);
+        assert_eq!((fast.pc, fast.a, fast.d, fast.cycles), (reference.pc, reference.a, reference.d, reference.cycles));
+        assert!(fast.ram == reference.ram);
+    }
+
+    #[test]
+    fn programs() {
+        for (path, r0, r1) in [("../Max.hack", 3, 8), ("../Max.hack", 9, -2), ("../Rect.hack", 5, 0), ("../Add.hack", 0, 0)].iter() {
+            let rom = Computer::parse_hack(&std::fs::read_to_string(path).unwrap()).unwrap();
+            let mut c = Hack::new(&rom);
+            c.ram[0] = *r0 as u16;
+            c.ram[1] = *r1 as u16;
+            for limit in [1, 7, 50, 100_000].iter() {
+                compare(&c, *limit);
+            }
+        }
+    }
+
+    #[test]
+    fn random_programs() {
+        // any word is an instruction, so random ROM and RAM exercise every
+        // computation, destination and jump, and jumps to anywhere
+        let mut seed: u32 = 12345;
+        let mut next = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) as u16
+        };
+        for _ in 0..20 {
+            let rom: Vec<u16> = (0..4096).map(|_| next()).collect();
+            let mut c = Hack::new(&rom);
+            for w in c.ram.iter_mut() {
+                *w = next();
+            }
+            compare(&c, 20_000);
+        }
+    }
+}
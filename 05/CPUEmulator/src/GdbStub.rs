@@ -0,0 +1,566 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use super::Computer::{Computer, RAM_SIZE, ROM_SIZE};
+
+// Memory as gdb sees it, in bytes: RAM word N at 2N and ROM word N at
+// ROM_BASE + 2N, both little-endian. PC and breakpoints are ROM word
+// addresses.
+pub const ROM_BASE: usize = 0x10000;
+// continue looks for an interrupt from gdb this often
+const POLL_CYCLES: u64 = 100_000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nand2tetris.hack.cpu">
+    <reg name="a" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="d" bitsize="16" type="int16" regnum="1"/>
+    <reg name="pc" bitsize="16" type="code_ptr" regnum="2"/>
+  </feature>
+</target>
+"#;
+
+const MEMORY_MAP: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE memory-map PUBLIC "+//IDN gnu.org//DTD GDB Memory Map V1.0//EN" "http://sourceware.org/gdb/gdb-memory-map.dtd">
+<memory-map>
+  <memory type="ram" start="0x0" length="0x10000"/>
+  <memory type="rom" start="0x10000" length="0x10000"/>
+</memory-map>
+"#;
+
+// What the stub does after a packet.
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Action {
+    REPLY(String),
+    // run, one instruction or to a breakpoint, then reply with a stop
+    STEP,
+    CONTINUE,
+    // reply, if anything, then end the session
+    CLOSE(Option<String>),
+}
+
+// The target side of the GDB remote serial protocol for a Computer: the
+// registers A, D and PC, RAM and ROM, single-step, continue and software
+// breakpoints.
+pub struct GdbStub {
+    pub computer: Computer,
+    breakpoints: Vec<u16>,
+}
+
+impl GdbStub {
+    pub fn new(computer: Computer) -> Self {
+        GdbStub { computer: computer, breakpoints: vec![] }
+    }
+
+    // The action for the data of one packet, without $ and checksum.
+    pub fn handle(&mut self, packet: &str) -> Action {
+        let reply = |s: &str| Action::REPLY(s.to_string());
+        // a command is one ASCII letter, anything else is not understood
+        let command = packet.get(..1).unwrap_or("");
+        let args = &packet[command.len()..];
+        match command {
+            "?" => reply("S05"),
+            "g" => reply(&self.registers()),
+            "G" => Action::REPLY(self.set_registers(args).map_or_else(error, |_| "OK".to_string())),
+            "p" => Action::REPLY(self.register(args).unwrap_or_else(error)),
+            "P" => Action::REPLY(self.set_register(args).map_or_else(error, |_| "OK".to_string())),
+            "m" => Action::REPLY(self.read_memory(args).unwrap_or_else(error)),
+            "M" => Action::REPLY(self.write_memory(args).map_or_else(error, |_| "OK".to_string())),
+            "s" if args.is_empty() => Action::STEP,
+            "c" if args.is_empty() => Action::CONTINUE,
+            "Z" | "z" => Action::REPLY(self.breakpoint(command == "Z", args).unwrap_or_else(error)),
+            "H" => reply("OK"),
+            "T" => reply("OK"),
+            "k" => Action::CLOSE(None),
+            "D" => Action::CLOSE(Some("OK".to_string())),
+            "q" | "Q" | "v" => self.query(packet),
+            _ => reply(""),
+        }
+    }
+
+    fn query(&self, packet: &str) -> Action {
+        let reply = |s: &str| Action::REPLY(s.to_string());
+        if packet.starts_with("qSupported") {
+            return reply("PacketSize=4000;qXfer:features:read+;qXfer:memory-map:read+;QStartNoAckMode+");
+        }
+        if let Some(rest) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return Action::REPLY(transfer(TARGET_XML, rest).unwrap_or_else(error));
+        }
+        if let Some(rest) = packet.strip_prefix("qXfer:memory-map:read::") {
+            return Action::REPLY(transfer(MEMORY_MAP, rest).unwrap_or_else(error));
+        }
+        match packet {
+            "QStartNoAckMode" => reply("OK"),
+            "qAttached" => reply("1"),
+            "qC" => reply("QC1"),
+            "qfThreadInfo" => reply("m1"),
+            "qsThreadInfo" => reply("l"),
+            "vCont?" => reply("vCont;c;s"),
+            "vCont;s" | "vCont;s:1" => Action::STEP,
+            "vCont;c" | "vCont;c:1" => Action::CONTINUE,
+            _ => reply(""),
+        }
+    }
+
+    // a, d and pc, 4 hex digits each
+    fn registers(&self) -> String {
+        [self.computer.a, self.computer.d, self.computer.pc].iter().map(|r| hex_word(*r)).collect()
+    }
+
+    fn set_registers(&mut self, args: &str) -> Result<(), u8> {
+        let bytes = from_hex(args)?;
+        if bytes.len() != 6 {
+            return Err(1);
+        }
+        self.computer.a = word(&bytes[0..2]);
+        self.computer.d = word(&bytes[2..4]);
+        self.computer.pc = word(&bytes[4..6]);
+        Ok(())
+    }
+
+    fn register(&self, args: &str) -> Result<String, u8> {
+        match usize::from_str_radix(args, 16) {
+            Ok(0) => Ok(hex_word(self.computer.a)),
+            Ok(1) => Ok(hex_word(self.computer.d)),
+            Ok(2) => Ok(hex_word(self.computer.pc)),
+            _ => Err(1),
+        }
+    }
+
+    fn set_register(&mut self, args: &str) -> Result<(), u8> {
+        let mut parts = args.splitn(2, '=');
+        let n = usize::from_str_radix(parts.next().unwrap(), 16).map_err(|_| 1)?;
+        let bytes = from_hex(parts.next().ok_or(1)?)?;
+        if bytes.len() != 2 {
+            return Err(1);
+        }
+        let value = word(&bytes);
+        match n {
+            0 => self.computer.a = value,
+            1 => self.computer.d = value,
+            2 => self.computer.pc = value,
+            _ => return Err(1),
+        }
+        Ok(())
+    }
+
+    fn read_memory(&self, args: &str) -> Result<String, u8> {
+        let (address, length) = address_and_length(args)?;
+        let mut s = String::new();
+        let end = address.checked_add(length).ok_or(14)?;
+        for b in address..end {
+            s.push_str(&format!("{:02x}", self.memory_byte(b).ok_or(14)?));
+        }
+        Ok(s)
+    }
+
+    // Only RAM can be written.
+    fn write_memory(&mut self, args: &str) -> Result<(), u8> {
+        let mut parts = args.splitn(2, ':');
+        let (address, length) = address_and_length(parts.next().unwrap())?;
+        let bytes = from_hex(parts.next().ok_or(1)?)?;
+        let end = address.checked_add(length).ok_or(14)?;
+        if bytes.len() != length || end > RAM_SIZE * 2 {
+            return Err(14);
+        }
+        for (i, b) in bytes.iter().enumerate() {
+            let a = address + i;
+            let w = &mut self.computer.ram[a / 2];
+            *w = if a % 2 == 0 { (*w & 0xff00) | *b as u16 } else { (*w & 0x00ff) | (*b as u16) << 8 };
+        }
+        Ok(())
+    }
+
+    fn memory_byte(&self, address: usize) -> Option<u8> {
+        let w = if address < RAM_SIZE * 2 {
+            self.computer.ram[address / 2]
+        }
+        else if (ROM_BASE..ROM_BASE + ROM_SIZE * 2).contains(&address) {
+            self.computer.rom()[(address - ROM_BASE) / 2]
+        }
+        else {
+            return None;
+        };
+        Some(if address % 2 == 0 { w as u8 } else { (w >> 8) as u8 })
+    }
+
+    // Z0,ADDR,KIND at a ROM word address; the other kinds are unsupported.
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Result<String, u8> {
+        let fields: Vec<&str> = args.split(',').collect();
+        if fields[0] != "0" {
+            return Ok(String::new());
+        }
+        let address = fields.get(1).and_then(|a| u16::from_str_radix(a, 16).ok()).ok_or(1)?;
+        if address as usize >= ROM_SIZE {
+            return Err(14);
+        }
+        if insert && !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+        if !insert {
+            self.breakpoints.retain(|b| *b != address);
+        }
+        Ok("OK".to_string())
+    }
+
+    // Runs one instruction, or until a breakpoint, the program halts or
+    // interrupted, which is asked every POLL_CYCLES, says so. The reply is
+    // a stop packet: SIGTRAP, or SIGINT when interrupted.
+    pub fn resume<F: FnMut() -> bool>(&mut self, step: bool, mut interrupted: F) -> String {
+        if step {
+            self.computer.step();
+            return "S05".to_string();
+        }
+        let mut next_poll = self.computer.cycles + POLL_CYCLES;
+        loop {
+            // the instruction at a breakpoint runs when continuing from it
+            self.computer.step();
+            if self.breakpoints.contains(&self.computer.pc) || self.computer.is_halted() {
+                return "S05".to_string();
+            }
+            if self.computer.cycles >= next_poll {
+                if interrupted() {
+                    return "S02".to_string();
+                }
+                next_poll = self.computer.cycles + POLL_CYCLES;
+            }
+        }
+    }
+
+    // Serves one connection until gdb detaches, kills or disconnects.
+    pub fn serve(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut reader = PacketReader { stream: stream.try_clone()?, ack: true };
+        loop {
+            let packet = match reader.next_packet()? {
+                Some(Packet::DATA(p)) => p,
+                // an interrupt while stopped
+                Some(Packet::INTERRUPT) => {
+                    send(&mut stream, "S02")?;
+                    continue;
+                },
+                None => return Ok(()),
+            };
+            let action = self.handle(&packet);
+            let reply = match action {
+                Action::REPLY(r) => r,
+                Action::STEP => self.resume(true, || false),
+                Action::CONTINUE => {
+                    let poll = &mut reader;
+                    self.resume(false, || poll.interrupted())
+                },
+                Action::CLOSE(r) => {
+                    if let Some(r) = r {
+                        send(&mut stream, &r)?;
+                    }
+                    return Ok(());
+                },
+            };
+            send(&mut stream, &reply)?;
+            if packet == "QStartNoAckMode" {
+                reader.ack = false;
+            }
+        }
+    }
+}
+
+// Listens on the loopback interface, port 0 for any free one, and serves
+// the first connection. announce gets the port before it waits.
+pub fn listen<F: FnOnce(u16)>(stub: &mut GdbStub, port: u16, announce: F) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    announce(listener.local_addr()?.port());
+    let (stream, _) = listener.accept()?;
+    stub.serve(stream)
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+enum Packet {
+    DATA(String),
+    INTERRUPT,
+}
+
+struct PacketReader {
+    stream: TcpStream,
+    // acknowledge packets, until QStartNoAckMode
+    ack: bool,
+}
+
+impl PacketReader {
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        let mut b = [0u8];
+        match self.stream.read(&mut b)? {
+            0 => Ok(None),
+            _ => Ok(Some(b[0])),
+        }
+    }
+
+    // The next packet, with its escapes undone, or None at the end.
+    fn next_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            match self.byte()? {
+                None => return Ok(None),
+                Some(0x03) => return Ok(Some(Packet::INTERRUPT)),
+                Some(b'$') => {},
+                // acks and anything else between packets
+                Some(_) => continue,
+            }
+            let mut data = vec![];
+            let mut sum: u8 = 0;
+            loop {
+                let b = match self.byte()? {
+                    Some(b) => b,
+                    None => return Ok(None),
+                };
+                if b == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(b);
+                data.push(b);
+            }
+            let mut checksum = [0u8; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let ok = std::str::from_utf8(&checksum).ok().and_then(|c| u8::from_str_radix(c, 16).ok()) == Some(sum);
+            if self.ack {
+                self.stream.write_all(if ok { b"+" } else { b"-" })?;
+            }
+            if !ok {
+                continue;
+            }
+            let mut unescaped = vec![];
+            let mut i = 0;
+            while i < data.len() {
+                if data[i] == b'}' && i + 1 < data.len() {
+                    unescaped.push(data[i + 1] ^ 0x20);
+                    i += 2;
+                }
+                else {
+                    unescaped.push(data[i]);
+                    i += 1;
+                }
+            }
+            return Ok(Some(Packet::DATA(String::from_utf8_lossy(&unescaped).to_string())));
+        }
+    }
+
+    // True if gdb sent ^C, without waiting for it.
+    fn interrupted(&mut self) -> bool {
+        let mut b = [0u8];
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let r = self.stream.peek(&mut b);
+        let _ = self.stream.set_nonblocking(false);
+        match r {
+            Ok(1) if b[0] == 0x03 => {
+                let _ = self.stream.read(&mut b);
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+fn send(stream: &mut TcpStream, data: &str) -> io::Result<()> {
+    let mut packet = String::from("$");
+    let mut sum: u8 = 0;
+    for b in data.bytes() {
+        // # $ } and * are escaped
+        if b == b'#' || b == b'$' || b == b'}' || b == b'*' {
+            packet.push('}');
+            packet.push((b ^ 0x20) as char);
+            sum = sum.wrapping_add(b'}').wrapping_add(b ^ 0x20);
+        }
+        else {
+            packet.push(b as char);
+            sum = sum.wrapping_add(b);
+        }
+    }
+    packet.push_str(&format!("#{:02x}", sum));
+    stream.write_all(packet.as_bytes())
+}
+
+// A part of an XML document for qXfer: OFFSET,LENGTH in hex, answered with
+// m and more to come or l for the last part.
+fn transfer(document: &str, args: &str) -> Result<String, u8> {
+    let (offset, length) = address_and_length(args)?;
+    if offset >= document.len() {
+        return Ok("l".to_string());
+    }
+    let end = offset.checked_add(length).ok_or(1)?.min(document.len());
+    let more = if end < document.len() { "m" } else { "l" };
+    Ok(format!("{}{}", more, &document[offset..end]))
+}
+
+fn address_and_length(args: &str) -> Result<(usize, usize), u8> {
+    let mut parts = args.splitn(2, ',');
+    let address = usize::from_str_radix(parts.next().unwrap(), 16).map_err(|_| 1)?;
+    let length = usize::from_str_radix(parts.next().ok_or(1)?, 16).map_err(|_| 1)?;
+    Ok((address, length))
+}
+
+fn error(code: u8) -> String {
+    format!("E{:02x}", code)
+}
+
+// little-endian
+fn hex_word(w: u16) -> String {
+    format!("{:02x}{:02x}", w & 0xff, w >> 8)
+}
+
+fn word(bytes: &[u8]) -> u16 {
+    bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>, u8> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return Err(1);
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| 1)).collect()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use super::super::Computer::parse_hack;
+
+    fn max() -> GdbStub {
+        let rom = parse_hack(&std::fs::read_to_string("../Max.hack").unwrap()).unwrap();
+        let mut c = Computer::new(&rom);
+        c.ram[0] = 3;
+        c.ram[1] = 8;
+        GdbStub::new(c)
+    }
+
+    fn reply(stub: &mut GdbStub, packet: &str) -> String {
+        match stub.handle(packet) {
+            Action::REPLY(r) => r,
+            a => panic!("{:?}", a),
+        }
+    }
+
+    #[test]
+    fn packets() {
+        let mut stub = max();
+        assert_eq!(reply(&mut stub, "g"), "000000000000");
+        assert_eq!(reply(&mut stub, "m0,4"), "03000800");
+        // the first instructions of Max, @R0 D=M
+        assert_eq!(reply(&mut stub, "m10000,4"), "000010fc");
+        assert_eq!(reply(&mut stub, "M4,2:3412"), "OK");
+        assert_eq!(stub.computer.ram[2], 0x1234);
+        assert_eq!(reply(&mut stub, "M10000,2:0000"), "E0e");
+        assert_eq!(reply(&mut stub, "P1=ffff"), "OK");
+        assert_eq!(reply(&mut stub, "p1"), "ffff");
+        assert_eq!(reply(&mut stub, "G010002000a00"), "OK");
+        assert_eq!((stub.computer.a, stub.computer.d, stub.computer.pc), (1, 2, 10));
+        assert_eq!(reply(&mut stub, "p3"), "E01");
+        assert_eq!(reply(&mut stub, "m20000,2"), "E0e");
+        assert_eq!(reply(&mut stub, "Z1,4,2"), "");
+        assert_eq!(reply(&mut stub, "bogus"), "");
+        // malformed packets are answered, not panicked on
+        assert_eq!(reply(&mut stub, "\u{fffd}"), "");
+        assert_eq!(reply(&mut stub, ""), "");
+        assert_eq!(reply(&mut stub, "Mffffffffffffffff,1:00"), "E0e");
+        assert_eq!(reply(&mut stub, "mffffffffffffffff,2"), "E0e");
+        assert_eq!(reply(&mut stub, "m1,ffffffffffffffff"), "E0e");
+        assert_eq!(reply(&mut stub, "qXfer:features:read:target.xml:1,ffffffffffffffff"), "E01");
+        assert_eq!(stub.handle("vCont;s:1"), Action::STEP);
+        assert_eq!(stub.handle("D"), Action::CLOSE(Some("OK".to_string())));
+
+        // the target description comes in parts
+        let first = reply(&mut stub, "qXfer:features:read:target.xml:0,20");
+        assert_eq!(first, format!("m{}", &TARGET_XML[..0x20]));
+        let rest = reply(&mut stub, &format!("qXfer:features:read:target.xml:20,{:x}", TARGET_XML.len()));
+        assert_eq!(rest, format!("l{}", &TARGET_XML[0x20..]));
+    }
+
+    #[test]
+    fn breakpoints_and_steps() {
+        let mut stub = max();
+        assert_eq!(reply(&mut stub, "Z0,c,2"), "OK");
+        assert_eq!(stub.resume(false, || false), "S05");
+        assert_eq!(stub.computer.pc, 12);
+        assert_eq!(stub.resume(true, || false), "S05");
+        assert_eq!(stub.computer.pc, 13);
+        assert_eq!(reply(&mut stub, "z0,c,2"), "OK");
+        // to the end, where Max halts
+        assert_eq!(stub.resume(false, || false), "S05");
+        assert!(stub.computer.is_halted());
+        assert_eq!(stub.computer.ram[2], 8);
+
+        // an endless loop, D=D+1;JMP, runs until interrupted
+        let mut stub = GdbStub::new(Computer::new(&parse_hack("0000000000000000\n1110011111010111\n").unwrap()));
+        let mut polls = 0;
+        assert_eq!(stub.resume(false, || { polls += 1; polls == 3 }), "S02");
+        assert_eq!(stub.computer.cycles, 3 * POLL_CYCLES);
+    }
+
+    // A client on loopback, the way gdb talks to the stub.
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn request(&mut self, data: &str) -> String {
+            let sum = data.bytes().fold(0u8, |s, b| s.wrapping_add(b));
+            self.stream.write_all(format!("${}#{:02x}", data, sum).as_bytes()).unwrap();
+            self.read_reply()
+        }
+
+        fn read_reply(&mut self) -> String {
+            let mut b = [0u8];
+            loop {
+                self.stream.read_exact(&mut b).unwrap();
+                if b[0] == b'$' {
+                    break;
+                }
+                assert_eq!(b[0], b'+');
+            }
+            let mut data = vec![];
+            loop {
+                self.stream.read_exact(&mut b).unwrap();
+                if b[0] == b'#' {
+                    break;
+                }
+                data.push(b[0]);
+            }
+            let mut checksum = [0u8; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            let sum = data.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+            assert_eq!(u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap(), sum);
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(data).unwrap()
+        }
+    }
+
+    #[test]
+    fn loopback() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let server = std::thread::spawn(move || {
+            let mut stub = max();
+            listen(&mut stub, 0, |port| sender.send(port).unwrap()).unwrap();
+            stub.computer
+        });
+        let port = receiver.recv().unwrap();
+        let mut client = Client { stream: TcpStream::connect(("127.0.0.1", port)).unwrap() };
+        assert!(client.request("qSupported:multiprocess+").contains("qXfer:features:read+"));
+        assert_eq!(client.request("?"), "S05");
+        assert_eq!(client.request("Z0,e,2"), "OK");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p2"), "0e00");
+        assert_eq!(client.request("m4,2"), "0800");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("M4,2:0900"), "OK");
+        // a checksum error is answered with -, and the packet sent again
+        client.stream.write_all(b"$g#00").unwrap();
+        let mut b = [0u8];
+        client.stream.read_exact(&mut b).unwrap();
+        assert_eq!(b[0], b'-');
+        assert_eq!(client.request("QStartNoAckMode"), "OK");
+        assert_eq!(client.request("D"), "OK");
+        let computer = server.join().unwrap();
+        assert_eq!((computer.pc, computer.ram[2]), (15, 9));
+    }
+}
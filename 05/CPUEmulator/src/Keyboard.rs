@@ -0,0 +1,308 @@
+use super::Computer::{Computer, KBD};
+
+// The special keys of the Hack keyboard, from 128.
+const SPECIAL_KEYS: [&str; 25] = [
+    "NEWLINE", "BACKSPACE", "LEFT", "UP", "RIGHT", "DOWN", "HOME", "END", "PAGEUP", "PAGEDOWN", "INSERT", "DELETE", "ESC",
+    "F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9", "F10", "F11", "F12",
+];
+
+// A key code from its name: a number, NONE for no key, SPACE, a special
+// key such as LEFT or F1, or a single character. Digit keys are numbers,
+// 48 for 0.
+pub fn key_code(name: &str) -> Option<u16> {
+    if let Ok(n) = name.parse::<u16>() {
+        return Some(n);
+    }
+    match name {
+        "NONE" => return Some(0),
+        "SPACE" => return Some(32),
+        "ENTER" => return Some(128),
+        _ => {},
+    }
+    if let Some(i) = SPECIAL_KEYS.iter().position(|k| *k == name) {
+        return Some(128 + i as u16);
+    }
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_graphic() && !c.is_ascii_digit() => Some(c as u16),
+        _ => None,
+    }
+}
+
+// The name key_code reads back.
+pub fn key_name(code: u16) -> String {
+    match code {
+        0 => "NONE".to_string(),
+        32 => "SPACE".to_string(),
+        128..=152 => SPECIAL_KEYS[code as usize - 128].to_string(),
+        c if c < 128 && (c as u8).is_ascii_graphic() && !(c as u8).is_ascii_digit() => (c as u8 as char).to_string(),
+        c => c.to_string(),
+    }
+}
+
+// The keys pressed during a run: from each event's cycle on, KBD holds
+// its key, until the next event. A file has one "CYCLE KEY" per line, #
+// starts a comment.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Timeline {
+    events: Vec<(u64, u16)>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Timeline { events: vec![] }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut t = Timeline::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let cycle = fields[0].parse::<u64>().map_err(|_| format!("line {}: not a cycle number: {}", i + 1, fields[0]))?;
+            let key = match fields.get(1) {
+                Some(k) if fields.len() == 2 => key_code(k).ok_or_else(|| format!("line {}: unknown key: {}", i + 1, k))?,
+                _ => return Err(format!("line {}: expected CYCLE KEY", i + 1)),
+            };
+            if t.events.last().map_or(false, |(c, _)| *c > cycle) {
+                return Err(format!("line {}: cycle {} is before the one above", i + 1, cycle));
+            }
+            t.events.push((cycle, key));
+        }
+        Ok(t)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut s = String::from("# cycle key\n");
+        for (cycle, key) in &self.events {
+            s.push_str(&format!("{} {}\n", cycle, key_name(*key)));
+        }
+        s
+    }
+
+    pub fn events(&self) -> &[(u64, u16)] {
+        &self.events
+    }
+
+    // Adds an event at the end, if it changes the key. A second event at
+    // the same cycle replaces the first.
+    pub fn push(&mut self, cycle: u64, key: u16) {
+        if self.events.last().map_or(false, |(c, _)| *c == cycle) {
+            self.events.pop();
+        }
+        let current = self.events.last().map_or(0, |(_, k)| *k);
+        if key != current {
+            self.events.push((cycle, key));
+        }
+    }
+
+    // The key held at cycle.
+    pub fn key_at(&self, cycle: u64) -> u16 {
+        match self.events.iter().rposition(|(c, _)| *c <= cycle) {
+            Some(i) => self.events[i].1,
+            None => 0,
+        }
+    }
+
+    // Runs computer to limit with run, e.g. Computer::run or Engine::run,
+    // stopping at each event to set KBD. Every run with the same timeline
+    // reads the same keys at the same cycles.
+    pub fn replay<F: FnMut(&mut Computer, u64)>(&self, computer: &mut Computer, limit: u64, mut run: F) {
+        computer.ram[KBD] = self.key_at(computer.cycles);
+        for (cycle, key) in &self.events {
+            if *cycle <= computer.cycles {
+                continue;
+            }
+            if *cycle >= limit {
+                break;
+            }
+            run(computer, *cycle);
+            if computer.cycles < *cycle {
+                // halted
+                return;
+            }
+            computer.ram[KBD] = *key;
+        }
+        run(computer, limit);
+    }
+}
+
+// Presses keys on a running computer and keeps the Timeline that replays
+// them. A terminal sends no key releases, so each key is held for a number
+// of cycles, or until the next one.
+pub struct Recorder {
+    pub timeline: Timeline,
+    hold: u64,
+    release: Option<u64>,
+}
+
+impl Recorder {
+    pub fn new(hold: u64) -> Self {
+        Recorder { timeline: Timeline::new(), hold: hold, release: None }
+    }
+
+    // Presses key now. run is what runs the session, as for
+    // Timeline::replay, so that the cycle a repeated key needs goes through
+    // it as well.
+    pub fn press<F: FnMut(&mut Computer, u64)>(&mut self, computer: &mut Computer, key: u16, mut run: F) {
+        // a key pressed again, or just released, is released for a cycle,
+        // so that programs see two presses
+        let before = if computer.cycles == 0 { 0 } else { self.timeline.key_at(computer.cycles - 1) };
+        if key != 0 && (computer.ram[KBD] == key || before == key) {
+            self.set(computer, 0);
+            let cycle = computer.cycles + 1;
+            run(computer, cycle);
+        }
+        self.set(computer, key);
+        self.release = Some(computer.cycles + self.hold);
+    }
+
+    // The cycle at which the held key is released, if one is held.
+    pub fn release_at(&self) -> Option<u64> {
+        self.release
+    }
+
+    // Releases the key once its time has come.
+    pub fn update(&mut self, computer: &mut Computer) {
+        if self.release.map_or(false, |r| computer.cycles >= r) {
+            self.release = None;
+            self.set(computer, 0);
+        }
+    }
+
+    fn set(&mut self, computer: &mut Computer, key: u16) {
+        computer.ram[KBD] = key;
+        self.timeline.push(computer.cycles, key);
+    }
+}
+
+// The key codes of terminal input: arrows and other keys come as escape
+// sequences, enter as CR or LF and backspace as DEL or BS.
+pub fn decode_terminal(bytes: &[u8]) -> Vec<u16> {
+    let mut keys = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        i += 1;
+        let key = match b {
+            b'\r' | b'\n' => 128,
+            0x7f | 0x08 => 129,
+            0x1b if i < bytes.len() && (bytes[i] == b'[' || bytes[i] == b'O') => {
+                // ESC [ then parameters and a final letter or ~
+                let start = i + 1;
+                let mut end = start;
+                while end < bytes.len() && !(bytes[end].is_ascii_alphabetic() || bytes[end] == b'~') {
+                    end += 1;
+                }
+                if end == bytes.len() {
+                    i = end;
+                    continue;
+                }
+                i = end + 1;
+                let params = std::str::from_utf8(&bytes[start..end]).unwrap_or("");
+                match (bytes[end], params) {
+                    (b'A', _) => 131,
+                    (b'B', _) => 133,
+                    (b'C', _) => 132,
+                    (b'D', _) => 130,
+                    (b'H', _) | (b'~', "1") => 134,
+                    (b'F', _) | (b'~', "4") => 135,
+                    (b'~', "5") => 136,
+                    (b'~', "6") => 137,
+                    (b'~', "2") => 138,
+                    (b'~', "3") => 139,
+                    (b'P', _) => 141,
+                    (b'Q', _) => 142,
+                    (b'R', _) => 143,
+                    (b'S', _) => 144,
+                    (b'~', "15") => 145,
+                    (b'~', "17") => 146,
+                    (b'~', "18") => 147,
+                    (b'~', "19") => 148,
+                    (b'~', "20") => 149,
+                    (b'~', "21") => 150,
+                    (b'~', "23") => 151,
+                    (b'~', "24") => 152,
+                    _ => continue,
+                }
+            },
+            0x1b => 140,
+            0x20..=0x7e => b as u16,
+            _ => continue,
+        };
+        keys.push(key);
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(key_code("LEFT"), Some(130));
+        assert_eq!(key_code("F12"), Some(152));
+        assert_eq!(key_code("a"), Some(97));
+        assert_eq!(key_code("7"), Some(7));
+        assert_eq!(key_code("ab"), None);
+        for code in 0..=152 {
+            assert_eq!(key_code(&key_name(code)), Some(code));
+        }
+        assert_eq!(decode_terminal(b"a\x1b[D\x1b[C\r\x7f\x1b[3~\x1bOP\x1b"), vec![97, 130, 132, 128, 129, 139, 141, 140]);
+    }
+
+    #[test]
+    fn timeline() {
+        let t = Timeline::parse("# pong\n100 LEFT\n250 NONE  # release\n300 q\n").unwrap();
+        assert_eq!(t.events(), &[(100, 130), (250, 0), (300, 113)]);
+        assert_eq!((t.key_at(99), t.key_at(100), t.key_at(260)), (0, 130, 0));
+        assert_eq!(Timeline::parse(&t.to_text()).unwrap(), t);
+        assert!(Timeline::parse("10 LEFT\n5 NONE\n").is_err());
+        assert!(Timeline::parse("10 SHIFT\n").is_err());
+
+        // a live session replays the same
+        let mut c = Computer::new(&[]);
+        let mut r = Recorder::new(50);
+        let mut runs = vec![];
+        let mut run = |c: &mut Computer, limit: u64| {
+            runs.push((c.cycles, limit));
+            c.run(limit);
+        };
+        run(&mut c, 10);
+        r.press(&mut c, 97, &mut run);
+        run(&mut c, 20);
+        r.press(&mut c, 97, &mut run);
+        let release = r.release_at().unwrap();
+        run(&mut c, release);
+        r.update(&mut c);
+        assert_eq!(r.timeline.events(), &[(10, 97), (20, 0), (21, 97), (71, 0)]);
+        assert_eq!(c.ram[KBD], 0);
+        // and again as soon as it is released
+        r.press(&mut c, 97, &mut run);
+        assert_eq!(&r.timeline.events()[3..], &[(71, 0), (72, 97)]);
+        // the cycle of each release went through run
+        assert_eq!(runs, vec![(0, 10), (10, 20), (20, 21), (21, 71), (71, 72)]);
+    }
+
+    #[test]
+    fn replay() {
+        // adds KBD to R0 on every pass of a 4 instruction loop
+        let source = "(LOOP)\r\n@KBD\r\nD=M\r\n@R0\r\nM=D+M\r\n@LOOP\r\n0;JMP\r\n";
+        let assembly = assembler::assemble(std::io::Cursor::new(source)).unwrap();
+        let rom = super::super::Computer::parse_hack(&assembly.hack_code.join("\n")).unwrap();
+        let t = Timeline::parse("0 1\n60 100\n120 NONE\n").unwrap();
+        let mut step = Computer::new(&rom);
+        t.replay(&mut step, 600, |c, limit| c.run(limit));
+        let mut blocks = Computer::new(&rom);
+        let mut engine = super::super::Engine::Engine::new();
+        t.replay(&mut blocks, 600, |c, limit| engine.run(c, limit));
+        // KBD is read at cycles 1, 7, 13, ...: 10 reads of 1, 10 of 100
+        assert_eq!(step.ram[0], 10 + 1000);
+        assert_eq!((blocks.ram[0], blocks.cycles, blocks.pc), (step.ram[0], step.cycles, step.pc));
+    }
+}
@@ -0,0 +1,549 @@
+// PNG reading and writing for black and white images, with the zlib,
+// deflate and CRC parts written out here since the crate has no
+// dependencies.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+// A decoded image. A pixel is black when its luminance is below half.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub black: Vec<bool>,
+}
+
+// Encodes a 1 bit grayscale PNG.
+pub fn encode(width: usize, height: usize, black: &dyn Fn(usize, usize) -> bool) -> Vec<u8> {
+    let row_bytes = (width + 7) / 8;
+    let mut raw = Vec::with_capacity((row_bytes + 1) * height);
+    for y in 0..height {
+        // filter type None
+        raw.push(0);
+        for b in 0..row_bytes {
+            let mut byte = 0u8;
+            for i in 0..8 {
+                let x = b * 8 + i;
+                // 1 is white
+                if x < width && !black(x, y) {
+                    byte |= 0x80 >> i;
+                }
+            }
+            raw.push(byte);
+        }
+    }
+
+    let mut ihdr = vec![];
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // bit depth 1, grayscale, deflate, no filtering extensions, no interlace
+    ihdr.extend_from_slice(&[1, 0, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    write_chunk(&mut png, b"IDAT", &zlib_compress(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// Decodes a non-interlaced PNG of any color type.
+pub fn decode(data: &[u8]) -> Result<Image, String> {
+    if data.len() < 8 || data[..8] != SIGNATURE {
+        return Err("not a PNG file".to_string());
+    }
+    let mut pos = 8;
+    let mut header = None;
+    let mut palette = vec![];
+    let mut idat = vec![];
+    loop {
+        if pos + 12 > data.len() {
+            return Err("truncated PNG".to_string());
+        }
+        let length = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        if pos + 12 + length > data.len() {
+            return Err("truncated PNG".to_string());
+        }
+        let kind = &data[pos + 4..pos + 8];
+        let body = &data[pos + 8..pos + 8 + length];
+        let crc = u32::from_be_bytes([data[pos + 8 + length], data[pos + 9 + length], data[pos + 10 + length], data[pos + 11 + length]]);
+        if crc32(&data[pos + 4..pos + 8 + length]) != crc {
+            return Err(format!("bad CRC in {} chunk", String::from_utf8_lossy(kind)));
+        }
+        pos += 12 + length;
+        match kind {
+            b"IHDR" => {
+                if length != 13 {
+                    return Err("bad IHDR chunk".to_string());
+                }
+                let width = u32::from_be_bytes([body[0], body[1], body[2], body[3]]) as usize;
+                let height = u32::from_be_bytes([body[4], body[5], body[6], body[7]]) as usize;
+                if body[12] != 0 {
+                    return Err("interlaced PNG is not supported".to_string());
+                }
+                header = Some((width, height, body[8], body[9]));
+            },
+            b"PLTE" => palette = body.to_vec(),
+            b"IDAT" => idat.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {},
+        }
+    }
+
+    let (width, height, depth, color) = header.ok_or("no IHDR chunk")?;
+    let channels = match color {
+        0 => 1,
+        2 => 3,
+        3 => 1,
+        4 => 2,
+        6 => 4,
+        _ => return Err(format!("unknown color type {}", color)),
+    };
+    if ![1, 2, 4, 8, 16].contains(&depth) || (color != 0 && color != 3 && depth < 8) || (color == 3 && depth > 8) {
+        return Err(format!("unsupported bit depth {}", depth));
+    }
+    let bits = channels * depth as usize;
+    // the size the header claims, checked before anything depends on it
+    let too_large = || format!("the image is too large: {}x{}", width, height);
+    let row_bytes = (width.checked_mul(bits).ok_or_else(too_large)? + 7) / 8;
+    let size = (row_bytes + 1).checked_mul(height).ok_or_else(too_large)?;
+    let bpp = std::cmp::max(1, bits / 8);
+    let raw = zlib_decompress(&idat)?;
+    if raw.len() < size {
+        return Err("image data is too short".to_string());
+    }
+
+    let mut black = Vec::with_capacity(width * height);
+    let mut previous = vec![0u8; row_bytes];
+    for y in 0..height {
+        let start = y * (row_bytes + 1);
+        let mut row = raw[start + 1..start + 1 + row_bytes].to_vec();
+        unfilter(raw[start], &mut row, &previous, bpp)?;
+        for x in 0..width {
+            // the most significant byte of each sample is enough here
+            let sample = |i: usize| -> u32 {
+                if depth >= 8 {
+                    row[(x * channels + i) * (depth as usize / 8)] as u32
+                }
+                else {
+                    let bit = (x * channels + i) * depth as usize;
+                    let v = (row[bit / 8] >> (8 - depth as usize - bit % 8)) & ((1 << depth) - 1);
+                    v as u32 * 255 / ((1 << depth) - 1)
+                }
+            };
+            let luminance = match color {
+                0 | 4 => sample(0),
+                3 => {
+                    let bit = x * depth as usize;
+                    let index = if depth == 8 { row[x] as usize } else { ((row[bit / 8] >> (8 - depth as usize - bit % 8)) & ((1 << depth) - 1)) as usize };
+                    if index * 3 + 2 >= palette.len() {
+                        return Err("palette index out of range".to_string());
+                    }
+                    let p = &palette[index * 3..index * 3 + 3];
+                    (p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114) / 1000
+                },
+                _ => (sample(0) * 299 + sample(1) * 587 + sample(2) * 114) / 1000,
+            };
+            black.push(luminance < 128);
+        }
+        previous = row;
+    }
+    Ok(Image { width: width, height: height, black: black })
+}
+
+fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], bpp: usize) -> Result<(), String> {
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] as i32 } else { 0 };
+        let b = previous[i] as i32;
+        let c = if i >= bpp { previous[i - bpp] as i32 } else { 0 };
+        let predictor = match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => (a + b) / 2,
+            4 => {
+                let p = a + b - c;
+                let (pa, pb, pc) = ((p - a).abs(), (p - b).abs(), (p - c).abs());
+                if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+            },
+            _ => return Err(format!("unknown filter type {}", filter)),
+        };
+        row[i] = row[i].wrapping_add(predictor as u8);
+    }
+    Ok(())
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        self.bit |= value << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.bytes.push(self.bit as u8);
+            self.bit >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes go most significant bit first.
+    fn write_code(&mut self, code: u32, bits: u32) {
+        let mut reversed = 0;
+        for i in 0..bits {
+            reversed |= ((code >> i) & 1) << (bits - 1 - i);
+        }
+        self.write(reversed, bits);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bit as u8);
+        }
+        self.bytes
+    }
+}
+
+fn write_literal(w: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => w.write_code(0x30 + symbol, 8),
+        144..=255 => w.write_code(0x190 + symbol - 144, 9),
+        256..=279 => w.write_code(symbol - 256, 7),
+        _ => w.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, length: usize, distance: usize) {
+    let l = LENGTH_BASE.iter().rposition(|&b| b as usize <= length).unwrap();
+    write_literal(w, 257 + l as u32);
+    w.write((length - LENGTH_BASE[l] as usize) as u32, LENGTH_EXTRA[l] as u32);
+    let d = DISTANCE_BASE.iter().rposition(|&b| b as usize <= distance).unwrap();
+    w.write_code(d as u32, 5);
+    w.write((distance - DISTANCE_BASE[d] as usize) as u32, DISTANCE_EXTRA[d] as u32);
+}
+
+// zlib stream with a single fixed Huffman block and greedy LZ77 matching,
+// which is plenty for screen images that are mostly blank.
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter { bytes: vec![0x78, 0x01], bit: 0, count: 0 };
+    // final block, fixed Huffman
+    w.write(1, 1);
+    w.write(1, 2);
+    let mut last = vec![usize::MAX; 1 << 15];
+    let hash = |i: usize| ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize) & 0x7fff;
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        if i + 3 <= data.len() {
+            let h = hash(i);
+            let candidate = last[h];
+            last[h] = i;
+            // a run of one byte repeated is the common case
+            for &start in &[candidate, i.wrapping_sub(1)] {
+                if start >= i || i - start > 32768 {
+                    continue;
+                }
+                let mut length = 0;
+                while length < 258 && i + length < data.len() && data[start + length] == data[i + length] {
+                    length += 1;
+                }
+                if length > best.0 {
+                    best = (length, i - start);
+                }
+            }
+        }
+        if best.0 >= 3 {
+            write_match(&mut w, best.0, best.1);
+            for j in i + 1..i + best.0 {
+                if j + 3 <= data.len() {
+                    last[hash(j)] = j;
+                }
+            }
+            i += best.0;
+        }
+        else {
+            write_literal(&mut w, data[i] as u32);
+            i += 1;
+        }
+    }
+    write_literal(&mut w, 256);
+    let mut bytes = w.finish();
+    bytes.extend_from_slice(&adler32(data).to_be_bytes());
+    bytes
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn read(&mut self, bits: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..bits {
+            if self.pos >= self.data.len() {
+                return Err("truncated deflate stream".to_string());
+            }
+            value |= (((self.data[self.pos] >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit > 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+// A canonical Huffman code: the number of codes of each length and the
+// symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &l in lengths {
+            counts[l as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for i in 1..16 {
+            offsets[i] = offsets[i - 1] + counts[i - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (s, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbols[offsets[l as usize] as usize] = s as u16;
+                offsets[l as usize] += 1;
+            }
+        }
+        Huffman { counts: counts, symbols: symbols }
+    }
+
+    fn decode(&self, r: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= r.read(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("bad Huffman code".to_string())
+    }
+}
+
+pub fn zlib_decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 6 || data[0] & 0x0f != 8 || ((data[0] as u16) << 8 | data[1] as u16) % 31 != 0 {
+        return Err("bad zlib header".to_string());
+    }
+    let mut r = BitReader { data: &data[2..], pos: 0, bit: 0 };
+    let mut out = vec![];
+    loop {
+        let last = r.read(1)?;
+        match r.read(2)? {
+            0 => {
+                r.align();
+                let p = r.pos;
+                if p + 4 > r.data.len() {
+                    return Err("truncated deflate stream".to_string());
+                }
+                let length = u16::from_le_bytes([r.data[p], r.data[p + 1]]) as usize;
+                if p + 4 + length > r.data.len() {
+                    return Err("truncated deflate stream".to_string());
+                }
+                out.extend_from_slice(&r.data[p + 4..p + 4 + length]);
+                r.pos = p + 4 + length;
+            },
+            1 => {
+                let mut lengths = [0u8; 288];
+                for (i, l) in lengths.iter_mut().enumerate() {
+                    *l = match i {
+                        0..=143 => 8,
+                        144..=255 => 9,
+                        256..=279 => 7,
+                        _ => 8,
+                    };
+                }
+                inflate_block(&mut r, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            },
+            2 => {
+                let literals = r.read(5)? as usize + 257;
+                let distances = r.read(5)? as usize + 1;
+                let code_lengths = r.read(4)? as usize + 4;
+                const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+                let mut lengths = [0u8; 19];
+                for &i in ORDER.iter().take(code_lengths) {
+                    lengths[i] = r.read(3)? as u8;
+                }
+                let code = Huffman::new(&lengths);
+                let mut lengths = vec![];
+                while lengths.len() < literals + distances {
+                    let symbol = code.decode(&mut r)?;
+                    let (value, repeat) = match symbol {
+                        0..=15 => (symbol as u8, 1),
+                        16 => (*lengths.last().ok_or("repeat with no length")?, 3 + r.read(2)?),
+                        17 => (0, 3 + r.read(3)?),
+                        _ => (0, 11 + r.read(7)?),
+                    };
+                    for _ in 0..repeat {
+                        lengths.push(value);
+                    }
+                }
+                inflate_block(&mut r, &mut out, &Huffman::new(&lengths[..literals]), &Huffman::new(&lengths[literals..literals + distances]))?;
+            },
+            _ => return Err("bad deflate block type".to_string()),
+        }
+        if last == 1 {
+            break;
+        }
+    }
+    r.align();
+    let p = r.pos;
+    if p + 4 > r.data.len() || u32::from_be_bytes([r.data[p], r.data[p + 1], r.data[p + 2], r.data[p + 3]]) != adler32(&out) {
+        return Err("bad zlib checksum".to_string());
+    }
+    Ok(out)
+}
+
+fn inflate_block(r: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(r)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let l = symbol - 257;
+        if l >= 29 {
+            return Err("bad length code".to_string());
+        }
+        let length = LENGTH_BASE[l] as usize + r.read(LENGTH_EXTRA[l] as u32)? as usize;
+        let d = distances.decode(r)? as usize;
+        if d >= 30 {
+            return Err("bad distance code".to_string());
+        }
+        let distance = DISTANCE_BASE[d] as usize + r.read(DISTANCE_EXTRA[d] as u32)? as usize;
+        if distance > out.len() {
+            return Err("distance beyond the start of the data".to_string());
+        }
+        for _ in 0..length {
+            out.push(out[out.len() - distance]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn zlib_round_trip() {
+        let mut data = vec![0u8; 5000];
+        data.extend((0..3000).map(|i| (i * 7 % 251) as u8));
+        data.extend(b"abcabcabcabcabc".iter());
+        let compressed = zlib_compress(&data);
+        assert!(compressed.len() < data.len());
+        assert_eq!(zlib_decompress(&compressed).unwrap(), data);
+
+        // a stored block, as other encoders may write
+        let stored = [0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c', 0x02, 0x4d, 0x01, 0x27];
+        assert_eq!(zlib_decompress(&stored).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let png = encode(10, 3, &|x, y| x == y);
+        let image = decode(&png).unwrap();
+        assert_eq!((image.width, image.height), (10, 3));
+        assert!(image.black[0] && image.black[11] && image.black[22]);
+        assert_eq!(image.black.iter().filter(|&&b| b).count(), 3);
+
+        let mut broken = png.clone();
+        let last = broken.len() - 13;
+        broken[last] ^= 1;
+        assert!(decode(&broken).is_err());
+    }
+
+    // A PNG with the given IHDR fields and the rows of raw.
+    fn png_of(width: u32, height: u32, depth: u8, color: u8, raw: &[u8]) -> Vec<u8> {
+        let mut ihdr = vec![];
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[depth, color, 0, 0, 0]);
+        let mut png = SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &ihdr);
+        write_chunk(&mut png, b"PLTE", &[0, 0, 0, 255, 255, 255]);
+        write_chunk(&mut png, b"IDAT", &zlib_compress(raw));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    #[test]
+    fn malformed_headers() {
+        // palette indices are at most 8 bits
+        assert_eq!(decode(&png_of(2, 1, 16, 3, &[0, 0, 0, 0, 1])), Err("unsupported bit depth 16".to_string()));
+        assert!(decode(&png_of(2, 1, 8, 3, &[0, 0, 1])).is_ok());
+        // a size whose data would not fit in memory
+        assert_eq!(decode(&png_of(0xffff_ffff, 0xffff_ffff, 16, 6, &[0])), Err("the image is too large: 4294967295x4294967295".to_string()));
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::{HashMap, HashSet};
+
+use super::Computer::{Computer, ROM_SIZE};
+use super::SymbolMap::SymbolMap;
+
+// What reaching a ROM address means for the call stack.
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+enum Mark {
+    NONE,
+    // the (Foo.bar) label of writeFunction, reached by a call
+    ENTRY(usize),
+    // a Foo.bar$RETURN_ADDR.N label of writeCall, reached by a return
+    RETURN,
+}
+
+// A node of the call tree: a function called along one path from the top.
+#[derive(Clone)]
+#[derive(Debug)]
+struct Node {
+    parent: usize,
+    function: usize,
+    // exclusive
+    cycles: u64,
+}
+
+// The name of the root of the call tree, the code before the first call.
+const TOP: &str = "(top)";
+
+// Counts the instructions a program executes, per ROM address and per path
+// through the call tree. The calls and returns are found by the labels the
+// VM translator emits.
+pub struct Profiler {
+    counts: Vec<u64>,
+    marks: Vec<Mark>,
+    functions: Vec<String>,
+    function_labels: HashSet<String>,
+    nodes: Vec<Node>,
+    children: HashMap<(usize, usize), usize>,
+    current: usize,
+}
+
+// The labels of VM functions: writeFunction emits (Foo.bar) and then
+// (Foo.bar$$LOOP_START) for the locals, (Foo.bar$LOOP_START) in older
+// translations.
+pub fn function_labels(symbols: &SymbolMap) -> HashSet<String> {
+    symbols.labels().iter()
+        .filter_map(|(_, n)| n.strip_suffix("$$LOOP_START").or_else(|| n.strip_suffix("$LOOP_START")))
+        .filter(|f| symbols.rom_address(f).is_some())
+        .map(|f| f.to_string())
+        .collect()
+}
+
+pub fn is_return_label(name: &str) -> bool {
+    name.contains("$RETURN_ADDR")
+}
+
+impl Profiler {
+    pub fn new(symbols: &SymbolMap) -> Self {
+        let mut p = Profiler {
+            counts: vec![0; ROM_SIZE],
+            marks: vec![Mark::NONE; ROM_SIZE],
+            functions: vec![TOP.to_string()],
+            function_labels: function_labels(symbols),
+            nodes: vec![Node { parent: 0, function: 0, cycles: 0 }],
+            children: HashMap::new(),
+            current: 0,
+        };
+        for (address, name) in symbols.labels() {
+            let address = *address as usize;
+            if p.function_labels.contains(name) {
+                p.marks[address] = Mark::ENTRY(p.functions.len());
+                p.functions.push(name.clone());
+            }
+            else if is_return_label(name) && p.marks[address] == Mark::NONE {
+                p.marks[address] = Mark::RETURN;
+            }
+        }
+        p
+    }
+
+    // Counts the instruction at PC, before it runs.
+    pub fn record(&mut self, computer: &Computer) {
+        let pc = computer.pc as usize & (ROM_SIZE - 1);
+        self.counts[pc] += 1;
+        match self.marks[pc] {
+            Mark::NONE => {},
+            Mark::ENTRY(f) => {
+                let next = self.nodes.len();
+                let parent = self.current;
+                let node = *self.children.entry((parent, f)).or_insert(next);
+                if node == next {
+                    self.nodes.push(Node { parent: parent, function: f, cycles: 0 });
+                }
+                self.current = node;
+            },
+            Mark::RETURN => self.current = self.nodes[self.current].parent,
+        }
+        self.nodes[self.current].cycles += 1;
+    }
+
+    // Runs like Computer::run, counting every instruction.
+    pub fn run(&mut self, computer: &mut Computer, limit: u64) {
+        while computer.cycles < limit && !computer.is_halted() {
+            self.record(computer);
+            computer.step();
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts[address as usize]
+    }
+
+    // (name, cycles) of the enclosing labels that keep, most first.
+    fn by_label<F: Fn(&str) -> bool>(&self, symbols: &SymbolMap, keep: F) -> Vec<(String, u64)> {
+        let mut totals: HashMap<String, u64> = HashMap::new();
+        // the labels that keep, sorted by address
+        let labels: Vec<&(u16, String)> = symbols.labels().iter().filter(|(_, n)| keep(n)).collect();
+        let mut l = 0;
+        for (address, count) in self.counts.iter().enumerate() {
+            while l < labels.len() && labels[l].0 as usize <= address {
+                l += 1;
+            }
+            if *count == 0 {
+                continue;
+            }
+            let name = if l == 0 { TOP } else { labels[l - 1].1.as_str() };
+            *totals.entry(name.to_string()).or_insert(0) += count;
+        }
+        sorted(totals)
+    }
+
+    // Cycles per enclosing label.
+    pub fn labels(&self, symbols: &SymbolMap) -> Vec<(String, u64)> {
+        self.by_label(symbols, |n| !is_return_label(n))
+    }
+
+    // Cycles per enclosing VM function.
+    pub fn functions(&self, symbols: &SymbolMap) -> Vec<(String, u64)> {
+        self.by_label(symbols, |n| self.function_labels.contains(n))
+    }
+
+    // (function, exclusive, inclusive) from the call tree, by inclusive
+    // cycles. A recursive function counts once in its own inclusive cycles.
+    pub fn call_tree(&self) -> Vec<(String, u64, u64)> {
+        let mut exclusive = vec![0; self.functions.len()];
+        let mut inclusive = vec![0; self.functions.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            exclusive[node.function] += node.cycles;
+            let mut seen = vec![];
+            for f in self.path(i) {
+                if !seen.contains(&f) {
+                    seen.push(f);
+                    inclusive[f] += node.cycles;
+                }
+            }
+        }
+        let mut rows: Vec<(String, u64, u64)> = (0..self.functions.len())
+            .filter(|f| inclusive[*f] > 0)
+            .map(|f| (self.functions[f].clone(), exclusive[f], inclusive[f]))
+            .collect();
+        rows.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.cmp(&a.1)).then(a.0.cmp(&b.0)));
+        rows
+    }
+
+    // The functions from the top to a node.
+    fn path(&self, node: usize) -> Vec<usize> {
+        let mut path = vec![];
+        let mut n = node;
+        while n != 0 {
+            path.push(self.nodes[n].function);
+            n = self.nodes[n].parent;
+        }
+        path.push(0);
+        path.reverse();
+        path
+    }
+
+    // One "(top);Sys.init;Main.main 1234" line per call path, the format of
+    // flamegraph.pl and other folded stack tools.
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self.nodes.iter().enumerate()
+            .filter(|(_, n)| n.cycles > 0)
+            .map(|(i, n)| {
+                let names: Vec<&str> = self.path(i).iter().map(|f| self.functions[*f].as_str()).collect();
+                format!("{} {}", names.join(";"), n.cycles)
+            })
+            .collect();
+        lines.sort();
+        let mut s = lines.join("\n");
+        s.push('\n');
+        s
+    }
+
+    // The flat reports, by call tree, VM function and label.
+    pub fn report(&self, symbols: &SymbolMap) -> String {
+        let total = self.cycles();
+        let percent = |c: u64| if total == 0 { 0.0 } else { c as f64 * 100.0 / total as f64 };
+        let mut s = format!("{} cycles\n", total);
+        s.push_str("\ncall tree\n   exclusive       %   inclusive       %  function\n");
+        for (name, e, i) in self.call_tree() {
+            s.push_str(&format!("{:>12} {:>6.2}% {:>11} {:>6.2}%  {}\n", e, percent(e), i, percent(i), name));
+        }
+        for (title, rows) in [("VM function", self.functions(symbols)), ("label", self.labels(symbols))].iter() {
+            s.push_str(&format!("\nby {}\n      cycles       %  {}\n", title, title));
+            for (name, c) in rows {
+                s.push_str(&format!("{:>12} {:>6.2}%  {}\n", c, percent(*c), name));
+            }
+        }
+        s
+    }
+}
+
+fn sorted(totals: HashMap<String, u64>) -> Vec<(String, u64)> {
+    let mut rows: Vec<(String, u64)> = totals.into_iter().collect();
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    rows
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use super::super::Computer::parse_hack;
+
+    #[test]
+    fn fibonacci() {
+        // the translation of 08/FunctionCalls/FibonacciElement, fib(4)
+        let f = std::fs::File::open("../../08/FunctionCalls/FibonacciElement/FibonacciElement.asm").unwrap();
+        let assembly = assembler::assemble(f).unwrap();
+        let symbols = SymbolMap::parse(&assembly.symbol_map()).unwrap();
+        let mut c = Computer::new(&parse_hack(&assembly.hack_code.join("\n")).unwrap());
+        let mut p = Profiler::new(&symbols);
+        p.run(&mut c, 100_000);
+        assert!(c.is_halted());
+        assert_eq!(p.cycles(), c.cycles);
+
+        let tree = p.call_tree();
+        let fib = tree.iter().find(|r| r.0 == "Main.fibonacci").unwrap();
+        let init = tree.iter().find(|r| r.0 == "Sys.init").unwrap();
+        // everything after the bootstrap is in Sys.init
+        assert_eq!(tree[0].0, TOP);
+        assert_eq!(tree[0].2, p.cycles());
+        assert_eq!(init.2, init.1 + fib.2);
+        // the call tree and the enclosing functions agree
+        let functions = p.functions(&symbols);
+        assert_eq!(functions.iter().find(|r| r.0 == "Main.fibonacci").unwrap().1, fib.1);
+
+        // fib(4) calls fib 8 times below the first, 4 deep at most
+        let folded = p.folded();
+        assert_eq!(folded.lines().filter(|l| l.contains("Main.fibonacci")).count(), 4);
+        assert!(folded.contains("\n(top);Sys.init;Main.fibonacci;Main.fibonacci;Main.fibonacci;Main.fibonacci "));
+        let total: u64 = folded.lines().map(|l| l.rsplit(' ').next().unwrap().parse::<u64>().unwrap()).sum();
+        assert_eq!(total, p.cycles());
+        assert!(p.report(&symbols).contains("%  IF_FALSE\n"));
+    }
+
+    #[test]
+    fn function_labels_both_forms() {
+        let symbols = SymbolMap::parse("\
+            rom 10 Foo.bar\n\
+            rom 15 Foo.bar$$LOOP_START\n\
+            rom 30 Foo.bar$LOOP_START\n\
+            rom 40 Old.f\n\
+            rom 45 Old.f$LOOP_START\n\
+            rom 50 Sys.init$$LOOP_START\n").unwrap();
+        let mut f: Vec<String> = function_labels(&symbols).into_iter().collect();
+        f.sort();
+        // Sys.init has no label of its own
+        assert_eq!(f, vec!["Foo.bar", "Old.f"]);
+    }
+}
@@ -0,0 +1,397 @@
+use super::Computer::{self, ROM_SIZE};
+use super::Disassembler;
+use super::SymbolMap::SymbolMap;
+
+// The C before the ROM: RAM and the registers.
+const HEADER: &str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#define RAM_SIZE 32768
+#define SCREEN 16384
+#define KBD 24576
+
+uint16_t ram[RAM_SIZE];
+uint16_t a, d, pc;
+uint64_t cycles;
+"#;
+
+// The C after the ROM: the screen and keyboard hooks, and an interpreter
+// for what the translated blocks don't cover.
+const RUNTIME: &str = r#"
+/* With HACK_HOOKS defined, the program provides these: the key held at a
+   cycle, and each write to the screen. */
+#ifdef HACK_HOOKS
+uint16_t hack_keyboard(uint64_t cycle);
+void hack_screen(uint16_t address, uint16_t value, uint64_t cycle);
+#else
+#define hack_keyboard(cycle) ram[KBD]
+#define hack_screen(address, value, cycle) ((void)0)
+#endif
+
+#define RD(address) ((address) == KBD ? hack_keyboard(cycles) : ram[address])
+#define WR(address, value) do { \
+        ram[address] = (value); \
+        if ((address) >= SCREEN && (address) < KBD) hack_screen((address), (value), cycles); \
+    } while (0)
+
+static uint16_t alu(uint16_t x, uint16_t y, unsigned control) {
+    uint16_t out;
+    if (control & 0x20) x = 0;
+    if (control & 0x10) x = (uint16_t)~x;
+    if (control & 0x08) y = 0;
+    if (control & 0x04) y = (uint16_t)~y;
+    out = (control & 0x02) ? (uint16_t)(x + y) : (uint16_t)(x & y);
+    return (control & 0x01) ? (uint16_t)~out : out;
+}
+
+/* an @X / 0;JMP loop at X */
+static int halt_loop(unsigned at) {
+    return at + 1 < ROM_SIZE && rom[at] == at && (rom[at + 1] & 0xe03f) == 0xe007;
+}
+
+static int halted(void) {
+    return halt_loop(pc) || (pc > 0 && halt_loop(pc - 1u) && a == pc - 1u);
+}
+
+/* One instruction, as the reference emulator runs it. */
+static void step(void) {
+    uint16_t i = rom[pc & (ROM_SIZE - 1)];
+    uint16_t address, y, out, target;
+    int negative, zero, taken;
+    cycles++;
+    if (!(i & 0x8000)) {
+        a = i;
+        pc++;
+        return;
+    }
+    address = a & (RAM_SIZE - 1);
+    y = (i & 0x1000) ? RD(address) : a;
+    out = alu(d, y, (i >> 6) & 0x3f);
+    if (i & 0x08) WR(address, out);
+    target = a;
+    if (i & 0x20) a = out;
+    if (i & 0x10) d = out;
+    negative = (out & 0x8000) != 0;
+    zero = out == 0;
+    taken = ((i & 4) && negative) || ((i & 2) && zero) || ((i & 1) && !negative && !zero);
+    pc = taken ? target : (uint16_t)(pc + 1);
+}
+"#;
+
+const MAIN: &str = r#"
+#ifndef HACK_NO_MAIN
+/* prog [CYCLES] [ADDRESS=VALUE]...: runs until the program halts or CYCLES
+   have run, then prints the registers and the RAM words that are not 0. */
+int main(int argc, char **argv) {
+    uint64_t limit = argc > 1 ? strtoull(argv[1], 0, 10) : 1000000;
+    unsigned i;
+    int n;
+    for (n = 2; n < argc; n++) {
+        unsigned address;
+        int value;
+        if (sscanf(argv[n], "%u=%i", &address, &value) != 2 || address >= RAM_SIZE) {
+            fprintf(stderr, "expected ADDRESS=VALUE, not %s\n", argv[n]);
+            return 1;
+        }
+        ram[address] = (uint16_t)value;
+    }
+    run(limit);
+    printf("pc=%u a=%u d=%u cycles=%llu %s\n", pc, a, d, (unsigned long long)cycles, halted() ? "halted" : "running");
+    for (i = 0; i < RAM_SIZE; i++) {
+        if (ram[i]) printf("%u %u\n", i, ram[i]);
+    }
+    return 0;
+}
+#endif
+"#;
+
+// C for the computations of the Hack ALU, with x the D register.
+fn computation(control: u16, y: &str) -> String {
+    let e = match control {
+        0b101010 => "0".to_string(),
+        0b111111 => "1".to_string(),
+        0b111010 => "0xffff".to_string(),
+        0b001100 => "d".to_string(),
+        0b110000 => y.to_string(),
+        0b001101 => "~d".to_string(),
+        0b110001 => format!("~{}", y),
+        0b001111 => "-d".to_string(),
+        0b110011 => format!("-{}", y),
+        0b011111 => "d + 1".to_string(),
+        0b110111 => format!("{} + 1", y),
+        0b001110 => "d - 1".to_string(),
+        0b110010 => format!("{} - 1", y),
+        0b000010 => format!("d + {}", y),
+        0b010011 => format!("d - {}", y),
+        0b000111 => format!("{} - d", y),
+        0b000000 => format!("d & {}", y),
+        0b010101 => format!("d | {}", y),
+        c => return format!("alu(d, {}, {})", y, c),
+    };
+    format!("(uint16_t)({})", e)
+}
+
+fn condition(jump: u16) -> &'static str {
+    match jump {
+        1 => "(int16_t)out > 0",
+        2 => "out == 0",
+        3 => "(int16_t)out >= 0",
+        4 => "(int16_t)out < 0",
+        5 => "out != 0",
+        6 => "(int16_t)out <= 0",
+        _ => "1",
+    }
+}
+
+// The addresses that start a block: where the program starts, where
+// constant jumps and labels lead, the instructions after jumps, and the
+// halt loops, which stop the program.
+pub fn leaders(rom: &[u16], symbols: &SymbolMap) -> Vec<bool> {
+    let n = rom.len();
+    let mut leader = vec![false; n];
+    if n > 0 {
+        leader[0] = true;
+    }
+    for (i, word) in rom.iter().enumerate() {
+        if word & 0x8000 == 0 {
+            // @LABEL, e.g. the return address of a VM call
+            if (*word as usize) < n {
+                leader[*word as usize] = true;
+            }
+        }
+        else if word & 0x07 != 0 && i + 1 < n {
+            leader[i + 1] = true;
+        }
+        if Computer::is_halt_loop(rom, i) {
+            leader[i] = true;
+            leader[i + 1] = true;
+        }
+    }
+    for (address, _) in symbols.labels() {
+        if (*address as usize) < n {
+            leader[*address as usize] = true;
+        }
+    }
+    leader
+}
+
+// The instructions from each leader to the end of its block, 0 elsewhere.
+fn block_lengths(rom: &[u16], leader: &[bool]) -> Vec<usize> {
+    let n = rom.len();
+    let mut lengths = vec![0; n];
+    for start in (0..n).filter(|s| leader[*s]) {
+        let mut end = start;
+        loop {
+            let jumps = rom[end] & 0x8000 != 0 && rom[end] & 0x07 != 0;
+            end += 1;
+            if jumps || end == n || leader[end] {
+                break;
+            }
+        }
+        lengths[start] = end - start;
+    }
+    lengths
+}
+
+// A C program that runs the Hack program rom like Computer::run: every
+// block of ROM becomes straight C code, jumps to constant addresses are
+// gotos and the others go through a switch on PC. The result is the same,
+// cycle for cycle; the program stops at the same cycle limit or halt loop.
+pub fn recompile(rom: &[u16], symbols: &SymbolMap) -> String {
+    let n = rom.len().min(ROM_SIZE);
+    let rom = &rom[..n];
+    let leader = leaders(rom, symbols);
+    let lengths = block_lengths(rom, &leader);
+
+    let mut s = String::from("/* Generated from a Hack program by hack2c. */\n");
+    s.push_str(HEADER);
+    s.push_str(&format!("\n#define ROM_SIZE {}\n#define PROGRAM_SIZE {}\n\n", ROM_SIZE, n.max(1)));
+    s.push_str("static const uint16_t rom[ROM_SIZE] = {");
+    push_table(&mut s, rom.iter().map(|w| *w as usize));
+    // a block fits before the limit when its length is at most what is left
+    s.push_str("static const uint32_t block_length[PROGRAM_SIZE] = {");
+    push_table(&mut s, lengths.iter().copied());
+    s.push_str(RUNTIME);
+
+    s.push_str("\nstatic void run(uint64_t limit) {\n    uint16_t address, target, out;\n");
+    s.push_str("dispatch:\n    if (pc >= PROGRAM_SIZE || !block_length[pc] || cycles + block_length[pc] > limit) goto interpret;\n");
+    s.push_str("    switch (pc) {\n");
+    for a in (0..n).filter(|a| leader[*a]) {
+        s.push_str(&format!("    case {}: goto L{};\n", a, a));
+    }
+    s.push_str("    default: goto interpret;\n    }\n");
+
+    for a in 0..n {
+        if leader[a] {
+            for label in symbols.labels_at(a as u16) {
+                s.push_str(&format!("/* ({}) */\n", label));
+            }
+            s.push_str(&format!("L{}:\n", a));
+            if Computer::is_halt_loop(rom, a) {
+                s.push_str(&format!("    pc = {}; return;\n", a));
+                continue;
+            }
+            if a > 0 && Computer::is_halt_loop(rom, a - 1) {
+                s.push_str(&format!("    if (a == {}) {{ pc = {}; return; }}\n", a - 1, a));
+            }
+            s.push_str(&format!("    if (cycles + {} > limit) {{ pc = {}; goto interpret; }}\n", lengths[a], a));
+            s.push_str(&format!("    cycles += {};\n", lengths[a]));
+        }
+        s.push_str(&format!("    /* {}: {} */\n", a, Disassembler::disassemble(rom[a])));
+        // a constant jump target, @X before the jump in the same block
+        let constant = if a > 0 && !leader[a] && rom[a - 1] & 0x8000 == 0 { Some(rom[a - 1]) } else { None };
+        s.push_str(&instruction(rom[a], constant, &leader));
+    }
+    s.push_str(&format!("    pc = {};\n    goto dispatch;\n", n));
+    s.push_str("interpret:\n");
+    s.push_str("    while (cycles < limit && !halted()) {\n");
+    s.push_str("        if (pc < PROGRAM_SIZE && block_length[pc] && cycles + block_length[pc] <= limit) goto dispatch;\n");
+    s.push_str("        step();\n    }\n}\n");
+    s.push_str(MAIN);
+    s
+}
+
+fn instruction(i: u16, constant: Option<u16>, leader: &[bool]) -> String {
+    if i & 0x8000 == 0 {
+        return format!("    a = {};\n", i);
+    }
+    let y = if i & 0x1000 != 0 { "RD(address)" } else { "a" };
+    let mut s = String::from("    address = a & (RAM_SIZE - 1);\n");
+    s.push_str(&format!("    out = {};\n", computation((i >> 6) & 0x3f, y)));
+    let jump = i & 0x07;
+    if jump != 0 {
+        s.push_str("    target = a;\n");
+    }
+    if i & 0x08 != 0 {
+        s.push_str("    WR(address, out);\n");
+    }
+    if i & 0x20 != 0 {
+        s.push_str("    a = out;\n");
+    }
+    if i & 0x10 != 0 {
+        s.push_str("    d = out;\n");
+    }
+    if jump != 0 {
+        let go = match constant {
+            Some(t) if (t as usize) < leader.len() && leader[t as usize] => format!("goto L{};", t),
+            _ => "pc = target; goto dispatch;".to_string(),
+        };
+        s.push_str(&format!("    if ({}) {{ {} }}\n", condition(jump), go));
+    }
+    s
+}
+
+// The rest of a C array initializer, 16 numbers a line.
+fn push_table<I: Iterator<Item = usize>>(s: &mut String, values: I) {
+    let values: Vec<String> = values.map(|v| v.to_string()).collect();
+    if values.is_empty() {
+        s.push('0');
+    }
+    for (i, line) in values.chunks(16).enumerate() {
+        s.push_str(if i == 0 { "\n    " } else { ",\n    " });
+        s.push_str(&line.join(", "));
+    }
+    s.push_str("\n};\n");
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use super::super::Computer::{parse_hack, Computer as Hack};
+
+    // Compiles the C for rom with the system's cc and runs it from a RAM
+    // of sets, or None without a C compiler.
+    fn run_c(name: &str, rom: &[u16], symbols: &SymbolMap, limit: u64, sets: &[(usize, u16)]) -> Option<Hack> {
+        let dir = std::env::temp_dir().join(format!("hack2c-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("prog.c");
+        let binary = dir.join("prog");
+        std::fs::write(&source, recompile(rom, symbols)).unwrap();
+        let compiled = std::process::Command::new("cc").arg("-O1").arg("-o").arg(&binary).arg(&source).status();
+        match compiled {
+            Ok(s) => assert!(s.success(), "cc failed on {}", source.display()),
+            Err(_) => {
+                eprintln!("no cc, skipped");
+                return None;
+            },
+        }
+        let mut args = vec![limit.to_string()];
+        args.extend(sets.iter().map(|(a, v)| format!("{}={}", a, v)));
+        let output = std::process::Command::new(&binary).args(&args).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let text = String::from_utf8(output.stdout).unwrap();
+        let mut lines = text.lines();
+        let mut c = Hack::new(rom);
+        for field in lines.next().unwrap().split_whitespace() {
+            let mut kv = field.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some("pc"), Some(v)) => c.pc = v.parse().unwrap(),
+                (Some("a"), Some(v)) => c.a = v.parse().unwrap(),
+                (Some("d"), Some(v)) => c.d = v.parse().unwrap(),
+                (Some("cycles"), Some(v)) => c.cycles = v.parse().unwrap(),
+                _ => {},
+            }
+        }
+        for line in lines {
+            let w: Vec<usize> = line.split(' ').map(|x| x.parse().unwrap()).collect();
+            c.ram[w[0]] = w[1] as u16;
+        }
+        Some(c)
+    }
+
+    fn compare(name: &str, rom: &[u16], symbols: &SymbolMap, limits: &[u64], sets: &[(usize, u16)]) {
+        for limit in limits {
+            let mut reference = Hack::new(rom);
+            for (a, v) in sets {
+                reference.ram[*a] = *v;
+            }
+            reference.run(*limit);
+            let c = match run_c(name, rom, symbols, *limit, sets) {
+                Some(c) => c,
+                None => return,
+            };
+            assert_eq!((c.pc, c.a, c.d, c.cycles), (reference.pc, reference.a, reference.d, reference.cycles), "{} at {}", name, limit);
+            assert!(c.ram == reference.ram, "{} at {}", name, limit);
+        }
+    }
+
+    fn load(path: &str) -> Vec<u16> {
+        parse_hack(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn programs() {
+        let none = SymbolMap::new();
+        compare("add", &load("../Add.hack"), &none, &[3, 100], &[]);
+        compare("max", &load("../Max.hack"), &none, &[4, 1000], &[(0, 3), (1, 8)]);
+        compare("max2", &load("../Max.hack"), &none, &[1000], &[(0, 9), (1, 65534)]);
+        compare("rect", &load("../Rect.hack"), &none, &[77, 100_000], &[(0, 5)]);
+    }
+
+    #[test]
+    fn vm_program() {
+        // the translation of 08/FunctionCalls/FibonacciElement, with and
+        // without its labels
+        let f = std::fs::File::open("../../08/FunctionCalls/FibonacciElement/FibonacciElement.asm").unwrap();
+        let assembly = assembler::assemble(f).unwrap();
+        let symbols = SymbolMap::parse(&assembly.symbol_map()).unwrap();
+        let rom = parse_hack(&assembly.hack_code.join("\n")).unwrap();
+        compare("fib", &rom, &symbols, &[1, 1234, 100_000], &[]);
+        compare("fib2", &rom, &SymbolMap::new(), &[999, 100_000], &[]);
+    }
+
+    #[test]
+    fn random_program() {
+        // any word is an instruction: every computation, destination and
+        // jump, and jumps into the middle of blocks
+        let mut seed: u32 = 777;
+        let mut next = || {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) as u16
+        };
+        let rom: Vec<u16> = (0..2048).map(|_| next()).collect();
+        let sets: Vec<(usize, u16)> = (0..64).map(|i| (i * 512 + 7, next())).collect();
+        compare("random", &rom, &SymbolMap::new(), &[20_000], &sets);
+    }
+}
@@ -0,0 +1,238 @@
+use super::Png;
+
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 256;
+// the screen memory map, 32 words per row
+pub const SCREEN: usize = 16384;
+pub const SCREEN_WORDS: usize = WIDTH * HEIGHT / 16;
+
+// A snapshot of the screen memory map. Pixel (x, y) is bit x % 16 of word
+// y * 32 + x / 16, so the least significant bit is leftmost.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Screen {
+    words: Vec<u16>,
+}
+
+// The pixels that differ between two screens.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ScreenDiff {
+    pub pixels: usize,
+    // (left, top, right, bottom), inclusive
+    pub bounds: Option<(usize, usize, usize, usize)>,
+    // black where the screens differ
+    pub image: Screen,
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Screen { words: vec![0; SCREEN_WORDS] }
+    }
+
+    // The screen part of a RAM image.
+    pub fn from_ram(ram: &[u16]) -> Self {
+        let mut s = Screen::new();
+        for (i, w) in ram.iter().skip(SCREEN).take(SCREEN_WORDS).enumerate() {
+            s.words[i] = *w;
+        }
+        s
+    }
+
+    pub fn words(&self) -> &[u16] {
+        &self.words
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.words[y * 32 + x / 16] & (1 << (x % 16)) != 0
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, black: bool) {
+        let w = &mut self.words[y * 32 + x / 16];
+        if black {
+            *w |= 1 << (x % 16);
+        }
+        else {
+            *w &= !(1 << (x % 16));
+        }
+    }
+
+    // Binary PBM (P4), 1 is black.
+    pub fn to_pbm(&self) -> Vec<u8> {
+        let mut data = format!("P4\n{} {}\n", WIDTH, HEIGHT).into_bytes();
+        for y in 0..HEIGHT {
+            for b in 0..WIDTH / 8 {
+                let mut byte = 0u8;
+                for i in 0..8 {
+                    if self.pixel(b * 8 + i, y) {
+                        byte |= 0x80 >> i;
+                    }
+                }
+                data.push(byte);
+            }
+        }
+        data
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        Png::encode(WIDTH, HEIGHT, &|x, y| self.pixel(x, y))
+    }
+
+    // Reads a PBM (P1 or P4) or PNG image of the screen's size.
+    pub fn from_image(data: &[u8]) -> Result<Self, String> {
+        let image = if data.starts_with(b"P1") || data.starts_with(b"P4") {
+            read_pbm(data)?
+        }
+        else {
+            Png::decode(data)?
+        };
+        if image.width != WIDTH || image.height != HEIGHT {
+            return Err(format!("the image is {}x{}, not {}x{}", image.width, image.height, WIDTH, HEIGHT));
+        }
+        let mut s = Screen::new();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                s.set_pixel(x, y, image.black[y * WIDTH + x]);
+            }
+        }
+        Ok(s)
+    }
+
+    // The image for a file name: PNG for .png, PBM otherwise.
+    pub fn to_image(&self, file_name: &str) -> Vec<u8> {
+        if file_name.to_ascii_lowercase().ends_with(".png") {
+            self.to_png()
+        }
+        else {
+            self.to_pbm()
+        }
+    }
+}
+
+pub fn diff(a: &Screen, b: &Screen) -> ScreenDiff {
+    let mut d = ScreenDiff { pixels: 0, bounds: None, image: Screen::new() };
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            if a.pixel(x, y) == b.pixel(x, y) {
+                continue;
+            }
+            d.pixels += 1;
+            d.image.set_pixel(x, y, true);
+            d.bounds = Some(match d.bounds {
+                Some((l, t, r, b)) => (l.min(x), t.min(y), r.max(x), b.max(y)),
+                None => (x, y, x, y),
+            });
+        }
+    }
+    d
+}
+
+fn read_pbm(data: &[u8]) -> Result<Png::Image, String> {
+    // the header is magic, width and height, separated by whitespace and
+    // # comments
+    let mut pos = 2;
+    let mut numbers = vec![];
+    while numbers.len() < 2 {
+        while pos < data.len() && (data[pos].is_ascii_whitespace() || data[pos] == b'#') {
+            if data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            else {
+                pos += 1;
+            }
+        }
+        let start = pos;
+        while pos < data.len() && data[pos].is_ascii_digit() {
+            pos += 1;
+        }
+        let n = std::str::from_utf8(&data[start..pos]).ok().and_then(|s| s.parse::<usize>().ok()).ok_or("bad PBM header")?;
+        numbers.push(n);
+    }
+    let (width, height) = (numbers[0], numbers[1]);
+    // before anything is sized by the header
+    if width != WIDTH || height != HEIGHT {
+        return Err(format!("the image is {}x{}, not {}x{}", width, height, WIDTH, HEIGHT));
+    }
+    let mut black = Vec::with_capacity(width * height);
+    if data[1] == b'4' {
+        // a single whitespace, then packed rows
+        pos += 1;
+        let row_bytes = (width + 7) / 8;
+        if data.len() < pos + row_bytes * height {
+            return Err("PBM data is too short".to_string());
+        }
+        for y in 0..height {
+            for x in 0..width {
+                black.push(data[pos + y * row_bytes + x / 8] & (0x80 >> (x % 8)) != 0);
+            }
+        }
+    }
+    else {
+        black.extend(data[pos..].iter().filter(|c| **c == b'0' || **c == b'1').map(|c| *c == b'1'));
+        if black.len() < width * height {
+            return Err("PBM data is too short".to_string());
+        }
+        black.truncate(width * height);
+    }
+    Ok(Png::Image { width: width, height: height, black: black })
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn pixel_layout() {
+        let mut ram = vec![0u16; 32768];
+        // the leftmost 2 pixels of the first row and the rightmost of the last
+        ram[SCREEN] = 0b11;
+        ram[SCREEN + SCREEN_WORDS - 1] = 0x8000;
+        let s = Screen::from_ram(&ram);
+        assert!(s.pixel(0, 0) && s.pixel(1, 0) && !s.pixel(2, 0));
+        assert!(s.pixel(511, 255) && !s.pixel(510, 255));
+
+        let pbm = s.to_pbm();
+        assert!(pbm.starts_with(b"P4\n512 256\n"));
+        assert_eq!(pbm[11], 0b1100_0000);
+        assert_eq!(*pbm.last().unwrap(), 0b0000_0001);
+    }
+
+    #[test]
+    fn image_round_trip() {
+        let mut s = Screen::new();
+        for i in 0..256 {
+            s.set_pixel(i * 2, i, true);
+        }
+        assert_eq!(Screen::from_image(&s.to_pbm()).unwrap(), s);
+        assert_eq!(Screen::from_image(&s.to_png()).unwrap(), s);
+
+        let mut p1 = "P1\n# a comment\n512 256\n".to_string();
+        for y in 0..HEIGHT {
+            let row: Vec<&str> = (0..WIDTH).map(|x| if s.pixel(x, y) { "1" } else { "0" }).collect();
+            p1.push_str(&row.join(" "));
+            p1.push('\n');
+        }
+        assert_eq!(Screen::from_image(p1.as_bytes()).unwrap(), s);
+        assert!(Screen::from_image(b"P4\n8 8\n\0\0\0\0\0\0\0\0").is_err());
+        // huge sizes are refused, not allocated
+        assert_eq!(Screen::from_image(b"P4\n4294967296 4294967296\n").unwrap_err(), "the image is 4294967296x4294967296, not 512x256");
+        assert!(Screen::from_image(b"P1\n18446744073709551615 2\n").is_err());
+    }
+
+    #[test]
+    fn screen_diff() {
+        let a = Screen::new();
+        let mut b = Screen::new();
+        assert_eq!(diff(&a, &b).pixels, 0);
+        b.set_pixel(10, 20, true);
+        b.set_pixel(300, 5, true);
+        let d = diff(&a, &b);
+        assert_eq!(d.pixels, 2);
+        assert_eq!(d.bounds, Some((10, 5, 300, 20)));
+        assert!(d.image.pixel(300, 5));
+    }
+}
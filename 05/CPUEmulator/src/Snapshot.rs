@@ -0,0 +1,159 @@
+use super::Computer::{Computer, RAM_SIZE};
+use super::Png;
+
+const MAGIC: &[u8; 8] = b"HACKSNAP";
+const VERSION: u8 = 1;
+// magic, version, ROM hash, A, D, PC, cycles
+const HEADER: usize = 8 + 1 + 4 + 2 + 2 + 2 + 8;
+
+// The state of a running Computer: RAM, registers and the cycle count,
+// with a hash of the ROM it ran, so that it is only restored onto the same
+// program.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Snapshot {
+    pub rom_hash: u32,
+    pub ram: Vec<u16>,
+    pub a: u16,
+    pub d: u16,
+    pub pc: u16,
+    pub cycles: u64,
+}
+
+// The CRC-32 of all of ROM, unused words included, so that the same program
+// hashes the same however it was loaded.
+pub fn rom_hash(computer: &Computer) -> u32 {
+    Png::crc32(&words_to_bytes(computer.rom()))
+}
+
+impl Snapshot {
+    pub fn take(computer: &Computer) -> Self {
+        Snapshot {
+            rom_hash: rom_hash(computer),
+            ram: computer.ram.clone(),
+            a: computer.a,
+            d: computer.d,
+            pc: computer.pc,
+            cycles: computer.cycles,
+        }
+    }
+
+    // Puts the state back, unless computer has a different ROM.
+    pub fn restore(&self, computer: &mut Computer) -> Result<(), String> {
+        let hash = rom_hash(computer);
+        if hash != self.rom_hash {
+            return Err(format!("the snapshot is of another program: ROM hash {:08x}, not {:08x}", self.rom_hash, hash));
+        }
+        computer.ram.copy_from_slice(&self.ram);
+        computer.a = self.a;
+        computer.d = self.d;
+        computer.pc = self.pc;
+        computer.cycles = self.cycles;
+        Ok(())
+    }
+
+    // The file format: the header, big-endian, then RAM as zlib compressed
+    // big-endian words, then the CRC-32 of everything before it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.rom_hash.to_be_bytes());
+        bytes.extend_from_slice(&self.a.to_be_bytes());
+        bytes.extend_from_slice(&self.d.to_be_bytes());
+        bytes.extend_from_slice(&self.pc.to_be_bytes());
+        bytes.extend_from_slice(&self.cycles.to_be_bytes());
+        bytes.extend_from_slice(&Png::zlib_compress(&words_to_bytes(&self.ram)));
+        let crc = Png::crc32(&bytes);
+        bytes.extend_from_slice(&crc.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() < HEADER + 4 || &data[..8] != MAGIC {
+            return Err("not a Hack snapshot".to_string());
+        }
+        if data[8] != VERSION {
+            return Err(format!("unknown snapshot version {}", data[8]));
+        }
+        let (body, crc) = data.split_at(data.len() - 4);
+        if Png::crc32(body) != u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+            return Err("the snapshot is corrupt".to_string());
+        }
+        let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+        let mut cycles = [0u8; 8];
+        cycles.copy_from_slice(&data[19..27]);
+        let ram = Png::zlib_decompress(&body[HEADER..])?;
+        if ram.len() != RAM_SIZE * 2 {
+            return Err(format!("the snapshot has {} bytes of RAM, not {}", ram.len(), RAM_SIZE * 2));
+        }
+        Ok(Snapshot {
+            rom_hash: u32::from_be_bytes([data[9], data[10], data[11], data[12]]),
+            ram: ram.chunks(2).map(|w| u16::from_be_bytes([w[0], w[1]])).collect(),
+            a: u16_at(13),
+            d: u16_at(15),
+            pc: u16_at(17),
+            cycles: u64::from_be_bytes(cycles),
+        })
+    }
+}
+
+fn words_to_bytes(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_be_bytes().to_vec()).collect()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use super::super::Computer::parse_hack;
+    use super::super::SymbolMap::SymbolMap;
+
+    #[test]
+    fn save_and_restore() {
+        let rom = parse_hack(&std::fs::read_to_string("../Max.hack").unwrap()).unwrap();
+        let mut c = Computer::new(&rom);
+        c.ram[0] = 3;
+        c.ram[1] = 8;
+        c.run(5);
+        let bytes = Snapshot::take(&c).to_bytes();
+        let s = Snapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(s, Snapshot::take(&c));
+
+        let mut restored = Computer::new(&rom);
+        s.restore(&mut restored).unwrap();
+        c.run(1000);
+        restored.run(1000);
+        assert_eq!((restored.ram[2], restored.cycles, restored.pc), (8, c.cycles, c.pc));
+
+        // not onto another program, or from a damaged file
+        let mut other = Computer::new(&parse_hack(&std::fs::read_to_string("../Add.hack").unwrap()).unwrap());
+        assert!(s.restore(&mut other).unwrap_err().starts_with("the snapshot is of another program"));
+        assert_eq!(other.cycles, 0);
+        let mut damaged = bytes.clone();
+        damaged[20] ^= 1;
+        assert_eq!(Snapshot::from_bytes(&damaged), Err("the snapshot is corrupt".to_string()));
+        assert!(Snapshot::from_bytes(&bytes[..20]).is_err());
+    }
+
+    #[test]
+    fn after_boot() {
+        // Pong with the Jack OS, from where Main.main starts
+        let f = std::fs::File::open("../../06/pong/Pong.asm").unwrap();
+        let assembly = assembler::assemble(f).unwrap();
+        let main = SymbolMap::parse(&assembly.symbol_map()).unwrap().rom_address("main.main").unwrap();
+        let rom = parse_hack(&assembly.hack_code.join("\n")).unwrap();
+        let mut booted = Computer::new(&rom);
+        while booted.pc != main {
+            booted.step();
+        }
+        let snapshot = Snapshot::from_bytes(&Snapshot::take(&booted).to_bytes()).unwrap();
+
+        let mut c = Computer::new(&rom);
+        snapshot.restore(&mut c).unwrap();
+        assert_eq!(c.cycles, booted.cycles);
+        booted.run(booted.cycles + 100_000);
+        c.run(c.cycles + 100_000);
+        assert!(c.ram == booted.ram);
+        assert_eq!((c.a, c.d, c.pc), (booted.a, booted.d, booted.pc));
+    }
+}
@@ -0,0 +1,113 @@
+// The symbols of a program, from the map the assembler writes with
+// --symbols: one "rom ADDRESS LABEL" or "ram ADDRESS VARIABLE" per line.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct SymbolMap {
+    // sorted by address
+    rom: Vec<(u16, String)>,
+    ram: Vec<(u16, String)>,
+}
+
+const PREDEFINED: [(&str, u16); 7] = [("SP", 0), ("LCL", 1), ("ARG", 2), ("THIS", 3), ("THAT", 4), ("SCREEN", 16384), ("KBD", 24576)];
+
+impl SymbolMap {
+    pub fn new() -> Self {
+        SymbolMap { rom: vec![], ram: vec![] }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut map = SymbolMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let address = fields.get(1).and_then(|a| a.parse::<u16>().ok());
+            match (fields[0], address, fields.get(2)) {
+                ("rom", Some(a), Some(name)) => map.rom.push((a, name.to_string())),
+                ("ram", Some(a), Some(name)) => map.ram.push((a, name.to_string())),
+                _ => return Err(format!("line {}: expected rom|ram ADDRESS NAME", i + 1)),
+            }
+        }
+        map.rom.sort();
+        map.ram.sort();
+        Ok(map)
+    }
+
+    // The address of a label, written LOOP or (LOOP).
+    pub fn rom_address(&self, name: &str) -> Option<u16> {
+        let name = name.trim_start_matches('(').trim_end_matches(')');
+        self.rom.iter().find(|(_, n)| n == name).map(|(a, _)| *a)
+    }
+
+    // The address of a variable or of a predefined symbol such as SP or R13.
+    pub fn ram_address(&self, name: &str) -> Option<u16> {
+        if let Some((_, a)) = PREDEFINED.iter().find(|(n, _)| *n == name) {
+            return Some(*a);
+        }
+        if let Some(r) = (0..16).find(|r| name == format!("R{}", r)) {
+            return Some(r);
+        }
+        self.ram.iter().find(|(_, n)| n == name).map(|(a, _)| *a)
+    }
+
+    pub fn labels_at(&self, address: u16) -> Vec<&str> {
+        self.rom.iter().filter(|(a, _)| *a == address).map(|(_, n)| n.as_str()).collect()
+    }
+
+    pub fn variables_at(&self, address: u16) -> Vec<&str> {
+        self.ram.iter().filter(|(a, _)| *a == address).map(|(_, n)| n.as_str()).collect()
+    }
+
+    // A name for a RAM word: a variable, or SP to THAT, SCREEN or KBD.
+    pub fn ram_name(&self, address: u16) -> Option<&str> {
+        if let Some(v) = self.variables_at(address).first() {
+            return Some(v);
+        }
+        PREDEFINED.iter().find(|(_, a)| *a == address).map(|(n, _)| *n)
+    }
+
+    // The nearest label at or before address, and the distance from it.
+    pub fn enclosing_label(&self, address: u16) -> Option<(&str, u16)> {
+        // the last of several labels at one address, which is the one
+        // closest to the code in the source
+        let i = self.rom.iter().rposition(|(a, _)| *a <= address)?;
+        let (a, name) = &self.rom[i];
+        Some((name.as_str(), address - a))
+    }
+
+    // LOOP or LOOP+3 for a ROM address, the number without labels.
+    pub fn location(&self, address: u16) -> String {
+        match self.enclosing_label(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{}", name, offset),
+            None => address.to_string(),
+        }
+    }
+
+    pub fn labels(&self) -> &[(u16, String)] {
+        &self.rom
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn parse_and_lookup() {
+        let map = SymbolMap::parse("rom 4 LOOP\r\nram 16 i\r\nrom 10 END\r\n").unwrap();
+        assert_eq!(map.rom_address("(LOOP)"), Some(4));
+        assert_eq!(map.rom_address("END"), Some(10));
+        assert_eq!(map.ram_address("i"), Some(16));
+        assert_eq!(map.ram_address("SP"), Some(0));
+        assert_eq!(map.ram_address("R13"), Some(13));
+        assert_eq!(map.ram_address("LOOP"), None);
+        assert_eq!(map.location(4), "LOOP");
+        assert_eq!(map.location(7), "LOOP+3");
+        assert_eq!(map.location(2), "2");
+        assert_eq!((map.ram_name(16), map.ram_name(3), map.ram_name(17)), (Some("i"), Some("THIS"), None));
+        assert!(SymbolMap::parse("rom x LOOP").is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::VecDeque;
+
+use super::Computer::{Computer, RAM_SIZE, ROM_SIZE};
+use super::Disassembler;
+use super::Snapshot;
+use super::SymbolMap::SymbolMap;
+
+const MAGIC: &[u8; 8] = b"HACKTRCE";
+const VERSION: u8 = 1;
+// magic, version, ROM hash, first cycle, entries
+const HEADER: usize = 8 + 1 + 4 + 8 + 4;
+// PC, A, D, written address, old and new value
+const ENTRY: usize = 12;
+
+// A RAM word an instruction wrote, with the value it had before.
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct RamWrite {
+    pub address: u16,
+    pub old: u16,
+    pub new: u16,
+}
+
+// One executed instruction: the registers before it ran and its write.
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Entry {
+    pub pc: u16,
+    pub a: u16,
+    pub d: u16,
+    pub write: Option<RamWrite>,
+}
+
+// The last instructions a Computer executed, at most capacity of them,
+// with enough to undo each one.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Trace {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+    // the cycle of the first entry
+    first: u64,
+}
+
+impl Trace {
+    pub fn new(capacity: usize) -> Self {
+        Trace { entries: VecDeque::new(), capacity: capacity.max(1), first: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // (cycle, entry), oldest first.
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = (u64, &Entry)> {
+        let first = self.first;
+        self.entries.iter().enumerate().map(move |(i, e)| (first + i as u64, e))
+    }
+
+    // Executes the instruction at PC and records it.
+    pub fn record(&mut self, computer: &mut Computer) {
+        // anything run in between breaks the trace
+        if self.first + self.entries.len() as u64 != computer.cycles {
+            self.entries.clear();
+        }
+        if self.entries.is_empty() {
+            self.first = computer.cycles;
+        }
+        let i = computer.rom()[computer.pc as usize & (ROM_SIZE - 1)];
+        let address = computer.a & (RAM_SIZE as u16 - 1);
+        // a C instruction with M in its destination
+        let writes = i & 0x8008 == 0x8008;
+        let old = computer.ram[address as usize];
+        let mut entry = Entry { pc: computer.pc, a: computer.a, d: computer.d, write: None };
+        computer.step();
+        if writes {
+            entry.write = Some(RamWrite { address: address, old: old, new: computer.ram[address as usize] });
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+            self.first += 1;
+        }
+        self.entries.push_back(entry);
+    }
+
+    // Runs like Computer::run, recording every instruction.
+    pub fn run(&mut self, computer: &mut Computer, limit: u64) {
+        while computer.cycles < limit && !computer.is_halted() {
+            self.record(computer);
+        }
+    }
+
+    // Undoes the last instruction, which is returned, or None at the start
+    // of the trace.
+    pub fn step_back(&mut self, computer: &mut Computer) -> Option<Entry> {
+        if self.first + self.entries.len() as u64 != computer.cycles {
+            return None;
+        }
+        let entry = self.entries.pop_back()?;
+        if let Some(w) = entry.write {
+            computer.ram[w.address as usize] = w.old;
+        }
+        computer.pc = entry.pc;
+        computer.a = entry.a;
+        computer.d = entry.d;
+        computer.cycles -= 1;
+        Some(entry)
+    }
+
+    // The last instruction in the trace that wrote address, and its cycle.
+    pub fn last_write(&self, address: u16) -> Option<(u64, Entry)> {
+        self.entries().rev()
+            .find(|(_, e)| e.write.map_or(false, |w| w.address == address))
+            .map(|(c, e)| (c, *e))
+    }
+
+    // Steps back to just before the last write of address, if it is in
+    // the trace, and returns it; otherwise nothing changes.
+    pub fn back_to_write(&mut self, computer: &mut Computer, address: u16) -> Option<(u64, Entry)> {
+        let (cycle, entry) = self.last_write(address)?;
+        while computer.cycles > cycle {
+            self.step_back(computer)?;
+        }
+        Some((cycle, entry))
+    }
+
+    // The file format: the header, big-endian, with the hash of the ROM
+    // the trace ran, then 12 bytes per entry.
+    pub fn to_bytes(&self, computer: &Computer) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&Snapshot::rom_hash(computer).to_be_bytes());
+        bytes.extend_from_slice(&self.first.to_be_bytes());
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for e in &self.entries {
+            // bit 15 of the address marks a write
+            let w = e.write.unwrap_or(RamWrite { address: 0, old: 0, new: 0 });
+            let address = if e.write.is_some() { w.address | 0x8000 } else { 0 };
+            for v in [e.pc, e.a, e.d, address, w.old, w.new].iter() {
+                bytes.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    // Reads a trace of the program computer runs.
+    pub fn from_bytes(data: &[u8], computer: &Computer) -> Result<Self, String> {
+        if data.len() < HEADER || &data[..8] != MAGIC {
+            return Err("not a Hack trace".to_string());
+        }
+        if data[8] != VERSION {
+            return Err(format!("unknown trace version {}", data[8]));
+        }
+        let hash = u32::from_be_bytes([data[9], data[10], data[11], data[12]]);
+        if hash != Snapshot::rom_hash(computer) {
+            return Err("the trace is of another program".to_string());
+        }
+        let mut first = [0u8; 8];
+        first.copy_from_slice(&data[13..21]);
+        let count = u32::from_be_bytes([data[21], data[22], data[23], data[24]]) as usize;
+        if data.len() != HEADER + count * ENTRY {
+            return Err(format!("the trace should have {} entries", count));
+        }
+        let mut trace = Trace::new(count);
+        trace.first = u64::from_be_bytes(first);
+        for chunk in data[HEADER..].chunks(ENTRY) {
+            let v = |i: usize| u16::from_be_bytes([chunk[2 * i], chunk[2 * i + 1]]);
+            let write = if v(3) & 0x8000 != 0 { Some(RamWrite { address: v(3) & 0x7fff, old: v(4), new: v(5) }) } else { None };
+            trace.entries.push_back(Entry { pc: v(0), a: v(1), d: v(2), write: write });
+        }
+        Ok(trace)
+    }
+
+    // One line per entry, from the cycle from on:
+    //     1234  ball.new+3      145: M=D          A=258 D=7  RAM[258] 0 -> 7
+    pub fn view(&self, rom: &[u16], symbols: &SymbolMap, from: u64) -> String {
+        let mut s = String::new();
+        for (cycle, e) in self.entries().filter(|(c, _)| *c >= from) {
+            s.push_str(&view_entry(rom, symbols, cycle, e));
+            s.push('\n');
+        }
+        s
+    }
+}
+
+pub fn view_entry(rom: &[u16], symbols: &SymbolMap, cycle: u64, e: &Entry) -> String {
+    let pc = e.pc as usize & (ROM_SIZE - 1);
+    let instruction = format!("{}: {}", pc, Disassembler::disassemble_at(rom, pc, symbols));
+    let mut line = format!("{:>10}  {:<24} {:<32} A={} D={}", cycle, symbols.location(e.pc), instruction, e.a, e.d as i16);
+    if let Some(w) = e.write {
+        line.push_str(&format!("  {} {} -> {}", ram_location(symbols, w.address), w.old as i16, w.new as i16));
+    }
+    line
+}
+
+// RAM[258], or RAM[3] (THIS) for a named word
+pub fn ram_location(symbols: &SymbolMap, address: u16) -> String {
+    match symbols.ram_name(address) {
+        Some(n) => format!("RAM[{}] ({})", address, n),
+        None => format!("RAM[{}]", address),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use super::super::Computer::parse_hack;
+
+    fn fibonacci() -> (Computer, SymbolMap) {
+        let f = std::fs::File::open("../../08/FunctionCalls/FibonacciElement/FibonacciElement.asm").unwrap();
+        let assembly = assembler::assemble(f).unwrap();
+        let symbols = SymbolMap::parse(&assembly.symbol_map()).unwrap();
+        (Computer::new(&parse_hack(&assembly.hack_code.join("\n")).unwrap()), symbols)
+    }
+
+    #[test]
+    fn reverse() {
+        let (mut c, _) = fibonacci();
+        let start = c.clone();
+        let mut t = Trace::new(1_000_000);
+        t.run(&mut c, 100_000);
+        assert!(c.is_halted());
+        assert_eq!(t.len() as u64, c.cycles);
+
+        // back to the start, by way of the last write of SP
+        let end = c.clone();
+        let (cycle, e) = t.back_to_write(&mut c, 0).unwrap();
+        assert_eq!(c.cycles, cycle);
+        assert_eq!(c.ram[0], e.write.unwrap().old);
+        while t.step_back(&mut c).is_some() {}
+        assert!(c.ram == start.ram);
+        assert_eq!((c.pc, c.a, c.d, c.cycles), (start.pc, start.a, start.d, 0));
+        // and forward again
+        t.run(&mut c, 100_000);
+        assert!(c.ram == end.ram);
+        assert_eq!((c.pc, c.a, c.d, c.cycles), (end.pc, end.a, end.d, end.cycles));
+    }
+
+    #[test]
+    fn ring_and_file() {
+        let (mut c, symbols) = fibonacci();
+        let mut t = Trace::new(100);
+        t.run(&mut c, 1000);
+        assert_eq!(t.len(), 100);
+        assert_eq!(t.entries().next().unwrap().0, 900);
+        let read = Trace::from_bytes(&t.to_bytes(&c), &c).unwrap();
+        assert!(read.entries().eq(t.entries()));
+        assert!(Trace::from_bytes(&t.to_bytes(&c), &Computer::new(&[])).is_err());
+
+        // only as far back as the trace goes
+        for _ in 0..100 {
+            t.step_back(&mut c).unwrap();
+        }
+        assert_eq!((t.step_back(&mut c), c.cycles), (None, 900));
+
+        // the bootstrap sets SP to 256 first
+        let (mut c, _) = fibonacci();
+        let mut t = Trace::new(10);
+        t.run(&mut c, 4);
+        let (cycle, _) = t.last_write(0).unwrap();
+        assert_eq!(cycle, 3);
+        let view = t.view(c.rom(), &symbols, 0);
+        assert_eq!(view.lines().count(), 4);
+        assert!(view.lines().nth(3).unwrap().ends_with("RAM[0] (SP) 0 -> 256"), "{}", view);
+    }
+}
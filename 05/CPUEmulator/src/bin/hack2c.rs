@@ -0,0 +1,41 @@
+use std::fs;
+use std::env;
+use std::process;
+
+use CPUEmulator::Computer;
+use CPUEmulator::SymbolMap::SymbolMap;
+use CPUEmulator::Recompiler;
+
+// hack2c Prog.hack [Prog.sym] [--out Prog.c]
+// Translates a program into a single C file, Prog.c by default, which runs
+// it natively: cc -O2 Prog.c -o prog && ./prog CYCLES [ADDRESS=VALUE]...
+// The labels of the assembler's --symbols map start blocks and annotate
+// the C. Define HACK_HOOKS to provide the keyboard and screen.
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let value_of = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let program = match args.iter().skip(1).find(|a| a.ends_with(".hack")) {
+        Some(p) => p,
+        None => {
+            println!("not enough arguments");
+            return Ok(());
+        }
+    };
+    let rom = Computer::parse_hack(&fs::read_to_string(program)?).unwrap_or_else(|e| fail(&format!("{}: {}", program, e)));
+    let symbols = match args.iter().skip(1).find(|a| a.ends_with(".sym")) {
+        Some(p) => SymbolMap::parse(&fs::read_to_string(p)?).unwrap_or_else(|e| fail(&format!("{}: {}", p, e))),
+        None => SymbolMap::new(),
+    };
+    let out = match value_of("--out") {
+        Some(o) => o.clone(),
+        None => format!("{}.c", program.trim_end_matches(".hack")),
+    };
+    fs::write(&out, Recompiler::recompile(&rom, &symbols))?;
+    println!("wrote {}", out);
+    Ok(())
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
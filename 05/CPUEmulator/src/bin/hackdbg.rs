@@ -0,0 +1,52 @@
+use std::fs;
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use CPUEmulator::Computer::{self, Computer as Hack};
+use CPUEmulator::SymbolMap::SymbolMap;
+use CPUEmulator::Debugger::Debugger;
+
+// hackdbg Prog.hack [Prog.sym]
+// Debugs a program one command per line, e.g. "break (Main.main)",
+// "watch SP < 256", "continue". Prog.sym is the map written by
+// assembler --symbols.
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        println!("not enough arguments");
+        return Ok(());
+    }
+    let rom = Computer::parse_hack(&fs::read_to_string(&args[1])?).unwrap_or_else(|e| fail(&format!("{}: {}", args[1], e)));
+    let symbols = match args.get(2) {
+        Some(p) => SymbolMap::parse(&fs::read_to_string(p)?).unwrap_or_else(|e| fail(&format!("{}: {}", p, e))),
+        None => SymbolMap::new(),
+    };
+    let mut debugger = Debugger::new(Hack::new(&rom), symbols);
+    println!("{}", debugger.status());
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(hackdbg) ");
+        io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(l) => l?,
+            None => break,
+        };
+        let line = line.trim();
+        if line == "quit" || line == "q" {
+            break;
+        }
+        let out = debugger.execute(line);
+        if !out.is_empty() {
+            println!("{}", out);
+        }
+    }
+    Ok(())
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use std::env;
+use std::process;
+
+use CPUEmulator::Computer::{self, Computer as Hack};
+use CPUEmulator::SymbolMap::SymbolMap;
+use CPUEmulator::Trace::{ram_location, view_entry, Trace};
+
+// hacktrace Prog.hack Prog.trace [Prog.sym] [--last N] [--writes ADDRESS]
+// Shows a trace written by CPUEmulator --trace, one instruction per line
+// with the labels and variables of the assembler's --symbols map. --last
+// shows only the last N instructions, --writes only those that wrote a RAM
+// word, given by number or name.
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let value_of = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    // the arguments that are not options or their values
+    let files: Vec<&String> = args.iter().enumerate().skip(1)
+        .filter(|(i, a)| !a.starts_with("--") && !args[i - 1].starts_with("--"))
+        .map(|(_, a)| a)
+        .collect();
+    let (program, trace_file) = match (files.get(0), files.get(1)) {
+        (Some(p), Some(t)) => (p, t),
+        _ => {
+            println!("not enough arguments");
+            return Ok(());
+        }
+    };
+    let rom = Computer::parse_hack(&fs::read_to_string(program)?).unwrap_or_else(|e| fail(&format!("{}: {}", program, e)));
+    let computer = Hack::new(&rom);
+    let trace = Trace::from_bytes(&fs::read(trace_file)?, &computer).unwrap_or_else(|e| fail(&format!("{}: {}", trace_file, e)));
+    let symbols = match files.get(2) {
+        Some(p) => SymbolMap::parse(&fs::read_to_string(p)?).unwrap_or_else(|e| fail(&format!("{}: {}", p, e))),
+        _ => SymbolMap::new(),
+    };
+
+    let last = match value_of("--last").map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) => n,
+        Some(Err(_)) => fail("--last takes a number"),
+        None => trace.len(),
+    };
+    let writes = value_of("--writes").map(|a| match a.parse::<u16>() {
+        Ok(n) => n,
+        Err(_) => symbols.ram_address(a).unwrap_or_else(|| fail(&format!("no variable {}", a))),
+    });
+    let entries: Vec<_> = trace.entries()
+        .filter(|(_, e)| writes.map_or(true, |a| e.write.map_or(false, |w| w.address == a)))
+        .collect();
+    if let (Some(a), true) = (writes, entries.is_empty()) {
+        println!("{} was not written in the trace", ram_location(&symbols, a));
+    }
+    for (cycle, e) in entries.iter().skip(entries.len().saturating_sub(last)) {
+        println!("{}", view_entry(computer.rom(), &symbols, *cycle, e));
+    }
+    Ok(())
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
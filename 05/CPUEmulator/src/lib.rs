@@ -0,0 +1,13 @@
+pub mod Computer;
+pub mod Screen;
+pub mod Png;
+pub mod SymbolMap;
+pub mod Disassembler;
+pub mod Debugger;
+pub mod Profiler;
+pub mod Engine;
+pub mod Keyboard;
+pub mod Snapshot;
+pub mod GdbStub;
+pub mod Trace;
+pub mod Recompiler;
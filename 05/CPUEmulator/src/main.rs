@@ -0,0 +1,283 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::env;
+use std::io::{self, Read};
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use CPUEmulator::Computer::{self, Computer as Hack};
+use CPUEmulator::Screen;
+use CPUEmulator::SymbolMap::SymbolMap;
+use CPUEmulator::Profiler::Profiler;
+use CPUEmulator::Engine::Engine;
+use CPUEmulator::Keyboard::{self, Recorder, Timeline};
+use CPUEmulator::Snapshot::Snapshot;
+use CPUEmulator::GdbStub::{listen, GdbStub};
+use CPUEmulator::Trace::Trace;
+
+// CPUEmulator Prog.hack [--cycles N] [--set ADDR=VALUE]... [--screen OUT]
+//                       [--load SNAPSHOT] [--save SNAPSHOT] [--gdb PORT]
+//                       [--trace OUT [--trace-size N]]
+//                       [--expect GOLDEN [--diff OUT]]
+//                       [--symbols Prog.sym] [--profile REPORT] [--folded OUT]
+//                       [--engine blocks|step]
+//                       [--keys TIMELINE |
+//                        --record TIMELINE [--hold N] [--speed N]]
+// Runs a program headless until it halts or N cycles have run, then writes
+// or checks the screen. Images are PNG for .png files and PBM otherwise.
+// --load starts from a snapshot of the same program, e.g. one saved after
+// the OS has booted, and --save writes one at the end. --gdb waits for gdb,
+// or another remote protocol client, on a loopback port and runs the
+// program as it says instead. --trace writes the last N instructions run,
+// a million by default, for hacktrace to show.
+// --profile and --folded count the instructions by label, VM function and
+// call path, with the labels of the assembler's --symbols map. The blocks
+// engine is the fast default; step runs one instruction at a time.
+// --keys replays the keystrokes of a timeline file. --record runs the
+// program at --speed cycles per second with the keys typed in the terminal,
+// each held for --hold cycles, and writes them as a timeline.
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let value_of = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1));
+    let program = match args.iter().skip(1).find(|a| a.ends_with(".hack")) {
+        Some(p) => p,
+        None => {
+            println!("not enough arguments");
+            return Ok(());
+        }
+    };
+    let number_of = |name: &str, default: u64| match value_of(name).map(|c| c.parse::<u64>()) {
+        Some(Ok(c)) => c,
+        Some(Err(_)) => fail(&format!("{} takes a number", name)),
+        None => default,
+    };
+    let record = value_of("--record");
+    // a live session runs until it is ended
+    let cycles = number_of("--cycles", if record.is_some() { u64::MAX } else { 1_000_000 });
+
+    let rom = Computer::parse_hack(&fs::read_to_string(program)?).unwrap_or_else(|e| fail(&format!("{}: {}", program, e)));
+    let mut computer = Hack::new(&rom);
+    if let Some(p) = value_of("--load") {
+        let snapshot = Snapshot::from_bytes(&fs::read(p)?).unwrap_or_else(|e| fail(&format!("{}: {}", p, e)));
+        snapshot.restore(&mut computer).unwrap_or_else(|e| fail(&format!("{}: {}", p, e)));
+    }
+    // N more cycles after a snapshot
+    let cycles = computer.cycles.saturating_add(cycles);
+    for (i, a) in args.iter().enumerate() {
+        if a != "--set" {
+            continue;
+        }
+        let assignment = args.get(i + 1).map(|s| s.as_str()).unwrap_or("");
+        match parse_assignment(assignment) {
+            Some((address, value)) => computer.ram[address] = value,
+            None => fail(&format!("--set takes ADDRESS=VALUE, not {}", assignment)),
+        }
+    }
+
+    let engine = value_of("--engine").map_or("blocks", |e| e.as_str());
+    if engine != "blocks" && engine != "step" {
+        fail(&format!("unknown --engine: {}", engine));
+    }
+    let timeline = match value_of("--keys") {
+        Some(p) => Timeline::parse(&fs::read_to_string(p)?).unwrap_or_else(|e| fail(&format!("{}: {}", p, e))),
+        None => Timeline::new(),
+    };
+    let profiling = value_of("--profile").is_some() || value_of("--folded").is_some();
+    let symbols = match value_of("--symbols") {
+        Some(p) if profiling => SymbolMap::parse(&fs::read_to_string(p)?).unwrap_or_else(|e| fail(&format!("{}: {}", p, e))),
+        _ => SymbolMap::new(),
+    };
+    let mut profiler = Profiler::new(&symbols);
+    let tracing = value_of("--trace").is_some();
+    let mut trace = Trace::new(number_of("--trace-size", 1_000_000) as usize);
+    let mut blocks = Engine::new();
+    let mut run = |c: &mut Hack, limit: u64| {
+        if profiling || tracing {
+            while c.cycles < limit && !c.is_halted() {
+                if profiling {
+                    profiler.record(c);
+                }
+                if tracing {
+                    trace.record(c);
+                }
+                else {
+                    c.step();
+                }
+            }
+        }
+        else if engine == "step" {
+            c.run(limit);
+        }
+        else {
+            blocks.run(c, limit);
+        }
+    };
+
+    let first = computer.cycles;
+    let start = Instant::now();
+    match record {
+        _ if value_of("--gdb").is_some() => {
+            let port = value_of("--gdb").unwrap().parse::<u16>().unwrap_or_else(|_| fail("--gdb takes a port number"));
+            let mut stub = GdbStub::new(computer);
+            listen(&mut stub, port, |port| println!("waiting for gdb on 127.0.0.1:{}", port))?;
+            computer = stub.computer;
+        },
+        Some(out) => {
+            if value_of("--keys").is_some() {
+                fail("--record cannot be used with --keys");
+            }
+            let mut recorder = Recorder::new(number_of("--hold", 2_000_000));
+            let screen_out = value_of("--screen");
+            record_session(&mut computer, cycles, number_of("--speed", 10_000_000), &mut recorder, screen_out, &mut run);
+            fs::write(out, recorder.timeline.to_text())?;
+        },
+        None => timeline.replay(&mut computer, cycles, &mut run),
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    println!("{} cycles, {}, {:.1} MIPS", computer.cycles, if computer.is_halted() { "halted" } else { "running" },
+        (computer.cycles - first) as f64 / seconds.max(1e-9) / 1e6);
+
+    if let Some(out) = value_of("--trace") {
+        fs::write(out, trace.to_bytes(&computer))?;
+    }
+    if let Some(out) = value_of("--save") {
+        fs::write(out, Snapshot::take(&computer).to_bytes())?;
+    }
+    if profiling {
+        if let Some(out) = value_of("--profile") {
+            fs::write(out, profiler.report(&symbols))?;
+        }
+        if let Some(out) = value_of("--folded") {
+            fs::write(out, profiler.folded())?;
+        }
+    }
+
+    let screen = computer.screen();
+    if let Some(out) = value_of("--screen") {
+        fs::write(out, screen.to_image(out))?;
+    }
+    if let Some(golden) = value_of("--expect") {
+        let expected = Screen::Screen::from_image(&fs::read(golden)?).unwrap_or_else(|e| fail(&format!("{}: {}", golden, e)));
+        let d = Screen::diff(&expected, &screen);
+        if let Some(out) = value_of("--diff") {
+            fs::write(out, d.image.to_image(out))?;
+        }
+        if let Some((l, t, r, b)) = d.bounds {
+            fail(&format!("the screen differs from {} in {} pixels, within ({}, {})-({}, {})", golden, d.pixels, l, t, r, b));
+        }
+        println!("the screen matches {}", golden);
+    }
+    Ok(())
+}
+
+// Runs in real time with the keys typed on stdin until limit, the program
+// halts, or ^C, ^D or the end of input. The screen is written twice a
+// second to follow the session.
+fn record_session<F: FnMut(&mut Hack, u64)>(computer: &mut Hack, limit: u64, speed: u64, recorder: &mut Recorder, screen_out: Option<&String>, run: &mut F) {
+    // keys as they are typed rather than by line
+    let saved = terminal(&["-g"]);
+    if saved.is_some() {
+        terminal(&["-icanon", "-echo", "-isig", "min", "1"]);
+    }
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0u8; 64];
+        loop {
+            match io::stdin().read(&mut buffer) {
+                Ok(n) if n > 0 => {
+                    if sender.send(buffer[..n].to_vec()).is_err() {
+                        break;
+                    }
+                },
+                _ => break,
+            }
+        }
+    });
+
+    // keys that come together, e.g. pasted, are pressed one after another
+    let mut pending = VecDeque::new();
+    let start = Instant::now();
+    let mut last_screen = Instant::now();
+    'session: while computer.cycles < limit && !computer.is_halted() {
+        loop {
+            match receiver.try_recv() {
+                Ok(bytes) => {
+                    if bytes.contains(&3) || bytes.contains(&4) {
+                        break 'session;
+                    }
+                    pending.extend(Keyboard::decode_terminal(&bytes));
+                },
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) if pending.is_empty() && recorder.release_at().is_none() => break 'session,
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            }
+        }
+        if recorder.release_at().is_none() {
+            if let Some(key) = pending.pop_front() {
+                recorder.press(computer, key, &mut *run);
+            }
+        }
+        let target = (start.elapsed().as_secs_f64() * speed as f64) as u64;
+        let mut until = target.min(limit);
+        if let Some(r) = recorder.release_at() {
+            until = until.min(r);
+        }
+        if until > computer.cycles {
+            run(computer, until);
+        }
+        recorder.update(computer);
+        if let Some(out) = screen_out {
+            if last_screen.elapsed() >= Duration::from_millis(500) {
+                let _ = fs::write(out, computer.screen().to_image(out));
+                last_screen = Instant::now();
+            }
+        }
+        if computer.cycles >= target {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+    // the key held at the end is let go
+    recorder.update(computer);
+    if let Some(saved) = saved {
+        terminal(&[saved.trim()]);
+    }
+}
+
+// Runs stty on the terminal, None when stdin is not one.
+fn terminal(args: &[&str]) -> Option<String> {
+    let output = process::Command::new("stty").args(args).stdin(process::Stdio::inherit()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// 0=256 or 0x4000=-1
+fn parse_assignment(s: &str) -> Option<(usize, u16)> {
+    let mut parts = s.splitn(2, '=');
+    let address = parse_number(parts.next()?)?;
+    let value = parse_number(parts.next()?)?;
+    if address < 0 || address as usize >= Computer::RAM_SIZE {
+        return None;
+    }
+    Some((address as usize, value as u16))
+}
+
+fn parse_number(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let n = match s.strip_prefix("0x") {
+        Some(h) => i64::from_str_radix(h, 16).ok()?,
+        None => s.parse::<i64>().ok()?,
+    };
+    if !(-32768..=65535).contains(&n) {
+        return None;
+    }
+    Some(n)
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
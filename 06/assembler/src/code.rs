@@ -1,4 +1,10 @@
-
+// Every mnemonic of each field, for disassembling.
+pub const DESTS: [&str; 8] = ["", "M", "D", "MD", "A", "AM", "AD", "AMD"];
+pub const COMPS: [&str; 28] = [
+    "0", "1", "-1", "D", "A", "!D", "!A", "-D", "-A", "D+1", "A+1", "D-1", "A-1", "D+A", "D-A", "A-D", "D&A", "D|A",
+    "M", "!M", "-M", "M+1", "M-1", "D+M", "D-M", "M-D", "D&M", "D|M",
+];
+pub const JUMPS: [&str; 8] = ["", "JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
 
 pub fn dest(mnemonic : &str) -> Option<[char; 3]> {
     match mnemonic {
@@ -0,0 +1,170 @@
+use std::io::{self, SeekFrom};
+
+pub mod parser;
+pub mod code;
+pub mod symboltable;
+
+// An assembled program: one 16 character binary word per instruction and
+// the symbols it defines.
+pub struct Assembly {
+    pub hack_code: Vec<String>,
+    pub symbol_table: symboltable::SymbolTable,
+    // the labels, known after the 1st pass
+    pub labels: Vec<(String, i32)>,
+    // the commands that could not be assembled
+    pub errors: Vec<String>,
+}
+
+impl Assembly {
+    // "rom ADDRESS LABEL" and "ram ADDRESS VARIABLE" lines for the debugger,
+    // without the predefined symbols.
+    pub fn symbol_map(&self) -> String {
+        let mut map = String::new();
+        for (sym, addr) in self.symbol_table.entries() {
+            if symboltable::is_predefined(&sym) {
+                continue;
+            }
+            let kind = if self.labels.iter().any(|(l, _)| *l == sym) { "rom" } else { "ram" };
+            map.push_str(&format!("{} {} {}\r\n", kind, addr, sym));
+        }
+        map
+    }
+}
+
+pub fn assemble<R: io::Read + io::Seek>(mut f: R) -> io::Result<Assembly> {
+    let mut p1 = parser::Parser::new(&mut f);
+    let mut symbol_table = symboltable::SymbolTable::new();
+    let mut addr = 0;
+    let mut errors = vec![];
+
+    // Defined Symbols
+    symbol_table.addEntry("SP".to_string(), 0);
+    symbol_table.addEntry("LCL".to_string(), 1);
+    symbol_table.addEntry("ARG".to_string(), 2);
+    symbol_table.addEntry("THIS".to_string(), 3);
+    symbol_table.addEntry("THAT".to_string(), 4);
+    symbol_table.addEntry("R0".to_string(), 0);
+    symbol_table.addEntry("R1".to_string(), 1);
+    symbol_table.addEntry("R2".to_string(), 2);
+    symbol_table.addEntry("R3".to_string(), 3);
+    symbol_table.addEntry("R4".to_string(), 4);
+    symbol_table.addEntry("R5".to_string(), 5);
+    symbol_table.addEntry("R6".to_string(), 6);
+    symbol_table.addEntry("R7".to_string(), 7);
+    symbol_table.addEntry("R8".to_string(), 8);
+    symbol_table.addEntry("R9".to_string(), 9);
+    symbol_table.addEntry("R10".to_string(), 10);
+    symbol_table.addEntry("R11".to_string(), 11);
+    symbol_table.addEntry("R12".to_string(), 12);
+    symbol_table.addEntry("R13".to_string(), 13);
+    symbol_table.addEntry("R14".to_string(), 14);
+    symbol_table.addEntry("R15".to_string(), 15);
+    symbol_table.addEntry("SCREEN".to_string(), 16384);
+    symbol_table.addEntry("KBD".to_string(), 24576);
+
+    while p1.hasMoreComments() {
+        p1.advance();
+        match p1.commandType() {
+            Some(parser::CommandType::A_Command) => { addr += 1; }
+            Some(parser::CommandType::C_Command) => { addr += 1; }
+            Some(parser::CommandType::L_Command) => {
+                let sym = p1.symbol();
+                if !symbol_table.contains(sym) {
+                    symbol_table.addEntry(sym.to_string(), addr);
+                }
+            }
+            None => {
+                errors.push("Unknown command.".to_string());
+                continue;
+            }
+        }
+    }
+
+    let labels = symbol_table.entries();
+
+    f.seek(SeekFrom::Start(0))?;
+    let mut p2 = parser::Parser::new(&mut f);
+    let mut hack_code = vec![];
+    let mut v_addr = 16;
+
+    while p2.hasMoreComments() {
+        p2.advance();
+        let mut command = String::new();
+        match p2.commandType() {
+            Some(parser::CommandType::A_Command) => {
+                let sym = p2.symbol();
+                let num_res = sym.parse::<i32>();
+                if num_res.is_err() {
+                    // Symbol
+                    if symbol_table.contains(sym) {
+                        // Label or Defined Variable
+                        command = format!("{:016b}", symbol_table.getAddress(sym));
+                    }
+                    else {
+                        // New Variable
+                        command = format!("{:016b}", v_addr);
+                        symbol_table.addEntry(sym.to_string(), v_addr);
+                        v_addr += 1;
+                    }
+                }
+                else {
+                    let bin = format!("{:016b}", num_res.unwrap());
+                    command = bin;
+                }
+            }
+            Some(parser::CommandType::C_Command) => {
+                command = "111".to_string();
+                let comp = code::comp(p2.comp());
+                if comp.is_none() {
+                    errors.push("Unknown comp command.".to_string());
+                    continue;
+                }
+                let compc : String = comp.unwrap().into_iter().collect();
+                command.push_str(&compc);
+
+                let dest = code::dest(p2.dest());
+                if dest.is_none() {
+                    errors.push("Unknown dest command.".to_string());
+                    continue;
+                }
+                let destc : String = dest.unwrap().into_iter().collect();
+                command.push_str(&destc);
+
+                let jump = code::jump(p2.jump());
+                if jump.is_none() {
+                    errors.push("Unknown jump command.".to_string());
+                    continue;
+                }
+                let jumpc : String = jump.unwrap().into_iter().collect();
+                command.push_str(&jumpc);
+            }
+            Some(parser::CommandType::L_Command) => {}
+            None => {
+                errors.push("Unknown command.".to_string());
+                continue;
+            }
+        }
+        if !command.is_empty() {
+            hack_code.push(command);
+        }
+    }
+
+    Ok(Assembly { hack_code: hack_code, symbol_table: symbol_table, labels: labels, errors: errors })
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn assemble_program() {
+        let source = "// count down\r\n@3\r\nD=A\r\n(LOOP)\r\n@i\r\nM=D\r\nD=D-1\r\n@LOOP\r\nD;JGT\r\n";
+        let a = assemble(io::Cursor::new(source)).unwrap();
+        assert_eq!(a.hack_code, vec![
+            "0000000000000011", "1110110000010000",
+            "0000000000010000", "1110001100001000", "1110001110010000",
+            "0000000000000010", "1110001100000001"]);
+        assert_eq!(a.symbol_map(), "rom 2 LOOP\r\nram 16 i\r\n");
+        assert!(a.errors.is_empty());
+    }
+}
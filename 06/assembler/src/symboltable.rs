@@ -17,4 +17,34 @@ impl SymbolTable {
     pub fn getAddress(&self, symbol: &str) -> i32 {
         *self.table.get(symbol).unwrap_or(&0)
     }
-}
\ No newline at end of file
+    // (symbol, address) sorted by address, then by symbol.
+    pub fn entries(&self) -> Vec<(String, i32)> {
+        let mut entries: Vec<(String, i32)> = self.table.iter().map(|(s, a)| (s.clone(), *a)).collect();
+        entries.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        entries
+    }
+}
+
+// SP, LCL, ..., R0-R15, SCREEN and KBD.
+pub fn is_predefined(symbol: &str) -> bool {
+    match symbol {
+        "SP" | "LCL" | "ARG" | "THIS" | "THAT" | "SCREEN" | "KBD" => true,
+        _ => (0..16).any(|n| symbol == format!("R{}", n)),
+    }
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn entries() {
+        let mut t = SymbolTable::new();
+        t.addEntry("LOOP".to_string(), 4);
+        t.addEntry("R1".to_string(), 1);
+        t.addEntry("i".to_string(), 16);
+        t.addEntry("END".to_string(), 4);
+        assert_eq!(t.entries(), vec![("R1".to_string(), 1), ("END".to_string(), 4), ("LOOP".to_string(), 4), ("i".to_string(), 16)]);
+        assert!(is_predefined("R15") && is_predefined("KBD"));
+        assert!(!is_predefined("R16") && !is_predefined("LOOP"));
+    }
+}
@@ -0,0 +1,63 @@
+use std::fs;
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path;
+use std::process;
+
+use VMtranslator::machine::{Machine, Program};
+use VMtranslator::debugger::Debugger;
+
+// vmdbg Prog.vm | Dir
+// Debugs a VM program one command per line, e.g. "break Main.main",
+// "continue", "backtrace", "frame 1". A directory is loaded like the
+// translator does, Sys.vm first.
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        println!("not enough arguments");
+        return Ok(());
+    }
+    let input = path::Path::new(&args[1]);
+    let mut vm_files = if input.is_dir() {
+        input.read_dir()?
+            .filter_map(|d| d.ok())
+            .map(|d| d.path())
+            .filter(|p| p.extension().map_or(false, |e| e == "vm"))
+            .collect::<Vec<path::PathBuf>>()
+    }
+    else {
+        vec![input.to_path_buf()]
+    };
+    vm_files.sort_by_key(|p| (!p.ends_with("Sys.vm"), p.clone()));
+
+    let mut program = Program::new();
+    for p in &vm_files {
+        let f_name = p.file_name().unwrap().to_string_lossy().to_string();
+        if let Err(e) = program.add_file(&f_name, fs::File::open(p)?) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+    let mut debugger = Debugger::new(Machine::new(program));
+    println!("{}", debugger.status());
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(vmdbg) ");
+        io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(l) => l?,
+            None => break,
+        };
+        let line = line.trim();
+        if line == "quit" || line == "q" {
+            break;
+        }
+        let out = debugger.execute(line);
+        if !out.is_empty() {
+            println!("{}", out);
+        }
+    }
+    Ok(())
+}
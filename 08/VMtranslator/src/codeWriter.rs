@@ -1,4 +1,5 @@
 use super::parser;
+use std::collections::HashSet;
 use std::io::{self, BufWriter, Write};
 
 pub struct CodeWriter<W: io::Write> {
@@ -6,6 +7,8 @@ pub struct CodeWriter<W: io::Write> {
     fileName_wo_ext: String,
     index_jmp: i32,
     index_call: i32,
+    function_name: String,
+    labels: HashSet<String>,
 }
 
 impl<W: io::Write> CodeWriter<W> {
@@ -15,6 +18,8 @@ impl<W: io::Write> CodeWriter<W> {
             fileName_wo_ext: String::from(""),
             index_jmp: 0,
             index_call: 0,
+            function_name: String::from(""),
+            labels: HashSet::new(),
         }
     }
     pub fn setFileName(&mut self, file_name: &str) {
@@ -193,16 +198,37 @@ impl<W: io::Write> CodeWriter<W> {
         }
     }
 
-    pub fn writeLabel(&mut self, label: &str) {
-        let asm = format!("({})\r\n", label);
+    // Labels are scoped to the function they appear in: functionName$label.
+    // Outside of any function the label is kept as it is.
+    fn mangle_label(&self, label: &str) -> String {
+        if self.function_name.is_empty() {
+            label.to_string()
+        }
+        else {
+            format!("{}${}", self.function_name, label)
+        }
+    }
+
+    pub fn writeLabel(&mut self, label: &str) -> Result<(), String> {
+        // '$' is left for the labels the translator makes up
+        if label.contains('$') {
+            return Err(format!("label {} cannot contain '$'", label));
+        }
+        let mangled = self.mangle_label(label);
+        if !self.labels.insert(mangled.clone()) {
+            return Err(format!("label {} is already defined in {}", label,
+                if self.function_name.is_empty() { &self.fileName_wo_ext } else { &self.function_name }));
+        }
+        let asm = format!("({})\r\n", mangled);
         self.os.write(asm.as_bytes());
+        Ok(())
     }
 
     pub fn writeGoto(&mut self, label: &str) {
         let asm = format!("\
         @{}\r\n\
         0;JMP\r\n\
-        ", label);
+        ", self.mangle_label(label));
         self.os.write(asm.as_bytes());
     }
 
@@ -215,21 +241,26 @@ impl<W: io::Write> CodeWriter<W> {
         D=M\r\n\
         @{}\r\n\
         D;JNE\r\n\
-        ", label);
+        ", self.mangle_label(label));
         self.os.write(asm.as_bytes());
     }
 
     pub fn writeFunction(&mut self, f_name: &str, num_locals: i32) {
+        self.function_name = f_name.to_string();
+        self.labels.clear();
+
+        // the locals initialization loop: VM labels cannot contain '$',
+        // so Foo.bar$$LOOP_START cannot clash with a label of the function
         let asm = format!("\
         ({0})\r\n\
         @{1}\r\n\
         D=A\r\n\
         @14\r\n\
         M=D\r\n\
-        ({0}$LOOP_START)\r\n\
+        ({0}$$LOOP_START)\r\n\
         @14\r\n\
         D=M\r\n\
-        @{0}$LOOP_END\r\n\
+        @{0}$$LOOP_END\r\n\
         D;JEQ\r\n\
         @0\r\n\
         D=A\r\n\
@@ -240,9 +271,9 @@ impl<W: io::Write> CodeWriter<W> {
         M=M+1\r\n\
         @14\r\n\
         M=M-1\r\n\
-        @{0}$LOOP_START\r\n\
+        @{0}$$LOOP_START\r\n\
         0;JMP\r\n\
-        ({0}$LOOP_END)\r\n\
+        ({0}$$LOOP_END)\r\n\
         ", f_name, num_locals);
         self.os.write(asm.as_bytes());
     }
@@ -1076,6 +1107,58 @@ mod tests{
         assert_eq!(String::from_utf8(cw.os.buffer().to_vec()).unwrap(), c);
     }
 
+    #[test]
+    fn label_in_function() {
+        let s = io::Cursor::new(Vec::new());
+        let mut cw = CodeWriter::new(s);
+        cw.writeFunction("Main.main", 0);
+        let start = cw.os.buffer().len();
+        assert_eq!(cw.writeLabel("WHILE_EXP0"), Ok(()));
+        cw.writeGoto("WHILE_EXP0");
+        cw.writeIf("WHILE_EXP0");
+
+        let c = "\
+        (Main.main$WHILE_EXP0)\r\n\
+        @Main.main$WHILE_EXP0\r\n\
+        0;JMP\r\n\
+        @SP\r\n\
+        M=M-1\r\n\
+        @SP\r\n\
+        A=M\r\n\
+        D=M\r\n\
+        @Main.main$WHILE_EXP0\r\n\
+        D;JNE\r\n\
+        ";
+        assert_eq!(String::from_utf8(cw.os.buffer()[start..].to_vec()).unwrap(), c);
+
+        // the same label in another function is fine
+        cw.writeFunction("Main.sub", 0);
+        assert_eq!(cw.writeLabel("WHILE_EXP0"), Ok(()));
+    }
+
+    #[test]
+    fn duplicate_label() {
+        let s = io::Cursor::new(Vec::new());
+        let mut cw = CodeWriter::new(s);
+        cw.writeFunction("Main.main", 0);
+        assert_eq!(cw.writeLabel("IF_TRUE0"), Ok(()));
+        assert_eq!(cw.writeLabel("IF_TRUE0"), Err("label IF_TRUE0 is already defined in Main.main".to_string()));
+    }
+
+    #[test]
+    fn locals_loop_labels() {
+        // the labels of the locals loop are not reserved
+        let s = io::Cursor::new(Vec::new());
+        let mut cw = CodeWriter::new(s);
+        cw.writeFunction("Foo.bar", 0);
+        assert_eq!(cw.writeLabel("LOOP_START"), Ok(()));
+        assert_eq!(cw.writeLabel("LOOP_END"), Ok(()));
+        let asm = String::from_utf8(cw.os.buffer().to_vec()).unwrap();
+        assert!(asm.contains("(Foo.bar$$LOOP_START)\r\n"));
+        assert!(asm.contains("(Foo.bar$LOOP_START)\r\n"));
+        assert_eq!(cw.writeLabel("$LOOP_START"), Err("label $LOOP_START cannot contain '$'".to_string()));
+    }
+
     #[test]
     fn function_0() {
         let s = io::Cursor::new(Vec::new());
@@ -1088,10 +1171,10 @@ mod tests{
         D=A\r\n\
         @14\r\n\
         M=D\r\n\
-        (SimpleFunction$LOOP_START)\r\n\
+        (SimpleFunction$$LOOP_START)\r\n\
         @14\r\n\
         D=M\r\n\
-        @SimpleFunction$LOOP_END\r\n\
+        @SimpleFunction$$LOOP_END\r\n\
         D;JEQ\r\n\
         @0\r\n\
         D=A\r\n\
@@ -1102,9 +1185,9 @@ mod tests{
         M=M+1\r\n\
         @14\r\n\
         M=M-1\r\n\
-        @SimpleFunction$LOOP_START\r\n\
+        @SimpleFunction$$LOOP_START\r\n\
         0;JMP\r\n\
-        (SimpleFunction$LOOP_END)\r\n\
+        (SimpleFunction$$LOOP_END)\r\n\
         ";
         assert_eq!(String::from_utf8(cw.os.buffer().to_vec()).unwrap(), c);
     }
@@ -1122,10 +1205,10 @@ mod tests{
         D=A\r\n\
         @14\r\n\
         M=D\r\n\
-        (SimpleFunction$LOOP_START)\r\n\
+        (SimpleFunction$$LOOP_START)\r\n\
         @14\r\n\
         D=M\r\n\
-        @SimpleFunction$LOOP_END\r\n\
+        @SimpleFunction$$LOOP_END\r\n\
         D;JEQ\r\n\
         @0\r\n\
         D=A\r\n\
@@ -1136,9 +1219,9 @@ mod tests{
         M=M+1\r\n\
         @14\r\n\
         M=M-1\r\n\
-        @SimpleFunction$LOOP_START\r\n\
+        @SimpleFunction$$LOOP_START\r\n\
         0;JMP\r\n\
-        (SimpleFunction$LOOP_END)\r\n\
+        (SimpleFunction$$LOOP_END)\r\n\
         ";
         assert_eq!(String::from_utf8(cw.os.buffer().to_vec()).unwrap(), c);
     }
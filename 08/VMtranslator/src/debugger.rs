@@ -0,0 +1,330 @@
+use super::machine::{self, Frame, Machine, RAM_SIZE};
+
+// continue stops after this many commands without a break
+const RUN_LIMIT: u64 = 10_000_000;
+
+// A line-mode debugger for a VM program. execute() runs one command and
+// returns what to print.
+pub struct Debugger {
+    pub machine: Machine,
+    // by number and name, with the command each one stops at
+    breakpoints: Vec<(usize, String, usize)>,
+    // the number of the next breakpoint; numbers are never reused
+    next_breakpoint: usize,
+}
+
+const HELP: &str = "\
+break NAME        break at a function, e.g. Main.main, or a label, e.g.
+                  Main.main$WHILE_EXP0 or WHILE_EXP0 if it is unique
+delete [N]        delete breakpoint N, or all of them
+info              list breakpoints
+step [N]          execute N VM commands
+next              step over a call
+finish            run until the current function returns
+continue          run to a breakpoint or the end
+backtrace         show the call stack
+frame [N]         show the segments of frame N, 0 by default
+stack             show the operand stack of the current function
+list              show the commands around the current one
+x ADDRESS [N]     show N RAM words
+quit";
+
+impl Debugger {
+    pub fn new(machine: Machine) -> Self {
+        Debugger { machine: machine, breakpoints: vec![], next_breakpoint: 1 }
+    }
+
+    pub fn execute(&mut self, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            return String::new();
+        }
+        let r = match words[0] {
+            "break" | "b" => self.add_breakpoint(&words[1..]),
+            "delete" | "d" => self.delete(&words[1..]),
+            "info" | "i" => Ok(self.info()),
+            "step" | "s" => self.step(&words[1..]),
+            "next" | "n" => Ok(self.next()),
+            "finish" => Ok(self.finish()),
+            "continue" | "c" => Ok(self.run(|_| false)),
+            "backtrace" | "bt" => Ok(self.backtrace()),
+            "frame" | "f" => self.frame(&words[1..]),
+            "stack" => Ok(self.stack_line(&self.machine.frames()[0])),
+            "list" | "l" => Ok(self.list()),
+            "x" => self.examine(&words[1..]),
+            "help" | "h" => Ok(HELP.to_string()),
+            w => Err(format!("unknown command: {}", w)),
+        };
+        r.unwrap_or_else(|e| e)
+    }
+
+    fn add_breakpoint(&mut self, args: &[&str]) -> Result<String, String> {
+        let name = args.get(0).ok_or("break takes a function or a label")?;
+        let pc = match self.machine.program.function(name) {
+            Some(f) => f.start,
+            None => self.machine.program.label(name)?,
+        };
+        let n = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.breakpoints.push((n, name.to_string(), pc));
+        Ok(format!("breakpoint {} at {}", n, self.location(pc)))
+    }
+
+    fn delete(&mut self, args: &[&str]) -> Result<String, String> {
+        match args.get(0) {
+            Some(n) => {
+                let n = n.parse::<usize>().map_err(|_| format!("not a number: {}", n))?;
+                let i = self.breakpoints.iter().position(|(b, _, _)| *b == n).ok_or(format!("no breakpoint {}", n))?;
+                self.breakpoints.remove(i);
+                Ok(format!("deleted {}", n))
+            },
+            None => {
+                self.breakpoints.clear();
+                Ok("deleted all breakpoints".to_string())
+            },
+        }
+    }
+
+    fn info(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return "no breakpoints".to_string();
+        }
+        let lines: Vec<String> = self.breakpoints.iter()
+            .map(|(n, name, pc)| format!("{}: {} at {}", n, name, self.location(*pc)))
+            .collect();
+        lines.join("\n")
+    }
+
+    fn step(&mut self, args: &[&str]) -> Result<String, String> {
+        let n = match args.get(0) {
+            Some(n) => n.parse::<u64>().map_err(|_| format!("not a number: {}", n))?,
+            None => 1,
+        };
+        for _ in 0..n {
+            if self.machine.is_halted() {
+                return Ok(format!("halted\n{}", self.status()));
+            }
+            if let Err(e) = self.machine.step() {
+                return Ok(format!("error: {}\n{}", e, self.status()));
+            }
+        }
+        Ok(self.status())
+    }
+
+    // Runs a call to its return, or steps any other command.
+    fn next(&mut self) -> String {
+        let depth = self.machine.depth;
+        let is_call = self.machine.current().map_or(false, |c| c.command_type == super::parser::CommandType::C_CALL);
+        if !is_call {
+            return self.run(|_| true);
+        }
+        self.run(move |m| m.depth <= depth)
+    }
+
+    fn finish(&mut self) -> String {
+        let depth = self.machine.depth;
+        self.run(move |m| m.depth < depth)
+    }
+
+    // Runs until done says so, a breakpoint or the end. The command at pc
+    // runs first, so that continue leaves a breakpoint.
+    fn run<F: Fn(&Machine) -> bool>(&mut self, done: F) -> String {
+        let start = self.machine.steps;
+        let stop = loop {
+            if self.machine.is_halted() {
+                break Some("halted".to_string());
+            }
+            if let Err(e) = self.machine.step() {
+                break Some(format!("error: {}", e));
+            }
+            if done(&self.machine) {
+                break None;
+            }
+            let pc = self.machine.pc;
+            if let Some((n, name, _)) = self.breakpoints.iter().find(|(_, _, b)| *b == pc) {
+                break Some(format!("breakpoint {}, {}", n, name));
+            }
+            if self.machine.steps - start >= RUN_LIMIT {
+                break Some(format!("stopped after {} commands", RUN_LIMIT));
+            }
+        };
+        match stop {
+            Some(s) => format!("{}\n{}", s, self.status()),
+            None => self.status(),
+        }
+    }
+
+    // Sys.add12 at Sys.vm:35: push argument 0
+    // stack: 123
+    pub fn status(&self) -> String {
+        let frames = self.machine.frames();
+        format!("{}\n{}", self.location(self.machine.pc), self.stack_line(&frames[0]))
+    }
+
+    fn location(&self, pc: usize) -> String {
+        match self.machine.program.commands.get(pc) {
+            Some(c) => {
+                let function = if c.function.is_empty() { String::new() } else { format!("{} at ", c.function) };
+                format!("{}{}.vm:{}: {}", function, c.file, c.line, c.text())
+            },
+            None => "at the end of the program".to_string(),
+        }
+    }
+
+    fn words(&self, address: u16, n: u16) -> String {
+        let values: Vec<String> = (address as usize..(address as usize + n as usize).min(RAM_SIZE))
+            .map(|a| (self.machine.ram[a] as i16).to_string())
+            .collect();
+        values.join(" ")
+    }
+
+    // The operand stack of a frame, the most recent push last.
+    fn stack_line(&self, frame: &Frame) -> String {
+        let base = if frame.function.is_empty() { machine::STACK as u16 } else { frame.lcl.wrapping_add(frame.num_locals) };
+        if frame.sp < base || frame.sp as usize > machine::HEAP {
+            return format!("stack: SP={} is below the frame at {}", frame.sp, base);
+        }
+        format!("stack: {}", self.words(base, frame.sp - base)).trim_end().to_string()
+    }
+
+    fn backtrace(&self) -> String {
+        let lines: Vec<String> = self.machine.frames().iter().enumerate()
+            .map(|(i, f)| format!("#{} {}", i, self.location(f.pc)))
+            .collect();
+        lines.join("\n")
+    }
+
+    // The header of a frame and its segments: all of argument and local,
+    // and the words of this and that the function uses.
+    fn frame(&self, args: &[&str]) -> Result<String, String> {
+        let n = match args.get(0) {
+            Some(n) => n.parse::<usize>().map_err(|_| format!("not a number: {}", n))?,
+            None => 0,
+        };
+        let frames = self.machine.frames();
+        let f = frames.get(n).ok_or_else(|| format!("no frame {}", n))?;
+        let (this_words, that_words) = match self.machine.program.function(&f.function) {
+            Some(function) => (function.this_words, function.that_words),
+            None => (0, 0),
+        };
+        let segment = |name: &str, address: u16, n: u16| format!("  {} @{}: {}", name, address, self.words(address, n)).trim_end().to_string();
+        let mut lines = vec![format!("#{} {}", n, self.location(f.pc))];
+        lines.push(segment("argument", f.arg, f.num_args));
+        lines.push(segment("local", f.lcl, f.num_locals));
+        lines.push(segment("this", f.this, this_words));
+        lines.push(segment("that", f.that, that_words));
+        lines.push(format!("  {}", self.stack_line(f)));
+        Ok(lines.join("\n"))
+    }
+
+    // 5 commands either side, the current one marked with >.
+    fn list(&self) -> String {
+        let pc = self.machine.pc;
+        let first = pc.saturating_sub(5);
+        let lines: Vec<String> = self.machine.program.commands.iter().enumerate().skip(first).take(pc + 6 - first)
+            .map(|(i, c)| format!("{} {:>4}  {}", if i == pc { ">" } else { " " }, c.line, c.text()))
+            .collect();
+        lines.join("\n")
+    }
+
+    // ADDRESS is a number or a static, File.index.
+    fn examine(&self, args: &[&str]) -> Result<String, String> {
+        let name = args.get(0).ok_or("x takes an address")?;
+        let address = match name.parse::<u16>() {
+            Ok(a) if (a as usize) < RAM_SIZE => a,
+            _ => self.machine.program.static_address(name).ok_or_else(|| format!("no static {}", name))?,
+        };
+        let n = match args.get(1) {
+            Some(n) => n.parse::<u16>().map_err(|_| format!("not a number: {}", n))?,
+            None => 1,
+        };
+        let lines: Vec<String> = (address as usize..(address as usize + n as usize).min(RAM_SIZE))
+            .map(|a| {
+                let v = self.machine.ram[a] as i16;
+                match self.machine.program.static_name(a as u16) {
+                    Some(s) => format!("{}: {} ({})", a, v, s),
+                    None => format!("{}: {}", a, v),
+                }
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use super::super::machine::Program;
+
+    fn nested_call() -> Debugger {
+        let mut program = Program::new();
+        program.add_file("Sys.vm", std::fs::File::open("../FunctionCalls/NestedCall/Sys.vm").unwrap()).unwrap();
+        Debugger::new(Machine::new(program))
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut d = nested_call();
+        assert_eq!(d.execute("break Sys.add12"), "breakpoint 1 at Sys.add12 at Sys.vm:55: function Sys.add12 0");
+        assert_eq!(d.execute("break LOOP"), "breakpoint 2 at Sys.init at Sys.vm:15: label LOOP");
+        assert!(d.execute("break Sys.none").starts_with("no function or label"));
+        assert_eq!(d.execute("c"), "breakpoint 1, Sys.add12\nSys.add12 at Sys.vm:55: function Sys.add12 0\nstack:");
+        let s = d.execute("finish");
+        assert!(s.starts_with("Sys.main at Sys.vm:39: pop temp 0\nstack: 135"), "{}", s);
+        assert!(d.execute("continue").starts_with("breakpoint 2, LOOP"));
+        assert!(d.execute("c").starts_with("halted"));
+        assert_eq!(d.execute("x 6"), "6: 246");
+    }
+
+    #[test]
+    fn breakpoint_numbers() {
+        // deleting a breakpoint leaves the others' numbers alone
+        let mut d = nested_call();
+        d.execute("break Sys.main");
+        d.execute("break Sys.add12");
+        d.execute("break LOOP");
+        assert_eq!(d.execute("delete 1"), "deleted 1");
+        assert_eq!(d.execute("delete 1"), "no breakpoint 1");
+        let info = d.execute("info");
+        assert!(info.starts_with("2: Sys.add12 at ") && info.contains("\n3: LOOP at "), "{}", info);
+        assert!(d.execute("c").starts_with("breakpoint 2, Sys.add12"));
+        assert_eq!(d.execute("delete 2"), "deleted 2");
+        assert!(d.execute("c").starts_with("breakpoint 3, LOOP"));
+        d.execute("delete");
+        assert!(d.execute("break Sys.main").starts_with("breakpoint 4 at "));
+    }
+
+    #[test]
+    fn call_stack() {
+        let mut d = nested_call();
+        d.execute("break Sys.add12");
+        d.execute("c");
+        d.execute("step 5");
+        assert_eq!(d.execute("bt"), "\
+#0 Sys.add12 at Sys.vm:60: push argument 0
+#1 Sys.main at Sys.vm:38: call Sys.add12 1
+#2 Sys.init at Sys.vm:13: call Sys.main 0");
+        assert_eq!(d.execute("frame 1"), "\
+#1 Sys.main at Sys.vm:38: call Sys.add12 1
+  argument @261:
+  local @266: 0 200 40 6 0
+  this @4001:
+  that @5001:
+  stack:");
+        assert_eq!(d.execute("frame"), "\
+#0 Sys.add12 at Sys.vm:60: push argument 0
+  argument @271: 123
+  local @277:
+  this @4002:
+  that @5002:
+  stack:");
+        // a call is stepped over, anything else stepped into
+        d.execute("delete");
+        let mut d2 = nested_call();
+        d2.execute("break Sys.main");
+        d2.execute("c");
+        d2.execute("step 12");
+        assert!(d2.execute("list").contains(">   38  call Sys.add12 1"));
+        assert!(d2.execute("next").starts_with("Sys.main at Sys.vm:39: pop temp 0"));
+    }
+}
@@ -0,0 +1,4 @@
+pub mod parser;
+pub mod codeWriter;
+pub mod machine;
+pub mod debugger;
@@ -0,0 +1,523 @@
+use super::parser::{self, CommandType};
+use std::collections::HashMap;
+use std::io;
+
+// The registers and memory map of the translated program.
+pub const SP: usize = 0;
+pub const LCL: usize = 1;
+pub const ARG: usize = 2;
+pub const THIS: usize = 3;
+pub const THAT: usize = 4;
+pub const TEMP: usize = 5;
+pub const STATIC: usize = 16;
+pub const STACK: usize = 256;
+pub const HEAP: usize = 2048;
+pub const RAM_SIZE: usize = 32768;
+// the return address of the frame of Sys.init
+pub const BOOTSTRAP_RETURN: u16 = 0xffff;
+
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Command {
+    pub command_type: CommandType,
+    pub arg1: String,
+    pub arg2: i32,
+    pub file: String,
+    pub line: usize,
+    // the function the command is in, empty outside of functions
+    pub function: String,
+}
+
+impl Command {
+    // The command as it is written in the .vm file.
+    pub fn text(&self) -> String {
+        match self.command_type {
+            CommandType::C_ARITHMETIC => self.arg1.clone(),
+            CommandType::C_PUSH => format!("push {} {}", self.arg1, self.arg2),
+            CommandType::C_POP => format!("pop {} {}", self.arg1, self.arg2),
+            CommandType::C_LABEL => format!("label {}", self.arg1),
+            CommandType::C_GOTO => format!("goto {}", self.arg1),
+            CommandType::C_IF => format!("if-goto {}", self.arg1),
+            CommandType::C_FUNCTION => format!("function {} {}", self.arg1, self.arg2),
+            CommandType::C_CALL => format!("call {} {}", self.arg1, self.arg2),
+            CommandType::C_RETURN => "return".to_string(),
+        }
+    }
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub start: usize,
+    pub num_locals: u16,
+    // the this and that words the function uses, one more than the
+    // highest index
+    pub this_words: u16,
+    pub that_words: u16,
+}
+
+// The commands of all .vm files of a program, with the addresses the
+// translator would give to functions, labels and statics.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Program {
+    pub commands: Vec<Command>,
+    pub functions: Vec<Function>,
+    function_index: HashMap<String, usize>,
+    // scoped as the translator does, functionName$label
+    labels: HashMap<String, usize>,
+    // File.index
+    statics: HashMap<String, u16>,
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Program { commands: vec![], functions: vec![], function_index: HashMap::new(), labels: HashMap::new(), statics: HashMap::new() }
+    }
+
+    // Adds the commands of one file. The translator puts Sys.vm first, and
+    // statics get their addresses in the order they appear.
+    pub fn add_file<R: io::Read>(&mut self, file_name: &str, reader: R) -> Result<(), String> {
+        let file = std::path::Path::new(file_name).file_stem().map_or(String::new(), |s| s.to_string_lossy().to_string());
+        let mut p = parser::Parser::new(reader);
+        let mut function = String::new();
+        while p.hasMoreCommands() {
+            p.advance();
+            let command_type = match p.commandType() {
+                Some(t) => t,
+                None => continue,
+            };
+            let at = format!("{}.vm:{}", file, p.lineNumber());
+            match command_type {
+                CommandType::C_FUNCTION => {
+                    function = p.arg1().to_string();
+                    if self.function_index.contains_key(&function) {
+                        return Err(format!("{}: function {} is already defined", at, function));
+                    }
+                    self.function_index.insert(function.clone(), self.functions.len());
+                    self.functions.push(Function { name: function.clone(), start: self.commands.len(), num_locals: p.arg2() as u16, this_words: 0, that_words: 0 });
+                },
+                CommandType::C_LABEL => {
+                    let label = scoped_label(&function, p.arg1());
+                    if self.labels.insert(label, self.commands.len()).is_some() {
+                        return Err(format!("{}: label {} is already defined", at, p.arg1()));
+                    }
+                },
+                CommandType::C_PUSH | CommandType::C_POP => {
+                    let words = p.arg2() as u16 + 1;
+                    match (p.arg1(), self.functions.last_mut()) {
+                        ("static", _) => {
+                            let name = format!("{}.{}", file, p.arg2());
+                            let address = (STATIC + self.statics.len()) as u16;
+                            let address = *self.statics.entry(name).or_insert(address);
+                            if address as usize >= STACK {
+                                return Err(format!("{}: too many static variables", at));
+                            }
+                        },
+                        ("this", Some(f)) => f.this_words = f.this_words.max(words),
+                        ("that", Some(f)) => f.that_words = f.that_words.max(words),
+                        _ => {},
+                    }
+                },
+                _ => {},
+            }
+            self.commands.push(Command {
+                command_type: command_type,
+                arg1: p.arg1().to_string(),
+                arg2: p.arg2(),
+                file: file.clone(),
+                line: p.lineNumber(),
+                function: function.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.function_index.get(name).map(|i| &self.functions[*i])
+    }
+
+    // The function the command at pc is in.
+    pub fn function_at(&self, pc: usize) -> Option<&Function> {
+        self.commands.get(pc).and_then(|c| self.function(&c.function))
+    }
+
+    // A label by its scoped name, Main.main$LOOP, or by its own name if
+    // only one function has it.
+    pub fn label(&self, name: &str) -> Result<usize, String> {
+        if let Some(i) = self.labels.get(name) {
+            return Ok(*i);
+        }
+        let suffix = format!("${}", name);
+        let found: Vec<(&String, &usize)> = self.labels.iter().filter(|(l, _)| l.ends_with(&suffix)).collect();
+        match found.len() {
+            0 => Err(format!("no function or label {}", name)),
+            1 => Ok(*found[0].1),
+            _ => {
+                let mut names: Vec<&str> = found.iter().map(|(l, _)| l.as_str()).collect();
+                names.sort();
+                Err(format!("label {} is in several functions: {}", name, names.join(", ")))
+            },
+        }
+    }
+
+    pub fn static_address(&self, name: &str) -> Option<u16> {
+        self.statics.get(name).cloned()
+    }
+
+    // File.index for a static address.
+    pub fn static_name(&self, address: u16) -> Option<&str> {
+        self.statics.iter().find(|(_, a)| **a == address).map(|(n, _)| n.as_str())
+    }
+}
+
+fn scoped_label(function: &str, label: &str) -> String {
+    if function.is_empty() {
+        label.to_string()
+    }
+    else {
+        format!("{}${}", function, label)
+    }
+}
+
+// A function call on the stack, from the frame writeCall lays down.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Frame {
+    pub function: String,
+    // the current command, or the call in the callers
+    pub pc: usize,
+    pub lcl: u16,
+    pub arg: u16,
+    pub this: u16,
+    pub that: u16,
+    pub num_args: u16,
+    pub num_locals: u16,
+    // the end of the frame's operand stack
+    pub sp: u16,
+}
+
+// Runs a Program on the memory layout of the translated code: the stack at
+// 256, a call leaves the return address, LCL, ARG, THIS and THAT below the
+// callee's locals. A return address is the index of the command after the
+// call.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Machine {
+    pub program: Program,
+    pub ram: Vec<u16>,
+    pub pc: usize,
+    pub steps: u64,
+    // the number of calls that have not returned
+    pub depth: usize,
+}
+
+impl Machine {
+    // Calls Sys.init as the bootstrap code does, or starts at the first
+    // command of a program without it.
+    pub fn new(program: Program) -> Self {
+        let mut m = Machine { program: program, ram: vec![0; RAM_SIZE], pc: 0, steps: 0, depth: 0 };
+        m.ram[SP] = STACK as u16;
+        if let Some(start) = m.program.function("Sys.init").map(|f| f.start) {
+            for v in [BOOTSTRAP_RETURN, 0, 0, 0, 0].iter() {
+                let _ = m.push(*v);
+            }
+            m.ram[ARG] = STACK as u16;
+            m.ram[LCL] = m.ram[SP];
+            m.pc = start;
+            m.depth = 1;
+        }
+        m
+    }
+
+    pub fn current(&self) -> Option<&Command> {
+        self.program.commands.get(self.pc)
+    }
+
+    // At the end of the commands, in a goto to itself, e.g. label END,
+    // goto END, or in Sys.halt of the OS.
+    pub fn is_halted(&self) -> bool {
+        let c = match self.current() {
+            Some(c) => c,
+            None => return true,
+        };
+        if c.function == "Sys.halt" {
+            return true;
+        }
+        if c.command_type != CommandType::C_GOTO {
+            return false;
+        }
+        match self.program.label(&scoped_label(&c.function, &c.arg1)) {
+            Ok(target) => target <= self.pc && self.program.commands[target..self.pc].iter().all(|c| c.command_type == CommandType::C_LABEL),
+            Err(_) => false,
+        }
+    }
+
+    // Executes the command at pc.
+    pub fn step(&mut self) -> Result<(), String> {
+        let c = match self.program.commands.get(self.pc) {
+            Some(c) => c.clone(),
+            None => return Err("the program has ended".to_string()),
+        };
+        let mut next = self.pc + 1;
+        match c.command_type {
+            CommandType::C_ARITHMETIC => {
+                let y = self.pop()?;
+                let value = match c.arg1.as_str() {
+                    "neg" => y.wrapping_neg(),
+                    "not" => !y,
+                    op => {
+                        let x = self.pop()?;
+                        let truth = |b: bool| if b { 0xffff } else { 0 };
+                        match op {
+                            "add" => x.wrapping_add(y),
+                            "sub" => x.wrapping_sub(y),
+                            "and" => x & y,
+                            "or" => x | y,
+                            "eq" => truth(x == y),
+                            "gt" => truth((x as i16) > (y as i16)),
+                            _ => truth((x as i16) < (y as i16)),
+                        }
+                    },
+                };
+                self.push(value)?;
+            },
+            CommandType::C_PUSH => {
+                let value = if c.arg1 == "constant" {
+                    c.arg2 as u16
+                }
+                else {
+                    self.ram[self.segment_address(&c)?]
+                };
+                self.push(value)?;
+            },
+            CommandType::C_POP => {
+                let address = self.segment_address(&c)?;
+                self.ram[address] = self.pop()?;
+            },
+            CommandType::C_LABEL => {},
+            CommandType::C_GOTO => next = self.program.label(&scoped_label(&c.function, &c.arg1))?,
+            CommandType::C_IF => {
+                if self.pop()? != 0 {
+                    next = self.program.label(&scoped_label(&c.function, &c.arg1))?;
+                }
+            },
+            CommandType::C_FUNCTION => {
+                for _ in 0..c.arg2 {
+                    self.push(0)?;
+                }
+            },
+            CommandType::C_CALL => {
+                let start = self.program.function(&c.arg1).map(|f| f.start).ok_or_else(|| format!("function {} is not defined", c.arg1))?;
+                self.push(next as u16)?;
+                for r in [LCL, ARG, THIS, THAT].iter() {
+                    self.push(self.ram[*r])?;
+                }
+                let sp = self.ram[SP];
+                self.ram[ARG] = sp.wrapping_sub(c.arg2 as u16 + 5);
+                self.ram[LCL] = sp;
+                self.depth += 1;
+                next = start;
+            },
+            CommandType::C_RETURN => {
+                let frame = self.ram[LCL] as usize;
+                if frame < 5 {
+                    return Err("return without a call".to_string());
+                }
+                if frame > RAM_SIZE {
+                    return Err(format!("LCL is outside of RAM, at {}", frame));
+                }
+                let ret = self.ram[frame - 5];
+                let value = self.pop()?;
+                let arg = self.ram[ARG] as usize;
+                *self.ram.get_mut(arg).ok_or("ARG is outside of RAM")? = value;
+                self.ram[SP] = arg as u16 + 1;
+                self.ram[THAT] = self.ram[frame - 1];
+                self.ram[THIS] = self.ram[frame - 2];
+                self.ram[ARG] = self.ram[frame - 3];
+                self.ram[LCL] = self.ram[frame - 4];
+                if ret == BOOTSTRAP_RETURN {
+                    return Err("Sys.init returned".to_string());
+                }
+                self.depth = self.depth.saturating_sub(1);
+                next = ret as usize;
+            },
+        }
+        self.pc = next;
+        self.steps += 1;
+        Ok(())
+    }
+
+    fn segment_address(&self, c: &Command) -> Result<usize, String> {
+        let index = c.arg2 as usize;
+        let address = match c.arg1.as_str() {
+            "local" => self.ram[LCL] as usize + index,
+            "argument" => self.ram[ARG] as usize + index,
+            "this" => self.ram[THIS] as usize + index,
+            "that" => self.ram[THAT] as usize + index,
+            "temp" if index < 8 => TEMP + index,
+            "pointer" if index < 2 => THIS + index,
+            "static" => self.program.static_address(&format!("{}.{}", c.file, c.arg2)).unwrap() as usize,
+            _ => return Err(format!("bad segment: {} {}", c.arg1, c.arg2)),
+        };
+        if address >= RAM_SIZE {
+            return Err(format!("{} {} is outside of RAM, at {}", c.arg1, c.arg2, address));
+        }
+        Ok(address)
+    }
+
+    fn push(&mut self, value: u16) -> Result<(), String> {
+        let sp = self.ram[SP] as usize;
+        if sp >= HEAP {
+            return Err("stack overflow".to_string());
+        }
+        self.ram[sp] = value;
+        self.ram[SP] += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<u16, String> {
+        let sp = self.ram[SP] as usize;
+        if sp <= STACK || sp > HEAP {
+            return Err(format!("pop with SP at {}", sp));
+        }
+        self.ram[SP] -= 1;
+        Ok(self.ram[sp - 1])
+    }
+
+    // The frames of the calls in progress, the current one first, found by
+    // following the saved LCL, ARG, THIS and THAT back to the bootstrap.
+    pub fn frames(&self) -> Vec<Frame> {
+        let mut frames = vec![];
+        let mut pc = self.pc;
+        let (mut lcl, mut arg, mut this, mut that) = (self.ram[LCL], self.ram[ARG], self.ram[THIS], self.ram[THAT]);
+        let mut sp = self.ram[SP];
+        loop {
+            let f = match self.program.function_at(pc) {
+                Some(f) => f,
+                None => {
+                    // commands outside of any function
+                    frames.push(Frame { function: String::new(), pc: pc, lcl: lcl, arg: arg, this: this, that: that, num_args: 0, num_locals: 0, sp: sp });
+                    break;
+                },
+            };
+            // before the function command runs, its locals are not pushed
+            let num_locals = if pc == f.start { 0 } else { f.num_locals };
+            frames.push(Frame {
+                function: f.name.clone(),
+                pc: pc,
+                lcl: lcl,
+                arg: arg,
+                this: this,
+                that: that,
+                num_args: lcl.wrapping_sub(arg).wrapping_sub(5),
+                num_locals: num_locals,
+                sp: sp,
+            });
+            let frame = lcl as usize;
+            if !(STACK + 5..=HEAP).contains(&frame) || frames.len() > HEAP {
+                break;
+            }
+            let ret = self.ram[frame - 5];
+            if ret == BOOTSTRAP_RETURN || ret == 0 || ret as usize > self.program.commands.len() {
+                break;
+            }
+            pc = ret as usize - 1;
+            sp = arg;
+            that = self.ram[frame - 1];
+            this = self.ram[frame - 2];
+            arg = self.ram[frame - 3];
+            lcl = self.ram[frame - 4];
+        }
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn load(dir: &str, files: &[&str]) -> Machine {
+        let mut program = Program::new();
+        for f in files {
+            let path = format!("../FunctionCalls/{}/{}", dir, f);
+            program.add_file(f, std::fs::File::open(path).unwrap()).unwrap();
+        }
+        Machine::new(program)
+    }
+
+    #[test]
+    fn fibonacci() {
+        let mut m = load("FibonacciElement", &["Sys.vm", "Main.vm"]);
+        while !m.is_halted() {
+            m.step().unwrap();
+        }
+        // the result of Main.fibonacci 4 on top of the stack, as in the .cmp
+        assert_eq!(m.ram[SP], 262);
+        assert_eq!(m.ram[261], 3);
+        assert_eq!(m.depth, 1);
+    }
+
+    #[test]
+    fn frames() {
+        let mut m = load("NestedCall", &["Sys.vm"]);
+        let add12 = m.program.function("Sys.add12").unwrap().start;
+        while m.pc != add12 + 5 {
+            m.step().unwrap();
+        }
+        let frames = m.frames();
+        let names: Vec<&str> = frames.iter().map(|f| f.function.as_str()).collect();
+        assert_eq!(names, vec!["Sys.add12", "Sys.main", "Sys.init"]);
+        assert_eq!((frames[0].num_args, frames[0].this, frames[0].that), (1, 4002, 5002));
+        assert_eq!(m.ram[frames[0].arg as usize], 123);
+        assert_eq!((frames[1].num_locals, frames[1].this, frames[1].that), (5, 4001, 5001));
+        assert_eq!(m.program.commands[frames[1].pc].text(), "call Sys.add12 1");
+        assert_eq!(m.ram[frames[1].lcl as usize + 1], 200);
+        assert_eq!((frames[2].this, frames[2].that, frames[2].arg), (4000, 5000, 256));
+
+        while !m.is_halted() {
+            m.step().unwrap();
+        }
+        // Sys.main returns the sum of its locals, 0 + 200 + 40 + 6 + 0
+        assert_eq!(m.ram[TEMP + 1], 246);
+        assert_eq!(m.ram[TEMP], 135);
+        assert_eq!(m.frames().len(), 1);
+    }
+
+    #[test]
+    fn statics_and_labels() {
+        let mut program = Program::new();
+        program.add_file("Sys.vm", io::Cursor::new("function Sys.init 0\npush static 1\npush static 0\nlabel END\ngoto END")).unwrap();
+        program.add_file("Main.vm", io::Cursor::new("function Main.f 0\nlabel END\npop static 0\nreturn")).unwrap();
+        assert_eq!(program.static_address("Sys.1"), Some(16));
+        assert_eq!(program.static_address("Main.0"), Some(18));
+        assert_eq!(program.label("Main.f$END"), Ok(6));
+        assert!(program.label("END").unwrap_err().contains("Main.f$END, Sys.init$END"));
+        assert!(program.add_file("X.vm", io::Cursor::new("function Main.f 0")).is_err());
+
+        let mut m = Machine::new(program);
+        m.step().unwrap();
+        assert_eq!(m.step(), Ok(()));
+        assert_eq!(m.step(), Ok(()));
+        m.step().unwrap();
+        assert!(m.is_halted());
+    }
+
+    #[test]
+    fn corrupted_frame() {
+        // Main.f overwrites the LCL that Sys.init's frame saved
+        let mut program = Program::new();
+        program.add_file("Sys.vm", io::Cursor::new("function Sys.init 0\ncall Main.f 0\nreturn")).unwrap();
+        program.add_file("Main.vm", io::Cursor::new("function Main.f 0\npush constant 262\npop pointer 1\npush constant 40000\npop that 0\npush constant 0\nreturn")).unwrap();
+        let mut m = Machine::new(program);
+        let r = loop {
+            if let Err(e) = m.step() {
+                break e;
+            }
+        };
+        assert_eq!(r, "LCL is outside of RAM, at 40000");
+        assert_eq!(m.program.commands[m.pc].text(), "return");
+    }
+}
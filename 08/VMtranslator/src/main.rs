@@ -2,8 +2,7 @@ use std::fs;
 use std::env;
 use std::path;
 
-mod parser;
-mod codeWriter;
+use VMtranslator::{parser, codeWriter};
 
 fn main() -> Result<(), std::io::Error> {
     let args: Vec<String> = env::args().collect();
@@ -24,7 +23,8 @@ fn main() -> Result<(), std::io::Error> {
         let f_w = fs::File::create(&w_file_name)?;
         let mut cw = codeWriter::CodeWriter::new(f_w);
         
-        proc_translate(&f, file_name.to_str().unwrap(), &mut cw, false);
+        let errors = proc_translate(&f, file_name.to_str().unwrap(), &mut cw, false);
+        exit_on_errors(&errors);
     
         return Ok(());
     }
@@ -35,6 +35,7 @@ fn main() -> Result<(), std::io::Error> {
         let f_w = fs::File::create(&w_file_name)?;
         let mut cw = codeWriter::CodeWriter::new(f_w);
 
+        let mut errors = vec![];
         let mut iter = input.read_dir()?;
         match iter.find(|d| d.as_ref().unwrap().path().ends_with("Sys.vm")) {
             None => {
@@ -45,7 +46,7 @@ fn main() -> Result<(), std::io::Error> {
                 let p = d.unwrap().path();
                 let f_name = p.file_name().unwrap().to_string_lossy().to_string();
                 let f = fs::File::open(p)?;
-                proc_translate(&f, &f_name, &mut cw, true);
+                errors.append(&mut proc_translate(&f, &f_name, &mut cw, true));
             } 
         }
 
@@ -61,14 +62,26 @@ fn main() -> Result<(), std::io::Error> {
         for p in vm_files {
             let f_name = p.file_name().unwrap().to_string_lossy().to_string();
             let f = fs::File::open(p)?;
-            proc_translate(&f, &f_name, &mut cw, false);
+            errors.append(&mut proc_translate(&f, &f_name, &mut cw, false));
         }
+        exit_on_errors(&errors);
     }
     
     Ok(())
 }
 
-fn proc_translate(f: &fs::File, f_name: &str, cw: &mut codeWriter::CodeWriter<fs::File>, is_bootstrap: bool) {
+fn exit_on_errors(errors: &[String]) {
+    if errors.is_empty() {
+        return;
+    }
+    for e in errors {
+        eprintln!("{}", e);
+    }
+    std::process::exit(1);
+}
+
+fn proc_translate(f: &fs::File, f_name: &str, cw: &mut codeWriter::CodeWriter<fs::File>, is_bootstrap: bool) -> Vec<String> {
+    let mut errors = vec![];
     let mut p = parser::Parser::new(f);
     cw.setFileName(f_name);
     if is_bootstrap {
@@ -85,7 +98,9 @@ fn proc_translate(f: &fs::File, f_name: &str, cw: &mut codeWriter::CodeWriter<fs
                 cw.writeArithmetic(p.arg1());
             },
             Some(parser::CommandType::C_LABEL) => {
-                cw.writeLabel(p.arg1());
+                if let Err(e) = cw.writeLabel(p.arg1()) {
+                    errors.push(format!("{}: {}", f_name, e));
+                }
             },
             Some(parser::CommandType::C_GOTO) => {
                 cw.writeGoto(p.arg1());
@@ -105,5 +120,5 @@ fn proc_translate(f: &fs::File, f_name: &str, cw: &mut codeWriter::CodeWriter<fs
             _ => {}
         }
     }
-
+    errors
 }
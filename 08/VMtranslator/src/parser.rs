@@ -3,6 +3,7 @@ use std::io::{self, BufRead, Read};
 pub struct Parser<R: io::Read> {
     fs : io::BufReader<R>,
     cur_line : String,
+    line_number : usize,
     command_type : Option<CommandType>,
     arg1 : String,
     arg2 : i32,
@@ -28,6 +29,7 @@ impl<R: io::Read> Parser<R> {
         Parser {
             fs : io::BufReader::new(reader),
             cur_line : String::from(""),
+            line_number : 0,
             command_type : None,
             arg1 : String::from(""),
             arg2 : 0
@@ -35,6 +37,7 @@ impl<R: io::Read> Parser<R> {
     }
     pub fn hasMoreCommands(&mut self) -> bool {
         for line in self.fs.by_ref().lines() {
+            self.line_number += 1;
             let l = line.unwrap_or_default();
             if !(l.starts_with("//") || l.is_empty()) {
                 self.cur_line = l;
@@ -107,6 +110,10 @@ impl<R: io::Read> Parser<R> {
     pub fn arg2(&self) -> i32 {
         self.arg2
     }
+    // The line of the current command, from 1.
+    pub fn lineNumber(&self) -> usize {
+        self.line_number
+    }
 }
 
 #[cfg(test)]
@@ -228,6 +235,7 @@ mod tests{
             p.advance();
             assert_eq!(p.commandType(), Some(CommandType::C_LABEL));
             assert_eq!(p.arg1(), "LOOP");
+            assert_eq!(p.lineNumber(), 5);
         }
         {
             assert_eq!(p.hasMoreCommands(), true);
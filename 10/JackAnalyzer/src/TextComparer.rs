@@ -0,0 +1,51 @@
+// Compare two texts the way the course TextComparer does: whitespace is
+// ignored, so only the sequence of non-blank lines matters.
+pub fn compare(expected: &str, actual: &str) -> Result<(), String> {
+    let e = significant_lines(expected);
+    let a = significant_lines(actual);
+    for i in 0..e.len().max(a.len()) {
+        match (e.get(i), a.get(i)) {
+            (Some((n, el)), Some((_, al))) if el != al => {
+                return Err(format!("line {}: expected {}, found {}", n, el, al));
+            },
+            (Some((n, el)), None) => {
+                return Err(format!("line {}: expected {}, found end of file", n, el));
+            },
+            (None, Some((_, al))) => {
+                return Err(format!("unexpected {} after end of file", al));
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+// Lines without whitespace, numbered as in the original text.
+fn significant_lines(s: &str) -> Vec<(usize, String)> {
+    s.lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.chars().filter(|c| !c.is_whitespace()).collect::<String>()))
+        .filter(|(_, l)| !l.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn ignore_whitespace() {
+        let e = "<tokens>\r\n<keyword> class </keyword>\r\n</tokens>\r\n";
+        let a = "<tokens>\n  <keyword>class</keyword>\n\n</tokens>";
+        assert_eq!(compare(e, a), Ok(()));
+    }
+
+    #[test]
+    fn mismatch() {
+        let e = "<tokens>\r\n<keyword> class </keyword>\r\n</tokens>\r\n";
+        let a = "<tokens>\r\n<identifier> class </identifier>\r\n</tokens>\r\n";
+        assert_eq!(compare(e, a), Err("line 2: expected <keyword>class</keyword>, found <identifier>class</identifier>".to_string()));
+        assert!(compare(e, "<tokens>").is_err());
+        assert!(compare("<tokens>", e).is_err());
+    }
+}
@@ -1,6 +1,94 @@
+use std::fs;
+use std::env;
+use std::io;
+use std::path;
+use std::process;
+
 mod JackTokenizer;
 mod CompilationEngine;
+mod TextComparer;
+
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    // --compare checks the output against the XxxT.xml and Xxx.xml
+    // next to each .jack file; --out DIR writes them to DIR instead, so
+    // that the reference files are never overwritten.
+    let compare = args.iter().any(|a| a == "--compare");
+    let out_pos = args.iter().position(|a| a == "--out");
+    let out_dir = out_pos.and_then(|i| args.get(i + 1)).map(path::Path::new);
+    let input = args.iter().enumerate().skip(1)
+        .find(|(i, a)| !a.starts_with('-') && out_pos.map_or(true, |p| *i != p + 1))
+        .map(|(_, a)| path::Path::new(a));
+    let input = match input {
+        Some(i) if compare || out_dir.is_some() => i,
+        _ => {
+            println!("usage: JackAnalyzer (--compare | --out DIR) FILE.jack|DIR");
+            return Ok(());
+        }
+    };
+    if let Some(d) = out_dir {
+        fs::create_dir_all(d)?;
+    }
+
+    let mut failed = false;
+    for p in collect_jack_files(input)? {
+        let (tokens, tree) = analyze(&p)?;
+        let class_name = p.file_stem().unwrap().to_string_lossy().to_string();
+        let outputs = [(format!("{}T.xml", class_name), tokens), (format!("{}.xml", class_name), tree)];
+        for (name, xml) in outputs.iter() {
+            if let Some(d) = out_dir {
+                fs::write(d.join(name), xml)?;
+            }
+            if !compare {
+                continue;
+            }
+            let out_path = p.with_file_name(name);
+            let expected = match fs::read_to_string(&out_path) {
+                Ok(s) => s,
+                Err(e) => {
+                    println!("{}: {}", out_path.display(), e);
+                    failed = true;
+                    continue;
+                }
+            };
+            match TextComparer::compare(&expected, xml) {
+                Ok(()) => println!("{}: ok", out_path.display()),
+                Err(e) => {
+                    println!("{}: {}", out_path.display(), e);
+                    failed = true;
+                }
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn collect_jack_files(input: &path::Path) -> io::Result<Vec<path::PathBuf>> {
+    if input.is_file() {
+        return Ok(vec![input.to_path_buf()]);
+    }
+    let mut jack_files = input.read_dir()?
+        .filter_map(|d| d.ok())
+        .map(|d| d.path())
+        .filter(|p| p.extension().map_or(false, |e| e == "jack"))
+        .collect::<Vec<path::PathBuf>>();
+    jack_files.sort();
+    Ok(jack_files)
+}
+
+// Returns the token list and the parse tree of a class as XML.
+fn analyze(p: &path::Path) -> io::Result<(String, String)> {
+    let mut t = JackTokenizer::JackTokenizer::new(fs::File::open(p)?);
+    let tokens = t.to_xml();
 
-fn main() {
-    println!("Hello, world!");
+    let mut tree = vec![];
+    {
+        // CompilationEngine must be dropped to flush its BufWriter.
+        let mut c = CompilationEngine::CompilationEngine::new(fs::File::open(p)?, &mut tree);
+        c.compileClass();
+    }
+    Ok((tokens, String::from_utf8_lossy(&tree).to_string()))
 }
@@ -0,0 +1,778 @@
+/*
+ * Jack OS runtime for programs translated to C by JackCompiler --emit c.
+ *
+ * The Hack RAM is modelled by jack_ram: the heap starts at 2048, the screen
+ * at 16384 and the keyboard at 24576, so programs that peek and poke memory
+ * behave as they do on the VM. Each OS class can be replaced by a Jack
+ * implementation: the generated code defines JACK_USER_<Class> for it and
+ * the C version below is left out.
+ *
+ * Sys.halt writes the screen as a 512x256 PBM image to $JACK_SCREEN
+ * (screen.pbm by default) and exits. Output also echoes text to stdout and
+ * Keyboard.readChar reads from stdin.
+ */
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+typedef int16_t word;
+
+static word jack_ram[65536];
+#define RAM(address) jack_ram[(uint16_t)(address)]
+
+#define JACK_SCREEN 16384
+#define JACK_KEYBOARD 24576
+
+word Math_init(void);
+word Math_abs(word x);
+word Math_multiply(word x, word y);
+word Math_divide(word x, word y);
+word Math_min(word x, word y);
+word Math_max(word x, word y);
+word Math_sqrt(word x);
+word String_new(word maxLength);
+word String_dispose(word this_);
+word String_length(word this_);
+word String_charAt(word this_, word j);
+word String_setCharAt(word this_, word j, word c);
+word String_appendChar(word this_, word c);
+word String_eraseLastChar(word this_);
+word String_intValue(word this_);
+word String_setInt(word this_, word val);
+word String_backSpace(void);
+word String_doubleQuote(void);
+word String_newLine(void);
+word Array_new(word size);
+word Array_dispose(word this_);
+word Output_init(void);
+word Output_moveCursor(word i, word j);
+word Output_printChar(word c);
+word Output_printString(word s);
+word Output_printInt(word i);
+word Output_println(void);
+word Output_backSpace(void);
+word Screen_init(void);
+word Screen_clearScreen(void);
+word Screen_setColor(word b);
+word Screen_drawPixel(word x, word y);
+word Screen_drawLine(word x1, word y1, word x2, word y2);
+word Screen_drawRectangle(word x1, word y1, word x2, word y2);
+word Screen_drawCircle(word x, word y, word r);
+word Memory_init(void);
+word Memory_peek(word address);
+word Memory_poke(word address, word value);
+word Memory_alloc(word size);
+word Memory_deAlloc(word o);
+word Keyboard_init(void);
+word Keyboard_keyPressed(void);
+word Keyboard_readChar(void);
+word Keyboard_readLine(word message);
+word Keyboard_readInt(word message);
+word Sys_init(void);
+word Sys_halt(void);
+word Sys_error(word errorCode);
+word Sys_wait(word duration);
+word Main_main(void);
+
+// A string constant: String.new and String.appendChar as the VM code does.
+word jack_string(word length, const word *chars)
+{
+    word s = String_new(length);
+    word i;
+    for (i = 0; i < length; i++) {
+        String_appendChar(s, chars[i]);
+    }
+    return s;
+}
+
+// Writes the screen memory as a PBM image and exits.
+void jack_exit(int status)
+{
+    const char *path = getenv("JACK_SCREEN");
+    FILE *f;
+    int y, x;
+    fflush(stdout);
+    f = fopen(path ? path : "screen.pbm", "wb");
+    if (f) {
+        fprintf(f, "P4\n512 256\n");
+        for (y = 0; y < 256; y++) {
+            for (x = 0; x < 512; x += 8) {
+                uint16_t w = (uint16_t)RAM(JACK_SCREEN + y * 32 + x / 16);
+                unsigned char bits = (unsigned char)(w >> (x % 16));
+                unsigned char b = 0;
+                int i;
+                // Hack draws bit 0 leftmost, PBM the most significant bit
+                for (i = 0; i < 8; i++) {
+                    if (bits & (1 << i)) {
+                        b |= (unsigned char)(0x80 >> i);
+                    }
+                }
+                fputc(b, f);
+            }
+        }
+        fclose(f);
+    }
+    exit(status);
+}
+
+#ifndef JACK_USER_Math
+word Math_init(void)
+{
+    return 0;
+}
+
+word Math_abs(word x)
+{
+    return (word)(x < 0 ? -x : x);
+}
+
+word Math_multiply(word x, word y)
+{
+    return (word)(uint16_t)((uint32_t)(uint16_t)x * (uint32_t)(uint16_t)y);
+}
+
+word Math_divide(word x, word y)
+{
+    if (y == 0) {
+        Sys_error(3);
+    }
+    return (word)((int32_t)x / (int32_t)y);
+}
+
+word Math_min(word x, word y)
+{
+    return x < y ? x : y;
+}
+
+word Math_max(word x, word y)
+{
+    return x > y ? x : y;
+}
+
+word Math_sqrt(word x)
+{
+    int32_t y = 0;
+    int j;
+    if (x < 0) {
+        Sys_error(4);
+    }
+    for (j = 7; j >= 0; j--) {
+        int32_t t = y + (1 << j);
+        if (t * t <= x) {
+            y = t;
+        }
+    }
+    return (word)y;
+}
+#endif
+
+#ifndef JACK_USER_Memory
+static word jack_free_list;
+
+// Free blocks are [length, next]; an allocated block keeps its length
+// just before the address handed out.
+word Memory_init(void)
+{
+    jack_free_list = 2048;
+    RAM(2048) = JACK_SCREEN - 2048;
+    RAM(2049) = 0;
+    return 0;
+}
+
+word Memory_peek(word address)
+{
+    return RAM(address);
+}
+
+word Memory_poke(word address, word value)
+{
+    RAM(address) = value;
+    return 0;
+}
+
+word Memory_alloc(word size)
+{
+    word block;
+    word best = 0;
+    if (size <= 0) {
+        Sys_error(5);
+    }
+    // best fit, cut from the end of the block
+    for (block = jack_free_list; block != 0; block = RAM(block + 1)) {
+        if (RAM(block) >= size + 3 && (best == 0 || RAM(block) < RAM(best))) {
+            best = block;
+        }
+    }
+    if (best == 0) {
+        Sys_error(6);
+    }
+    RAM(best) = (word)(RAM(best) - (size + 1));
+    block = (word)(best + RAM(best));
+    RAM(block) = (word)(size + 1);
+    return (word)(block + 1);
+}
+
+word Memory_deAlloc(word o)
+{
+    word block = (word)(o - 1);
+    if (RAM(block) < 2) {
+        return 0;
+    }
+    RAM(block + 1) = jack_free_list;
+    jack_free_list = block;
+    return 0;
+}
+#endif
+
+#ifndef JACK_USER_Array
+word Array_new(word size)
+{
+    if (size <= 0) {
+        Sys_error(2);
+    }
+    return Memory_alloc(size);
+}
+
+word Array_dispose(word this_)
+{
+    return Memory_deAlloc(this_);
+}
+#endif
+
+#ifndef JACK_USER_String
+// [maxLength, length, chars]
+#define STRING_MAX(s) RAM((s))
+#define STRING_LENGTH(s) RAM((s) + 1)
+#define STRING_CHARS(s) RAM((s) + 2)
+
+word String_new(word maxLength)
+{
+    word s;
+    if (maxLength < 0) {
+        Sys_error(14);
+    }
+    s = Memory_alloc(3);
+    STRING_MAX(s) = maxLength;
+    STRING_LENGTH(s) = 0;
+    STRING_CHARS(s) = maxLength > 0 ? Array_new(maxLength) : 0;
+    return s;
+}
+
+word String_dispose(word this_)
+{
+    if (STRING_CHARS(this_) != 0) {
+        Array_dispose(STRING_CHARS(this_));
+    }
+    return Memory_deAlloc(this_);
+}
+
+word String_length(word this_)
+{
+    return STRING_LENGTH(this_);
+}
+
+word String_charAt(word this_, word j)
+{
+    if (j < 0 || j >= STRING_LENGTH(this_)) {
+        Sys_error(15);
+    }
+    return RAM(STRING_CHARS(this_) + j);
+}
+
+word String_setCharAt(word this_, word j, word c)
+{
+    if (j < 0 || j >= STRING_LENGTH(this_)) {
+        Sys_error(16);
+    }
+    RAM(STRING_CHARS(this_) + j) = c;
+    return 0;
+}
+
+word String_appendChar(word this_, word c)
+{
+    if (STRING_LENGTH(this_) >= STRING_MAX(this_)) {
+        Sys_error(17);
+    }
+    RAM(STRING_CHARS(this_) + STRING_LENGTH(this_)) = c;
+    STRING_LENGTH(this_)++;
+    return this_;
+}
+
+word String_eraseLastChar(word this_)
+{
+    if (STRING_LENGTH(this_) == 0) {
+        Sys_error(18);
+    }
+    STRING_LENGTH(this_)--;
+    return 0;
+}
+
+word String_intValue(word this_)
+{
+    word n = 0;
+    word i = 0;
+    int negative = STRING_LENGTH(this_) > 0 && RAM(STRING_CHARS(this_)) == '-';
+    if (negative) {
+        i = 1;
+    }
+    for (; i < STRING_LENGTH(this_); i++) {
+        word c = RAM(STRING_CHARS(this_) + i);
+        if (c < '0' || c > '9') {
+            break;
+        }
+        n = (word)(n * 10 + (c - '0'));
+    }
+    return (word)(negative ? -n : n);
+}
+
+word String_setInt(word this_, word val)
+{
+    char digits[8];
+    int count = 0;
+    int32_t n = val < 0 ? -(int32_t)val : val;
+    do {
+        digits[count++] = (char)('0' + n % 10);
+        n /= 10;
+    } while (n > 0);
+    if (count + (val < 0) > STRING_MAX(this_)) {
+        Sys_error(19);
+    }
+    STRING_LENGTH(this_) = 0;
+    if (val < 0) {
+        String_appendChar(this_, '-');
+    }
+    while (count > 0) {
+        String_appendChar(this_, digits[--count]);
+    }
+    return 0;
+}
+
+word String_newLine(void)
+{
+    return 128;
+}
+
+word String_backSpace(void)
+{
+    return 129;
+}
+
+word String_doubleQuote(void)
+{
+    return 34;
+}
+#endif
+
+#ifndef JACK_USER_Output
+// The rows of each 8x11 character, bit 0 leftmost, from Output.jack.
+static const unsigned char jack_font[127][11] = {
+    [0] = {63,63,63,63,63,63,63,63,63,0,0},
+    [32] = {0,0,0,0,0,0,0,0,0,0,0},
+    [33] = {12,30,30,30,12,12,0,12,12,0,0},
+    [34] = {54,54,20,0,0,0,0,0,0,0,0},
+    [35] = {0,18,18,63,18,18,63,18,18,0,0},
+    [36] = {12,30,51,3,30,48,51,30,12,12,0},
+    [37] = {0,0,35,51,24,12,6,51,49,0,0},
+    [38] = {12,30,30,12,54,27,27,27,54,0,0},
+    [39] = {12,12,6,0,0,0,0,0,0,0,0},
+    [40] = {24,12,6,6,6,6,6,12,24,0,0},
+    [41] = {6,12,24,24,24,24,24,12,6,0,0},
+    [42] = {0,0,0,51,30,63,30,51,0,0,0},
+    [43] = {0,0,0,12,12,63,12,12,0,0,0},
+    [44] = {0,0,0,0,0,0,0,12,12,6,0},
+    [45] = {0,0,0,0,0,63,0,0,0,0,0},
+    [46] = {0,0,0,0,0,0,0,12,12,0,0},
+    [47] = {0,0,32,48,24,12,6,3,1,0,0},
+    [48] = {12,30,51,51,51,51,51,30,12,0,0},
+    [49] = {12,14,15,12,12,12,12,12,63,0,0},
+    [50] = {30,51,48,24,12,6,3,51,63,0,0},
+    [51] = {30,51,48,48,28,48,48,51,30,0,0},
+    [52] = {16,24,28,26,25,63,24,24,60,0,0},
+    [53] = {63,3,3,31,48,48,48,51,30,0,0},
+    [54] = {28,6,3,3,31,51,51,51,30,0,0},
+    [55] = {63,49,48,48,24,12,12,12,12,0,0},
+    [56] = {30,51,51,51,30,51,51,51,30,0,0},
+    [57] = {30,51,51,51,62,48,48,24,14,0,0},
+    [58] = {0,0,12,12,0,0,12,12,0,0,0},
+    [59] = {0,0,12,12,0,0,12,12,6,0,0},
+    [60] = {0,0,24,12,6,3,6,12,24,0,0},
+    [61] = {0,0,0,63,0,0,63,0,0,0,0},
+    [62] = {0,0,3,6,12,24,12,6,3,0,0},
+    [63] = {30,51,51,24,12,12,0,12,12,0,0},
+    [64] = {30,51,51,59,59,59,27,3,30,0,0},
+    [65] = {12,30,51,51,63,51,51,51,51,0,0},
+    [66] = {31,51,51,51,31,51,51,51,31,0,0},
+    [67] = {28,54,35,3,3,3,35,54,28,0,0},
+    [68] = {15,27,51,51,51,51,51,27,15,0,0},
+    [69] = {63,51,35,11,15,11,35,51,63,0,0},
+    [70] = {63,51,35,11,15,11,3,3,3,0,0},
+    [71] = {28,54,35,3,59,51,51,54,44,0,0},
+    [72] = {51,51,51,51,63,51,51,51,51,0,0},
+    [73] = {30,12,12,12,12,12,12,12,30,0,0},
+    [74] = {60,24,24,24,24,24,27,27,14,0,0},
+    [75] = {51,51,51,27,15,27,51,51,51,0,0},
+    [76] = {3,3,3,3,3,3,35,51,63,0,0},
+    [77] = {33,51,63,63,51,51,51,51,51,0,0},
+    [78] = {51,51,55,55,63,59,59,51,51,0,0},
+    [79] = {30,51,51,51,51,51,51,51,30,0,0},
+    [80] = {31,51,51,51,31,3,3,3,3,0,0},
+    [81] = {30,51,51,51,51,51,63,59,30,48,0},
+    [82] = {31,51,51,51,31,27,51,51,51,0,0},
+    [83] = {30,51,51,6,28,48,51,51,30,0,0},
+    [84] = {63,63,45,12,12,12,12,12,30,0,0},
+    [85] = {51,51,51,51,51,51,51,51,30,0,0},
+    [86] = {51,51,51,51,51,30,30,12,12,0,0},
+    [87] = {51,51,51,51,51,63,63,63,18,0,0},
+    [88] = {51,51,30,30,12,30,30,51,51,0,0},
+    [89] = {51,51,51,51,30,12,12,12,30,0,0},
+    [90] = {63,51,49,24,12,6,35,51,63,0,0},
+    [91] = {30,6,6,6,6,6,6,6,30,0,0},
+    [92] = {0,0,1,3,6,12,24,48,32,0,0},
+    [93] = {30,24,24,24,24,24,24,24,30,0,0},
+    [94] = {8,28,54,0,0,0,0,0,0,0,0},
+    [95] = {0,0,0,0,0,0,0,0,0,63,0},
+    [96] = {6,12,24,0,0,0,0,0,0,0,0},
+    [97] = {0,0,0,14,24,30,27,27,54,0,0},
+    [98] = {3,3,3,15,27,51,51,51,30,0,0},
+    [99] = {0,0,0,30,51,3,3,51,30,0,0},
+    [100] = {48,48,48,60,54,51,51,51,30,0,0},
+    [101] = {0,0,0,30,51,63,3,51,30,0,0},
+    [102] = {28,54,38,6,15,6,6,6,15,0,0},
+    [103] = {0,0,30,51,51,51,62,48,51,30,0},
+    [104] = {3,3,3,27,55,51,51,51,51,0,0},
+    [105] = {12,12,0,14,12,12,12,12,30,0,0},
+    [106] = {48,48,0,56,48,48,48,48,51,30,0},
+    [107] = {3,3,3,51,27,15,15,27,51,0,0},
+    [108] = {14,12,12,12,12,12,12,12,30,0,0},
+    [109] = {0,0,0,29,63,43,43,43,43,0,0},
+    [110] = {0,0,0,29,51,51,51,51,51,0,0},
+    [111] = {0,0,0,30,51,51,51,51,30,0,0},
+    [112] = {0,0,0,30,51,51,51,31,3,3,0},
+    [113] = {0,0,0,30,51,51,51,62,48,48,0},
+    [114] = {0,0,0,29,55,51,3,3,7,0,0},
+    [115] = {0,0,0,30,51,6,24,51,30,0,0},
+    [116] = {4,6,6,15,6,6,6,54,28,0,0},
+    [117] = {0,0,0,27,27,27,27,27,54,0,0},
+    [118] = {0,0,0,51,51,51,51,30,12,0,0},
+    [119] = {0,0,0,51,51,51,63,63,18,0,0},
+    [120] = {0,0,0,51,30,12,12,30,51,0,0},
+    [121] = {0,0,0,51,51,51,62,48,24,15,0},
+    [122] = {0,0,0,63,27,12,6,51,63,0,0},
+    [123] = {56,12,12,12,7,12,12,12,56,0,0},
+    [124] = {12,12,12,12,12,12,12,12,12,0,0},
+    [125] = {7,12,12,12,56,12,12,12,7,0,0},
+    [126] = {38,45,25,0,0,0,0,0,0,0,0},
+};
+
+static word jack_line;
+static word jack_column;
+
+// Draws character c at the cursor, clearing the rest of its cell.
+static void jack_draw_char(word c)
+{
+    const unsigned char *map = jack_font[(c < 32 || c > 126) ? 0 : c];
+    int shift = (jack_column % 2) * 8;
+    int j;
+    for (j = 0; j < 11; j++) {
+        word address = (word)(JACK_SCREEN + (jack_line * 11 + j) * 32 + jack_column / 2);
+        uint16_t w = (uint16_t)RAM(address);
+        w = (uint16_t)((w & ~(0xff << shift)) | (map[j] << shift));
+        RAM(address) = (word)w;
+    }
+}
+
+word Output_init(void)
+{
+    jack_line = 0;
+    jack_column = 0;
+    return 0;
+}
+
+word Output_moveCursor(word i, word j)
+{
+    if (i < 0 || i > 22 || j < 0 || j > 63) {
+        Sys_error(20);
+    }
+    jack_line = i;
+    jack_column = j;
+    return 0;
+}
+
+word Output_println(void)
+{
+    jack_line = (word)(jack_line == 22 ? 0 : jack_line + 1);
+    jack_column = 0;
+    putchar('\n');
+    return 0;
+}
+
+word Output_backSpace(void)
+{
+    if (jack_column > 0) {
+        jack_column--;
+    }
+    else if (jack_line > 0) {
+        jack_line--;
+        jack_column = 63;
+    }
+    jack_draw_char(32);
+    putchar('\b');
+    return 0;
+}
+
+word Output_printChar(word c)
+{
+    if (c == 128) {
+        return Output_println();
+    }
+    if (c == 129) {
+        return Output_backSpace();
+    }
+    jack_draw_char(c);
+    putchar(c >= 32 && c <= 126 ? c : '?');
+    if (jack_column == 63) {
+        jack_line = (word)(jack_line == 22 ? 0 : jack_line + 1);
+        jack_column = 0;
+    }
+    else {
+        jack_column++;
+    }
+    return 0;
+}
+
+word Output_printString(word s)
+{
+    word length = String_length(s);
+    word i;
+    for (i = 0; i < length; i++) {
+        Output_printChar(String_charAt(s, i));
+    }
+    return 0;
+}
+
+word Output_printInt(word i)
+{
+    word s = String_new(6);
+    String_setInt(s, i);
+    Output_printString(s);
+    String_dispose(s);
+    return 0;
+}
+#endif
+
+#ifndef JACK_USER_Screen
+static int jack_color = 1;
+
+static void jack_pixel(int x, int y)
+{
+    word address = (word)(JACK_SCREEN + y * 32 + x / 16);
+    uint16_t mask = (uint16_t)(1u << (x % 16));
+    uint16_t w = (uint16_t)RAM(address);
+    RAM(address) = (word)(jack_color ? (w | mask) : (w & ~mask));
+}
+
+static int jack_on_screen(int x, int y)
+{
+    return x >= 0 && x < 512 && y >= 0 && y < 256;
+}
+
+word Screen_init(void)
+{
+    jack_color = 1;
+    return 0;
+}
+
+word Screen_clearScreen(void)
+{
+    int i;
+    for (i = 0; i < 8192; i++) {
+        RAM(JACK_SCREEN + i) = 0;
+    }
+    return 0;
+}
+
+word Screen_setColor(word b)
+{
+    jack_color = b != 0;
+    return 0;
+}
+
+word Screen_drawPixel(word x, word y)
+{
+    if (!jack_on_screen(x, y)) {
+        Sys_error(7);
+    }
+    jack_pixel(x, y);
+    return 0;
+}
+
+word Screen_drawLine(word x1, word y1, word x2, word y2)
+{
+    int dx = abs(x2 - x1);
+    int dy = abs(y2 - y1);
+    int sx = x1 < x2 ? 1 : -1;
+    int sy = y1 < y2 ? 1 : -1;
+    int err = dx - dy;
+    int x = x1;
+    int y = y1;
+    if (!jack_on_screen(x1, y1) || !jack_on_screen(x2, y2)) {
+        Sys_error(8);
+    }
+    for (;;) {
+        int e2;
+        jack_pixel(x, y);
+        if (x == x2 && y == y2) {
+            break;
+        }
+        e2 = 2 * err;
+        if (e2 > -dy) {
+            err -= dy;
+            x += sx;
+        }
+        if (e2 < dx) {
+            err += dx;
+            y += sy;
+        }
+    }
+    return 0;
+}
+
+word Screen_drawRectangle(word x1, word y1, word x2, word y2)
+{
+    int x, y;
+    if (!jack_on_screen(x1, y1) || !jack_on_screen(x2, y2) || x1 > x2 || y1 > y2) {
+        Sys_error(9);
+    }
+    for (y = y1; y <= y2; y++) {
+        for (x = x1; x <= x2; x++) {
+            jack_pixel(x, y);
+        }
+    }
+    return 0;
+}
+
+word Screen_drawCircle(word x, word y, word r)
+{
+    int dy;
+    if (!jack_on_screen(x, y)) {
+        Sys_error(12);
+    }
+    if (r < 0 || r > 181) {
+        Sys_error(13);
+    }
+    for (dy = -r; dy <= r; dy++) {
+        int dx = Math_sqrt((word)(r * r - dy * dy));
+        int i;
+        for (i = x - dx; i <= x + dx; i++) {
+            if (jack_on_screen(i, y + dy)) {
+                jack_pixel(i, y + dy);
+            }
+        }
+    }
+    return 0;
+}
+#endif
+
+#ifndef JACK_USER_Keyboard
+word Keyboard_init(void)
+{
+    return 0;
+}
+
+word Keyboard_keyPressed(void)
+{
+    return RAM(JACK_KEYBOARD);
+}
+
+// Reads the next key from stdin. A newline is the Enter key; the end of
+// input halts the program.
+word Keyboard_readChar(void)
+{
+    int c = getchar();
+    if (c == EOF) {
+        Sys_halt();
+    }
+    if (c == '\n') {
+        c = 128;
+    }
+    else if (c == '\b' || c == 127) {
+        c = 129;
+    }
+    if (c != 128) {
+        Output_printChar((word)c);
+    }
+    return (word)c;
+}
+
+word Keyboard_readLine(word message)
+{
+    word s = String_new(64);
+    word c;
+    Output_printString(message);
+    fflush(stdout);
+    while ((c = Keyboard_readChar()) != 128) {
+        if (c == 129) {
+            if (String_length(s) > 0) {
+                String_eraseLastChar(s);
+            }
+        }
+        else if (String_length(s) < 64) {
+            String_appendChar(s, c);
+        }
+    }
+    Output_println();
+    return s;
+}
+
+word Keyboard_readInt(word message)
+{
+    word s = Keyboard_readLine(message);
+    word n = String_intValue(s);
+    String_dispose(s);
+    return n;
+}
+#endif
+
+#ifndef JACK_USER_Sys
+word Sys_init(void)
+{
+    Memory_init();
+    Math_init();
+    Output_init();
+    Screen_init();
+    Keyboard_init();
+    Main_main();
+    return Sys_halt();
+}
+
+word Sys_halt(void)
+{
+    jack_exit(0);
+    return 0;
+}
+
+word Sys_wait(word duration)
+{
+    if (duration < 0) {
+        Sys_error(1);
+    }
+    return 0;
+}
+
+word Sys_error(word errorCode)
+{
+    Output_printString(jack_string(3, (const word[]){'E', 'R', 'R'}));
+    Output_printInt(errorCode);
+    fprintf(stderr, "Sys.error(%d)\n", errorCode);
+    jack_exit(1);
+    return 0;
+}
+#endif
+
+#ifndef JACK_NO_MAIN
+int main(void)
+{
+    Sys_init();
+    return 0;
+}
+#endif
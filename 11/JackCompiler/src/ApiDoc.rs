@@ -0,0 +1,275 @@
+use std::io;
+use super::JackTokenizer::*;
+
+// The API of a class: its subroutine signatures and the /** */ comments
+// written just above them and above the class.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ClassDoc {
+    pub name: String,
+    pub doc: String,
+    pub subroutines: Vec<SubroutineDoc>,
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct SubroutineDoc {
+    pub kind: String,
+    pub return_type: String,
+    pub name: String,
+    // (type, name)
+    pub parameters: Vec<(String, String)>,
+    pub doc: String,
+}
+
+pub fn extract(source: &str) -> Result<ClassDoc, String> {
+    let mut tokenizer = JackTokenizer::new(io::Cursor::new(source.as_bytes()));
+    tokenizer.set_keep_comments(true);
+
+    let mut tokens = vec![];
+    while let Some(token) = tokenizer.next() {
+        // a doc comment belongs to the token right after it
+        let doc = tokenizer.take_comments().into_iter().last()
+            .filter(|c| c.text.starts_with("/**"))
+            .map(|c| doc_text(&c.text))
+            .unwrap_or_default();
+        tokens.push((token, doc));
+    }
+
+    let text = |i: usize| match tokens.get(i) {
+        Some((Token::Keyword(k), _)) => convert_keyword(k.clone()),
+        Some((Token::Identifier(s), _)) | Some((Token::Symbol(s), _)) => s.clone(),
+        _ => String::new(),
+    };
+    let mut class = match tokens.iter().position(|(t, _)| *t == Token::Keyword(KeywordType::CLASS)) {
+        Some(i) => ClassDoc { name: text(i + 1), doc: tokens[i].1.clone(), subroutines: vec![] },
+        None => return Err("no class".to_string()),
+    };
+    if class.name.is_empty() {
+        return Err("no class name".to_string());
+    }
+
+    let mut depth = 0;
+    for (i, (token, doc)) in tokens.iter().enumerate() {
+        match token {
+            Token::Symbol(s) if s == "{" => depth += 1,
+            Token::Symbol(s) if s == "}" => depth -= 1,
+            Token::Keyword(KeywordType::CONSTRUCTOR) | Token::Keyword(KeywordType::FUNCTION) | Token::Keyword(KeywordType::METHOD) if depth == 1 => {
+                // kind type name ( (type name ,)* )
+                let mut parameters = vec![];
+                let mut j = i + 4;
+                while j + 1 < tokens.len() && text(j) != ")" {
+                    parameters.push((text(j), text(j + 1)));
+                    j += if text(j + 2) == "," { 3 } else { 2 };
+                }
+                class.subroutines.push(SubroutineDoc {
+                    kind: text(i),
+                    return_type: text(i + 1),
+                    name: text(i + 2),
+                    parameters: parameters,
+                    doc: doc.clone(),
+                });
+            },
+            _ => {},
+        }
+    }
+    Ok(class)
+}
+
+// The text of a doc comment without /** */ and the * starting each line.
+fn doc_text(comment: &str) -> String {
+    let inner = comment.trim_start_matches("/**").trim_end_matches("*/");
+    let lines: Vec<&str> = inner.lines()
+        .map(|l| l.trim())
+        .map(|l| l.strip_prefix('*').map_or(l, |l| l.trim_start()))
+        .collect();
+    lines.join("\n").trim().to_string()
+}
+
+fn signature(s: &SubroutineDoc, link: &dyn Fn(&str) -> String) -> String {
+    let params: Vec<String> = s.parameters.iter().map(|(t, n)| format!("{} {}", link(t), n)).collect();
+    format!("{} {} {}({})", s.kind, link(&s.return_type), s.name, params.join(", "))
+}
+
+fn is_class(classes: &[ClassDoc], name: &str) -> bool {
+    classes.iter().any(|c| c.name == name)
+}
+
+// Class.name references in doc text, split out for linking.
+// Returns (text, Some(class), Some(subroutine)) pieces.
+fn references<'a>(classes: &[ClassDoc], text: &'a str) -> Vec<(&'a str, Option<&'a str>, Option<&'a str>)> {
+    let mut pieces = vec![];
+    let mut rest = text;
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    while let Some(start) = rest.find(|c: char| c.is_ascii_uppercase()) {
+        if start > 0 && rest[..start].chars().last().map_or(false, is_ident) {
+            // inside a word
+            let next = start + 1;
+            pieces.push((&rest[..next], None, None));
+            rest = &rest[next..];
+            continue;
+        }
+        let end = rest[start..].find(|c: char| !is_ident(c)).map_or(rest.len(), |e| start + e);
+        let name = &rest[start..end];
+        if !is_class(classes, name) {
+            pieces.push((&rest[..end], None, None));
+            rest = &rest[end..];
+            continue;
+        }
+        pieces.push((&rest[..start], None, None));
+        let member_end = if rest[end..].starts_with('.') {
+            rest[end + 1..].find(|c: char| !is_ident(c)).map_or(rest.len(), |e| end + 1 + e)
+        }
+        else {
+            end
+        };
+        let member = if member_end > end + 1 { Some(&rest[end + 1..member_end]) } else { None };
+        let link_end = if member.is_some() { member_end } else { end };
+        pieces.push((&rest[start..link_end], Some(name), member));
+        rest = &rest[link_end..];
+    }
+    pieces.push((rest, None, None));
+    pieces
+}
+
+fn anchor(class: &str, subroutine: Option<&str>) -> String {
+    match subroutine {
+        Some(s) => format!("{}.{}", class, s),
+        None => class.to_string(),
+    }
+}
+
+pub fn to_markdown(classes: &[ClassDoc]) -> String {
+    let link = |t: &str| if is_class(classes, t) { format!("[{}](#{})", t, t) } else { t.to_string() };
+    let doc = |text: &str| references(classes, text).iter().map(|(s, c, m)| match c {
+        Some(c) => format!("[{}](#{})", s, anchor(c, *m)),
+        None => s.to_string(),
+    }).collect::<String>();
+
+    let mut s = String::from("# API\n\n");
+    for c in classes {
+        s.push_str(&format!("- [{}](#{})\n", c.name, c.name));
+    }
+    s.push('\n');
+    for c in classes {
+        s.push_str(&format!("<a name=\"{}\"></a>\n\n## class {}\n\n", c.name, c.name));
+        if !c.doc.is_empty() {
+            s.push_str(&format!("{}\n\n", doc(&c.doc)));
+        }
+        for sr in &c.subroutines {
+            s.push_str(&format!("<a name=\"{}\"></a>\n\n### {}\n\n", anchor(&c.name, Some(&sr.name)), signature(sr, &link)));
+            if !sr.doc.is_empty() {
+                s.push_str(&format!("{}\n\n", doc(&sr.doc)));
+            }
+        }
+    }
+    s.truncate(s.trim_end().len());
+    s.push('\n');
+    s
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub fn to_html(classes: &[ClassDoc]) -> String {
+    let link = |t: &str| if is_class(classes, t) {
+        format!("<a href=\"#{}\">{}</a>", t, t)
+    }
+    else {
+        escape_html(t)
+    };
+    let doc = |text: &str| {
+        let paragraphs: Vec<String> = text.split("\n\n").map(|p| {
+            let body: String = references(classes, p).iter().map(|(s, c, m)| match c {
+                Some(c) => format!("<a href=\"#{}\">{}</a>", anchor(c, *m), escape_html(s)),
+                None => escape_html(s),
+            }).collect();
+            format!("<p>{}</p>\n", body.replace('\n', "<br>\n"))
+        }).collect();
+        paragraphs.concat()
+    };
+
+    let mut s = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>API</title>\n</head>\n<body>\n<h1>API</h1>\n<ul>\n");
+    for c in classes {
+        s.push_str(&format!("<li><a href=\"#{}\">{}</a></li>\n", c.name, c.name));
+    }
+    s.push_str("</ul>\n");
+    for c in classes {
+        s.push_str(&format!("<h2 id=\"{}\">class {}</h2>\n", c.name, c.name));
+        if !c.doc.is_empty() {
+            s.push_str(&doc(&c.doc));
+        }
+        for sr in &c.subroutines {
+            s.push_str(&format!("<h3 id=\"{}\"><code>{}</code></h3>\n", anchor(&c.name, Some(&sr.name)), signature(sr, &link)));
+            if !sr.doc.is_empty() {
+                s.push_str(&doc(&sr.doc));
+            }
+        }
+    }
+    s.push_str("</body>\n</html>\n");
+    s
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const SOURCE: &str = r#"// not a doc comment
+/**
+ * A point.
+ * See Math.abs.
+ */
+class Point {
+    field int x;
+
+    /** Makes a point. */
+    constructor Point new(int ax, Array a) {
+        let x = ax;
+        return this;
+    }
+
+    /* not a doc comment */
+    method boolean equals(Point other) { return x = other.getX(); }
+}
+"#;
+
+    #[test]
+    fn extract_class() {
+        let c = extract(SOURCE).unwrap();
+        assert_eq!(c.name, "Point");
+        assert_eq!(c.doc, "A point.\nSee Math.abs.");
+        assert_eq!(c.subroutines, vec![
+            SubroutineDoc {
+                kind: "constructor".to_string(),
+                return_type: "Point".to_string(),
+                name: "new".to_string(),
+                parameters: vec![("int".to_string(), "ax".to_string()), ("Array".to_string(), "a".to_string())],
+                doc: "Makes a point.".to_string(),
+            },
+            SubroutineDoc {
+                kind: "method".to_string(),
+                return_type: "boolean".to_string(),
+                name: "equals".to_string(),
+                parameters: vec![("Point".to_string(), "other".to_string())],
+                doc: "".to_string(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn cross_links() {
+        let math = extract("/** Math. */ class Math { /** Absolute value. */ function int abs(int x) { return x; } }").unwrap();
+        let classes = vec![extract(SOURCE).unwrap(), math];
+        let md = to_markdown(&classes);
+        assert!(md.contains("### constructor [Point](#Point) new(int ax, Array a)\n\nMakes a point.\n"));
+        assert!(md.contains("See [Math.abs](#Math.abs).\n"));
+        assert!(md.contains("<a name=\"Math.abs\"></a>\n\n### function int abs(int x)\n"));
+
+        let html = to_html(&classes);
+        assert!(html.contains("<h3 id=\"Point.equals\"><code>method boolean equals(<a href=\"#Point\">Point</a> other)</code></h3>\n"));
+        assert!(html.contains("<p>A point.<br>\nSee <a href=\"#Math.abs\">Math.abs</a>.</p>\n"));
+    }
+}
@@ -0,0 +1,260 @@
+use super::JackTokenizer::Span;
+
+// The parse tree the compiler builds, with the same node kinds as the XML
+// output, for --emit ast-json and --emit ast-sexp.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum AstNode {
+    // span is None for a node without tokens, e.g. an empty parameterList.
+    Node { kind: String, span: Option<Span>, children: Vec<AstNode> },
+    Token { kind: String, value: String, span: Span },
+    Identifier {
+        name: String,
+        category: String,
+        usage: String,
+        var_kind: Option<String>,
+        index: Option<i32>,
+        span: Span,
+    },
+}
+
+impl AstNode {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            AstNode::Node { span, .. } => *span,
+            AstNode::Token { span, .. } => Some(*span),
+            AstNode::Identifier { span, .. } => Some(*span),
+        }
+    }
+
+    pub fn to_json(&self, lines: &LineIndex) -> String {
+        let mut s = String::new();
+        write_json(self, lines, 0, &mut s);
+        s.push('\n');
+        s
+    }
+
+    pub fn to_sexp(&self, lines: &LineIndex) -> String {
+        let mut s = String::new();
+        write_sexp(self, lines, 0, &mut s);
+        s.push('\n');
+        s
+    }
+}
+
+// Builds the tree from the same open/close/token events as the XML writer.
+pub struct AstBuilder {
+    stack: Vec<AstNode>,
+    root: Option<AstNode>,
+}
+
+impl AstBuilder {
+    pub fn new() -> Self {
+        AstBuilder {
+            stack: vec![],
+            root: None,
+        }
+    }
+
+    pub fn open(&mut self, kind: &str) {
+        self.stack.push(AstNode::Node { kind: kind.to_string(), span: None, children: vec![] });
+    }
+
+    pub fn close(&mut self) {
+        let mut node = self.stack.pop().unwrap();
+        if let AstNode::Node { span, children, .. } = &mut node {
+            let spans: Vec<Span> = children.iter().filter_map(|c| c.span()).collect();
+            if let (Some(first), Some(last)) = (spans.first(), spans.last()) {
+                *span = Some(Span { start: first.start, end: last.end });
+            }
+        }
+        self.add(node);
+    }
+
+    pub fn add(&mut self, node: AstNode) {
+        match self.stack.last_mut() {
+            Some(AstNode::Node { children, .. }) => children.push(node),
+            _ => self.root = Some(node),
+        }
+    }
+
+    pub fn root(&self) -> Option<&AstNode> {
+        self.root.as_ref()
+    }
+
+    // Close the nodes still open and return the root.
+    pub fn finish(mut self) -> Option<AstNode> {
+        while !self.stack.is_empty() {
+            self.close();
+        }
+        self.root
+    }
+}
+
+// Converts byte offsets to 1-based line and column numbers.
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(source.bytes().enumerate().filter(|(_, b)| *b == b'\n').map(|(i, _)| i + 1));
+        LineIndex { line_starts: line_starts }
+    }
+
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(l) => l,
+            Err(l) => l - 1,
+        };
+        (line + 1, offset - self.line_starts[line] + 1)
+    }
+}
+
+fn write_json(node: &AstNode, lines: &LineIndex, level: usize, s: &mut String) {
+    let indent = " ".repeat(level);
+    s.push_str(&indent);
+    match node {
+        AstNode::Node { kind, span, children } => {
+            s.push_str(&format!("{{\"kind\": {}", json_string(kind)));
+            if let Some(span) = span {
+                s.push_str(&format!(", \"span\": {}", json_span(span, lines)));
+            }
+            s.push_str(", \"children\": [");
+            for (i, c) in children.iter().enumerate() {
+                s.push_str(if i == 0 { "\n" } else { ",\n" });
+                write_json(c, lines, level + 2, s);
+            }
+            if !children.is_empty() {
+                s.push('\n');
+                s.push_str(&indent);
+            }
+            s.push_str("]}");
+        },
+        AstNode::Token { kind, value, span } => {
+            let value = if kind == "integerConstant" { value.clone() } else { json_string(value) };
+            s.push_str(&format!("{{\"kind\": {}, \"value\": {}, \"span\": {}}}", json_string(kind), value, json_span(span, lines)));
+        },
+        AstNode::Identifier { name, category, usage, var_kind, index, span } => {
+            s.push_str(&format!("{{\"kind\": \"identifier\", \"name\": {}, \"category\": {}, \"usage\": {}",
+                json_string(name), json_string(category), json_string(usage)));
+            if let Some(k) = var_kind {
+                s.push_str(&format!(", \"varKind\": {}", json_string(k)));
+            }
+            if let Some(i) = index {
+                s.push_str(&format!(", \"index\": {}", i));
+            }
+            s.push_str(&format!(", \"span\": {}}}", json_span(span, lines)));
+        },
+    }
+}
+
+fn json_span(span: &Span, lines: &LineIndex) -> String {
+    let (sl, sc) = lines.position(span.start);
+    let (el, ec) = lines.position(span.end);
+    format!("{{\"start\": {{\"line\": {}, \"column\": {}}}, \"end\": {{\"line\": {}, \"column\": {}}}}}", sl, sc, el, ec)
+}
+
+fn json_string(s: &str) -> String {
+    let mut r = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            '\r' => r.push_str("\\r"),
+            '\t' => r.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == super::JackTokenizer::NEWLINE => r.push_str(&format!("\\u{:04x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+fn write_sexp(node: &AstNode, lines: &LineIndex, level: usize, s: &mut String) {
+    s.push_str(&" ".repeat(level));
+    match node {
+        AstNode::Node { kind, span, children } => {
+            s.push_str(&format!("({}", kind));
+            if let Some(span) = span {
+                s.push_str(&format!(" :span {}", sexp_span(span, lines)));
+            }
+            for c in children {
+                s.push('\n');
+                write_sexp(c, lines, level + 2, s);
+            }
+            s.push(')');
+        },
+        AstNode::Token { kind, value, span } => {
+            let value = if kind == "integerConstant" { value.clone() } else { sexp_string(value) };
+            s.push_str(&format!("({} {} :span {})", kind, value, sexp_span(span, lines)));
+        },
+        AstNode::Identifier { name, category, usage, var_kind, index, span } => {
+            s.push_str(&format!("(identifier {} :category {} :usage {}", sexp_string(name), category, usage));
+            if let Some(k) = var_kind {
+                s.push_str(&format!(" :kind {}", k));
+            }
+            if let Some(i) = index {
+                s.push_str(&format!(" :index {}", i));
+            }
+            s.push_str(&format!(" :span {})", sexp_span(span, lines)));
+        },
+    }
+}
+
+fn sexp_span(span: &Span, lines: &LineIndex) -> String {
+    let (sl, sc) = lines.position(span.start);
+    let (el, ec) = lines.position(span.end);
+    format!("({} {} {} {})", sl, sc, el, ec)
+}
+
+fn sexp_string(s: &str) -> String {
+    let mut r = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            super::JackTokenizer::NEWLINE => r.push_str("\\n"),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn line_index() {
+        let lines = LineIndex::new("class\r\n  Main\n{");
+        assert_eq!(lines.position(0), (1, 1));
+        assert_eq!(lines.position(9), (2, 3));
+        assert_eq!(lines.position(14), (3, 1));
+    }
+
+    #[test]
+    fn build_and_dump() {
+        let mut b = AstBuilder::new();
+        b.open("term");
+        b.add(AstNode::Token { kind: "stringConstant".to_string(), value: "a\"b".to_string(), span: Span { start: 2, end: 7 } });
+        b.open("expressionList");
+        b.close();
+        b.close();
+
+        let lines = LineIndex::new("x=\"a\\\"b\";");
+        assert_eq!(b.root().unwrap().to_json(&lines), r#"{"kind": "term", "span": {"start": {"line": 1, "column": 3}, "end": {"line": 1, "column": 8}}, "children": [
+  {"kind": "stringConstant", "value": "a\"b", "span": {"start": {"line": 1, "column": 3}, "end": {"line": 1, "column": 8}}},
+  {"kind": "expressionList", "children": []}
+]}
+"#);
+        assert_eq!(b.root().unwrap().to_sexp(&lines), r#"(term :span (1 3 1 8)
+  (stringConstant "a\"b" :span (1 3 1 8))
+  (expressionList))
+"#);
+    }
+}
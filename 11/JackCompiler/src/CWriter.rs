@@ -0,0 +1,581 @@
+use std::collections::HashMap;
+use super::Ast::AstNode;
+use super::ClassIndex::{ClassIndex, SubroutineKind};
+
+// The OS in C, put at the top of every translated program so that the
+// output builds on its own.
+pub const RUNTIME: &str = include_str!("../runtime/jack_os.c");
+
+// The classes the runtime implements. A program that brings its own Jack
+// version of one of them leaves the C version out.
+pub const OS_CLASSES: [&str; 8] = ["Math", "String", "Array", "Memory", "Output", "Screen", "Keyboard", "Sys"];
+
+// A class translated to C. The declarations of every class go before the
+// functions of any, so classes can call each other in any order.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct CClass {
+    pub name: String,
+    pub declarations: String,
+    pub functions: String,
+}
+
+struct Translator<'a> {
+    class_name: String,
+    index: Option<&'a ClassIndex>,
+    precedence: bool,
+    // variable name to type, of the class and of the current subroutine
+    types: HashMap<String, String>,
+    out: String,
+    indent: usize,
+    temps: usize,
+    // the continue label of each enclosing loop, None for while
+    loops: Vec<Option<usize>>,
+    labels: usize,
+    // labels a continue jumps to
+    continues: Vec<usize>,
+}
+
+// Translates the AST of a class to C. Every value is a 16 bit word kept in
+// the Hack RAM of the runtime, and each term is evaluated into a temporary
+// in the order the VM code evaluates it.
+pub fn translate(ast: &AstNode, index: Option<&ClassIndex>, precedence: bool) -> CClass {
+    let c = children(ast);
+    let mut t = Translator {
+        class_name: text(&c[1]),
+        index: index,
+        precedence: precedence,
+        types: HashMap::new(),
+        out: String::new(),
+        indent: 0,
+        temps: 0,
+        loops: vec![],
+        labels: 0,
+        continues: vec![],
+    };
+
+    let mut declarations = format!("/* class {} */\n", t.class_name);
+    let mut fields = vec![];
+    for node in c.iter().filter(|n| kind(n) == "classVarDec") {
+        for (name, var_kind, type_name) in definitions(node) {
+            if var_kind == "field" {
+                fields.push(name.clone());
+            }
+            else {
+                declarations.push_str(&format!("static word {}_s_{};\n", t.class_name, name));
+            }
+            t.types.insert(name, type_name);
+        }
+    }
+    if !fields.is_empty() {
+        // field i of an object is RAM[this + i], as in the VM
+        let members: Vec<String> = fields.iter().map(|f| format!("word f_{};", f)).collect();
+        declarations.push_str(&format!("struct {} {{ {} }};\n", t.class_name, members.join(" ")));
+        declarations.push_str(&format!("typedef char {}_layout[sizeof(struct {}) == {} * sizeof(word) ? 1 : -1];\n", t.class_name, t.class_name, fields.len()));
+        declarations.push_str(&format!("#define {}_this ((struct {} *)&RAM(this_))\n", t.class_name, t.class_name));
+    }
+
+    for node in c.iter().filter(|n| kind(n) == "subroutineDec") {
+        let signature = t.signature(node);
+        declarations.push_str(&format!("{};\n", signature));
+        t.subroutine(node, signature, fields.len());
+    }
+
+    CClass { name: t.class_name.clone(), declarations: declarations, functions: t.out }
+}
+
+// The whole program: the runtime, then every class.
+pub fn program(classes: &[CClass]) -> String {
+    let names: Vec<&str> = classes.iter().map(|c| c.name.as_str()).collect();
+    let mut s = format!("/* Translated from Jack by JackCompiler: {} */\n", names.join(", "));
+    for c in classes.iter().filter(|c| OS_CLASSES.contains(&c.name.as_str())) {
+        s.push_str(&format!("#define JACK_USER_{}\n", c.name));
+    }
+    s.push('\n');
+    s.push_str(RUNTIME);
+    for c in classes {
+        s.push('\n');
+        s.push_str(&c.declarations);
+    }
+    s.push('\n');
+    for c in classes {
+        s.push_str(&c.functions);
+    }
+    s.truncate(s.trim_end().len());
+    s.push('\n');
+    s
+}
+
+fn children(node: &AstNode) -> &[AstNode] {
+    match node {
+        AstNode::Node { children, .. } => children,
+        _ => &[],
+    }
+}
+
+fn kind(node: &AstNode) -> &str {
+    match node {
+        AstNode::Node { kind, .. } => kind,
+        AstNode::Token { kind, .. } => kind,
+        AstNode::Identifier { .. } => "identifier",
+    }
+}
+
+fn text(node: &AstNode) -> String {
+    match node {
+        AstNode::Token { value, .. } => value.clone(),
+        AstNode::Identifier { name, .. } => name.clone(),
+        _ => String::new(),
+    }
+}
+
+fn is_symbol(node: Option<&AstNode>, s: &str) -> bool {
+    match node {
+        Some(AstNode::Token { kind, value, .. }) => kind == "symbol" && value == s,
+        _ => false,
+    }
+}
+
+// (name, kind, type) of the declarations in a classVarDec, varDec or
+// parameterList.
+fn definitions(node: &AstNode) -> Vec<(String, String, String)> {
+    let c = children(node);
+    let mut defs = vec![];
+    for (i, child) in c.iter().enumerate() {
+        if let AstNode::Identifier { name, usage, var_kind: Some(k), .. } = child {
+            if usage != "defined" {
+                continue;
+            }
+            // type name, or [static|field|var] type name (, name)*
+            let type_name = if kind(node) == "parameterList" { text(&c[i - 1]) } else { text(&c[1]) };
+            defs.push((name.clone(), k.clone(), type_name));
+        }
+    }
+    defs
+}
+
+fn precedence_of(op: &str) -> i32 {
+    match op {
+        "*" | "/" => 3,
+        "+" | "-" => 2,
+        "<" | ">" | "=" => 1,
+        _ => 0,
+    }
+}
+
+impl<'a> Translator<'a> {
+    fn line(&mut self, s: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(s);
+        self.out.push('\n');
+    }
+
+    // Evaluates value now, into a new temporary.
+    fn temp(&mut self, value: String) -> String {
+        self.temps += 1;
+        let t = format!("t{}", self.temps);
+        self.line(&format!("word {} = {};", t, value));
+        t
+    }
+
+    fn signature(&self, sr: &AstNode) -> String {
+        let c = children(sr);
+        let mut params = vec![];
+        if text(&c[0]) == "method" {
+            params.push("word this_".to_string());
+        }
+        for node in c.iter().filter(|n| kind(n) == "parameterList") {
+            for (name, _, _) in definitions(node) {
+                params.push(format!("word a_{}", name));
+            }
+        }
+        if params.is_empty() {
+            params.push("void".to_string());
+        }
+        format!("word {}_{}({})", self.class_name, text(&c[2]), params.join(", "))
+    }
+
+    fn subroutine(&mut self, sr: &AstNode, signature: String, fields_count: usize) {
+        let c = children(sr);
+        let class_types = self.types.clone();
+        for node in c.iter().filter(|n| kind(n) == "parameterList") {
+            for (name, _, type_name) in definitions(node) {
+                self.types.insert(name, type_name);
+            }
+        }
+
+        self.temps = 0;
+        self.labels = 0;
+        self.continues.clear();
+        self.line(&signature);
+        self.line("{");
+        self.indent += 1;
+        if text(&c[0]) == "constructor" {
+            self.line(&format!("word this_ = Memory_alloc({});", fields_count));
+        }
+        let body = c.iter().find(|n| kind(n) == "subroutineBody").unwrap();
+        for node in children(body).iter().filter(|n| kind(n) == "varDec") {
+            for (name, _, type_name) in definitions(node) {
+                self.line(&format!("word l_{} = 0;", name));
+                self.types.insert(name, type_name);
+            }
+        }
+        let statements = children(body).iter().find(|n| kind(n) == "statements");
+        let mut returns = false;
+        if let Some(s) = statements {
+            self.statements(s);
+            returns = children(s).last().map_or(false, |l| kind(l) == "returnStatement");
+        }
+        if !returns {
+            self.line("return 0;");
+        }
+        self.indent -= 1;
+        self.line("}");
+        self.out.push('\n');
+        self.types = class_types;
+    }
+
+    fn statements(&mut self, node: &AstNode) {
+        for s in children(node) {
+            let c = children(s);
+            match kind(s) {
+                "letStatement" => self.assignment(&c[1..c.len() - 1]),
+                "ifStatement" => self.if_statement(c),
+                "whileStatement" => self.while_statement(c),
+                "forStatement" => self.for_statement(c),
+                "doStatement" => {
+                    let call = self.call(&c[1..c.len() - 1]);
+                    self.line(&format!("{};", call));
+                },
+                "returnStatement" => {
+                    let value = if c.len() == 3 { self.expression(&c[1]) } else { "0".to_string() };
+                    self.line(&format!("return {};", value));
+                },
+                "breakStatement" => self.line("break;"),
+                "continueStatement" => match self.loops.last() {
+                    Some(Some(label)) => {
+                        let label = *label;
+                        self.continues.push(label);
+                        self.line(&format!("goto continue_{};", label));
+                    },
+                    _ => self.line("continue;"),
+                },
+                _ => {},
+            }
+        }
+    }
+
+    // varName ([expression])? (+|-)? = expression
+    fn assignment(&mut self, c: &[AstNode]) {
+        let mut i = 1;
+        let mut address = None;
+        if is_symbol(c.get(1), "[") {
+            let offset = self.expression(&c[2]);
+            let base = self.temp(self.variable(&c[0]));
+            address = Some(self.temp(format!("(word)({} + {})", base, offset)));
+            i = 4;
+        }
+        let target = match &address {
+            Some(a) => format!("RAM({})", a),
+            None => self.variable(&c[0]),
+        };
+        let value = if is_symbol(c.get(i), "=") {
+            self.expression(&c[i + 1])
+        }
+        else {
+            // the current value is read before the right-hand side
+            let current = self.temp(target.clone());
+            let rhs = self.expression(&c[i + 2]);
+            self.binary(&text(&c[i]), current, rhs)
+        };
+        self.line(&format!("{} = {};", target, value));
+    }
+
+    // if ( expression ) { statements } (else { statements })?
+    fn if_statement(&mut self, c: &[AstNode]) {
+        let condition = self.expression(&c[2]);
+        self.line(&format!("if ({}) {{", condition));
+        let mut blocks = c.iter().filter(|n| kind(n) == "statements");
+        if let Some(s) = blocks.next() {
+            self.block(s);
+        }
+        if let Some(s) = blocks.next() {
+            self.line("} else {");
+            self.block(s);
+        }
+        self.line("}");
+    }
+
+    fn while_statement(&mut self, c: &[AstNode]) {
+        self.line("for (;;) {");
+        self.indent += 1;
+        let condition = self.expression(&c[2]);
+        self.line(&format!("if (!{}) break;", condition));
+        self.indent -= 1;
+        self.loops.push(None);
+        if let Some(s) = c.iter().find(|n| kind(n) == "statements") {
+            self.block(s);
+        }
+        self.loops.pop();
+        self.line("}");
+    }
+
+    // for ( assignment? ; expression ; assignment? ) { statements }
+    fn for_statement(&mut self, c: &[AstNode]) {
+        let semicolons: Vec<usize> = (0..c.len()).filter(|i| is_symbol(c.get(*i), ";")).collect();
+        let close = (semicolons[1]..c.len()).find(|i| is_symbol(c.get(*i), ")")).unwrap();
+        self.labels += 1;
+        let label = self.labels;
+
+        self.line("{");
+        self.indent += 1;
+        if semicolons[0] > 2 {
+            self.assignment(&c[2..semicolons[0]]);
+        }
+        self.line("for (;;) {");
+        self.indent += 1;
+        let condition = self.expression(&c[semicolons[0] + 1]);
+        self.line(&format!("if (!{}) break;", condition));
+        self.indent -= 1;
+        self.loops.push(Some(label));
+        if let Some(s) = c.iter().find(|n| kind(n) == "statements") {
+            self.block(s);
+        }
+        self.loops.pop();
+        self.indent += 1;
+        if self.continues.contains(&label) {
+            self.line(&format!("continue_{}: ;", label));
+        }
+        if close > semicolons[1] + 1 {
+            self.assignment(&c[semicolons[1] + 1..close]);
+        }
+        self.indent -= 1;
+        self.line("}");
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    fn block(&mut self, statements: &AstNode) {
+        self.indent += 1;
+        self.statements(statements);
+        self.indent -= 1;
+    }
+
+    // The C lvalue of a variable.
+    fn variable(&self, node: &AstNode) -> String {
+        let name = text(node);
+        match node {
+            // this.f() in a method
+            AstNode::Identifier { .. } if name == "this" => "this_".to_string(),
+            AstNode::Identifier { var_kind: Some(k), .. } => match k.as_str() {
+                "static" => format!("{}_s_{}", self.class_name, name),
+                "field" => format!("{}_this->f_{}", self.class_name, name),
+                "argument" => format!("a_{}", name),
+                _ => format!("l_{}", name),
+            },
+            _ => name,
+        }
+    }
+
+    // term (op term)*
+    fn expression(&mut self, e: &AstNode) -> String {
+        let c = children(e);
+        if self.precedence {
+            let mut pos = 0;
+            return self.climb(c, &mut pos, 1);
+        }
+        let mut value = self.term(&c[0]);
+        let mut i = 1;
+        while i + 1 < c.len() {
+            let rhs = self.term(&c[i + 1]);
+            value = self.binary(&text(&c[i]), value, rhs);
+            i += 2;
+        }
+        value
+    }
+
+    fn climb(&mut self, c: &[AstNode], pos: &mut usize, min: i32) -> String {
+        let mut value = self.term(&c[*pos]);
+        *pos += 1;
+        while *pos + 1 < c.len() && precedence_of(&text(&c[*pos])) >= min {
+            let op = text(&c[*pos]);
+            *pos += 1;
+            let rhs = self.climb(c, pos, precedence_of(&op) + 1);
+            value = self.binary(&op, value, rhs);
+        }
+        value
+    }
+
+    fn binary(&mut self, op: &str, a: String, b: String) -> String {
+        let value = match op {
+            "+" => format!("(word)({} + {})", a, b),
+            "-" => format!("(word)({} - {})", a, b),
+            "*" => format!("Math_multiply({}, {})", a, b),
+            "/" => format!("Math_divide({}, {})", a, b),
+            "&" => format!("{} & {}", a, b),
+            "|" => format!("{} | {}", a, b),
+            "<" => format!("-({} < {})", a, b),
+            ">" => format!("-({} > {})", a, b),
+            _ => format!("-({} == {})", a, b),
+        };
+        self.temp(value)
+    }
+
+    fn term(&mut self, t: &AstNode) -> String {
+        let c = children(t);
+        match c.first() {
+            Some(AstNode::Token { kind, value, .. }) if kind == "integerConstant" => value.clone(),
+            Some(AstNode::Token { kind, value, .. }) if kind == "stringConstant" => {
+                let codes: Vec<String> = value.chars().map(|ch| (ch as u32).to_string()).collect();
+                if codes.is_empty() {
+                    return self.temp("jack_string(0, 0)".to_string());
+                }
+                self.temp(format!("jack_string({}, (const word[]){{{}}})", codes.len(), codes.join(", ")))
+            },
+            Some(AstNode::Token { kind, value, .. }) if kind == "keyword" => match value.as_str() {
+                "true" => "-1".to_string(),
+                "this" => "this_".to_string(),
+                _ => "0".to_string(),
+            },
+            Some(AstNode::Token { value, .. }) if value == "(" => self.expression(&c[1]),
+            Some(AstNode::Token { value, .. }) if value == "-" => {
+                let operand = self.term(&c[1]);
+                self.temp(format!("(word)-{}", operand))
+            },
+            Some(AstNode::Token { value, .. }) if value == "~" => {
+                let operand = self.term(&c[1]);
+                self.temp(format!("~{}", operand))
+            },
+            Some(v) if c.len() == 1 => self.temp(self.variable(v)),
+            Some(v) if is_symbol(c.get(1), "[") => {
+                // the index is evaluated before the base
+                let offset = self.expression(&c[2]);
+                let base = self.temp(self.variable(v));
+                self.temp(format!("RAM({} + {})", base, offset))
+            },
+            Some(_) => {
+                let call = self.call(c);
+                self.temp(call)
+            },
+            None => "0".to_string(),
+        }
+    }
+
+    // subroutineName ( expressionList ) or (className|varName) . subroutineName ( expressionList )
+    fn call(&mut self, c: &[AstNode]) -> String {
+        let mut args = vec![];
+        let function;
+        let list;
+        if is_symbol(c.get(1), ".") {
+            list = &c[4];
+            let name = text(&c[2]);
+            if let AstNode::Identifier { var_kind: Some(_), .. } = &c[0] {
+                // the receiver is pushed before the arguments
+                let type_name = match self.types.get(&text(&c[0])) {
+                    Some(t) => t.clone(),
+                    None => self.class_name.clone(),
+                };
+                args.push(self.temp(self.variable(&c[0])));
+                function = format!("{}_{}", type_name, name);
+            }
+            else {
+                function = format!("{}_{}", text(&c[0]), name);
+            }
+        }
+        else {
+            list = &c[2];
+            let name = text(&c[0]);
+            let kind = self.index
+                .and_then(|i| i.get(&self.class_name))
+                .and_then(|info| info.subroutine(&name))
+                .map(|s| s.kind.clone());
+            if kind.map_or(true, |k| k == SubroutineKind::METHOD) {
+                args.push("this_".to_string());
+            }
+            function = format!("{}_{}", self.class_name, name);
+        }
+        for e in children(list).iter().filter(|n| kind(n) == "expression") {
+            let value = self.expression(e);
+            args.push(value);
+        }
+        format!("{}({})", function, args.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::io;
+    use super::super::CompilationEngine::CompilationEngine;
+
+    fn translate_source(source: &str, precedence: bool) -> CClass {
+        let mut c = CompilationEngine::new(io::Cursor::new(source.as_bytes()), io::sink(), None);
+        c.set_precedence(precedence);
+        c.set_ast(true);
+        c.compileClass();
+        translate(&c.take_ast().unwrap(), None, precedence)
+    }
+
+    #[test]
+    fn translate_class() {
+        let class = translate_source(r#"class Point {
+    field int x, y;
+    static Point origin;
+    constructor Point new(int ax) { let x = ax; return this; }
+    method int sum(Point other) { return x + other.getX(); }
+    method void loop(Array a) {
+        var int i;
+        while (i < 3) { let a[i] = i; let i = i + 1; }
+        for (i = 0; i < 3; i += 1) { if (i = 1) { continue; } do Output.printString("hi"); }
+        return;
+    }
+}
+"#, false);
+        assert_eq!(class.declarations, "/* class Point */
+static word Point_s_origin;
+struct Point { word f_x; word f_y; };
+typedef char Point_layout[sizeof(struct Point) == 2 * sizeof(word) ? 1 : -1];
+#define Point_this ((struct Point *)&RAM(this_))
+word Point_new(word a_ax);
+word Point_sum(word this_, word a_other);
+word Point_loop(word this_, word a_a);
+");
+        assert!(class.functions.starts_with("word Point_new(word a_ax)
+{
+    word this_ = Memory_alloc(2);
+    word t1 = a_ax;
+    Point_this->f_x = t1;
+    return this_;
+}
+"));
+        assert!(class.functions.contains("    word t1 = Point_this->f_x;
+    word t2 = a_other;
+    word t3 = Point_getX(t2);
+    word t4 = (word)(t1 + t3);
+    return t4;
+"));
+        assert!(class.functions.contains("        word t3 = l_i;
+        word t4 = a_a;
+        word t5 = (word)(t4 + t3);
+        word t6 = l_i;
+        RAM(t5) = t6;
+"));
+        assert!(class.functions.contains("                goto continue_1;
+            }
+            word t13 = jack_string(2, (const word[]){104, 105});
+            Output_printString(t13);
+            continue_1: ;
+            word t14 = l_i;
+"));
+    }
+
+    #[test]
+    fn precedence() {
+        let source = "class Main { function int f() { return 1 + 2 * 3; } }";
+        assert!(translate_source(source, false).functions.contains("word t1 = (word)(1 + 2);\n    word t2 = Math_multiply(t1, 3);\n"));
+        assert!(translate_source(source, true).functions.contains("word t1 = Math_multiply(2, 3);\n    word t2 = (word)(1 + t1);\n"));
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::io;
+use super::JackTokenizer::*;
+
+// Declarations of every class in the input set, collected before any class
+// is compiled so calls across classes can be checked.
+pub struct ClassIndex {
+    classes: HashMap<String, ClassInfo>,
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum SubroutineKind {
+    CONSTRUCTOR,
+    FUNCTION,
+    METHOD,
+}
+
+impl std::fmt::Display for SubroutineKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match *self {
+            SubroutineKind::CONSTRUCTOR => "constructor",
+            SubroutineKind::FUNCTION => "function",
+            SubroutineKind::METHOD => "method",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct SubroutineInfo {
+    pub name: String,
+    pub kind: SubroutineKind,
+    pub return_type: String,
+    pub parameter_types: Vec<String>,
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct ClassInfo {
+    pub name: String,
+    pub fields_count: i32,
+    pub statics_count: i32,
+    pub subroutines: Vec<SubroutineInfo>,
+}
+
+impl ClassInfo {
+    pub fn subroutine(&self, name: &str) -> Option<&SubroutineInfo> {
+        self.subroutines.iter().find(|s| s.name == name)
+    }
+}
+
+impl ClassIndex {
+    pub fn new() -> Self {
+        ClassIndex {
+            classes: HashMap::new(),
+        }
+    }
+
+    // Returns an error when the class name is already taken.
+    pub fn add(&mut self, info: ClassInfo) -> Result<(), String> {
+        if self.classes.contains_key(&info.name) {
+            return Err(format!("class {} is defined more than once", info.name));
+        }
+        self.classes.insert(info.name.clone(), info);
+        Ok(())
+    }
+
+    pub fn get(&self, class_name: &str) -> Option<&ClassInfo> {
+        self.classes.get(class_name)
+    }
+
+    pub fn contains(&self, class_name: &str) -> bool {
+        self.classes.contains_key(class_name)
+    }
+
+    pub fn class_names(&self) -> Vec<String> {
+        let mut v: Vec<String> = self.classes.keys().cloned().collect();
+        v.sort();
+        v
+    }
+}
+
+// Read only the declarations of a class: class name, class variables and
+// subroutine signatures. Subroutine bodies are skipped by brace matching.
+pub fn scan_class<R: io::Read + io::Seek>(reader: R) -> Result<ClassInfo, String> {
+    let mut tokens = JackTokenizer::new(reader);
+    let mut next = || tokens.next().ok_or_else(|| "unexpected end of file".to_string());
+
+    expect(next()?, Token::Keyword(KeywordType::CLASS))?;
+    let name = identifier(next()?)?;
+    expect(next()?, Token::Symbol("{".to_string()))?;
+
+    let mut info = ClassInfo {
+        name: name,
+        fields_count: 0,
+        statics_count: 0,
+        subroutines: vec![],
+    };
+
+    loop {
+        let token = next()?;
+        match token {
+            Token::Keyword(KeywordType::STATIC) | Token::Keyword(KeywordType::FIELD) => {
+                // type varName (, varName)* ;
+                next()?;
+                let mut vars = 0;
+                loop {
+                    identifier(next()?)?;
+                    vars += 1;
+                    if next()? == Token::Symbol(";".to_string()) {
+                        break;
+                    }
+                }
+                if token == Token::Keyword(KeywordType::FIELD) {
+                    info.fields_count += vars;
+                }
+                else {
+                    info.statics_count += vars;
+                }
+            },
+            Token::Keyword(KeywordType::CONSTRUCTOR)
+            | Token::Keyword(KeywordType::FUNCTION)
+            | Token::Keyword(KeywordType::METHOD) => {
+                let kind = match token {
+                    Token::Keyword(KeywordType::CONSTRUCTOR) => SubroutineKind::CONSTRUCTOR,
+                    Token::Keyword(KeywordType::FUNCTION) => SubroutineKind::FUNCTION,
+                    _ => SubroutineKind::METHOD,
+                };
+                let return_type = type_name(next()?)?;
+                let sr_name = identifier(next()?)?;
+                expect(next()?, Token::Symbol("(".to_string()))?;
+
+                // parameterList
+                let mut parameter_types = vec![];
+                let mut t = next()?;
+                while t != Token::Symbol(")".to_string()) {
+                    if t == Token::Symbol(",".to_string()) {
+                        t = next()?;
+                    }
+                    parameter_types.push(type_name(t)?);
+                    identifier(next()?)?;
+                    t = next()?;
+                }
+
+                // subroutineBody
+                expect(next()?, Token::Symbol("{".to_string()))?;
+                let mut depth = 1;
+                while depth > 0 {
+                    match next()? {
+                        Token::Symbol(s) if s == "{" => depth += 1,
+                        Token::Symbol(s) if s == "}" => depth -= 1,
+                        _ => {},
+                    }
+                }
+
+                info.subroutines.push(SubroutineInfo {
+                    name: sr_name,
+                    kind: kind,
+                    return_type: return_type,
+                    parameter_types: parameter_types,
+                });
+            },
+            Token::Symbol(ref s) if s == "}" => break,
+            t => return Err(format!("unexpected {:?} in class {}", t, info.name)),
+        }
+    }
+    Ok(info)
+}
+
+fn expect(token: Token, expected: Token) -> Result<(), String> {
+    if token == expected {
+        Ok(())
+    }
+    else {
+        Err(format!("expected {:?}, found {:?}", expected, token))
+    }
+}
+
+fn identifier(token: Token) -> Result<String, String> {
+    match token {
+        Token::Identifier(s) => Ok(s),
+        t => Err(format!("expected identifier, found {:?}", t)),
+    }
+}
+
+fn type_name(token: Token) -> Result<String, String> {
+    match token {
+        Token::Keyword(k @ KeywordType::INT)
+        | Token::Keyword(k @ KeywordType::CHAR)
+        | Token::Keyword(k @ KeywordType::BOOLEAN)
+        | Token::Keyword(k @ KeywordType::VOID) => Ok(convert_keyword(k)),
+        Token::Identifier(s) => Ok(s),
+        t => Err(format!("expected type, found {:?}", t)),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn scan_square() {
+        let s = std::fs::File::open("Square/Square.jack").unwrap();
+        let info = scan_class(s).unwrap();
+        assert_eq!(info.name, "Square");
+        assert_eq!(info.fields_count, 3);
+        assert_eq!(info.statics_count, 0);
+
+        let new = info.subroutine("new").unwrap();
+        assert_eq!(new.kind, SubroutineKind::CONSTRUCTOR);
+        assert_eq!(new.return_type, "Square");
+        assert_eq!(new.parameter_types, vec!["int", "int", "int"]);
+
+        let dispose = info.subroutine("dispose").unwrap();
+        assert_eq!(dispose.kind, SubroutineKind::METHOD);
+        assert_eq!(dispose.return_type, "void");
+        assert!(dispose.parameter_types.is_empty());
+    }
+
+    #[test]
+    fn duplicate_class() {
+        let s = io::Cursor::new("class Main { function void main() { return; } }");
+        let info = scan_class(s).unwrap();
+        let mut index = ClassIndex::new();
+        assert!(index.add(info.clone()).is_ok());
+        assert!(index.add(info).is_err());
+    }
+}
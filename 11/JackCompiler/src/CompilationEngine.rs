@@ -2,6 +2,10 @@ use std::io::{self, BufRead, Read, Seek, Write};
 use super::JackTokenizer::*;
 use super::SymbolTable::*;
 use super::VMWriter::*;
+use super::ClassIndex::*;
+use super::Ast::*;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
 
 pub struct CompilationEngine<R: io::Read + io::Seek, W: io::Write> {
     tokenizer: JackTokenizer<R>,
@@ -19,8 +23,48 @@ pub struct CompilationEngine<R: io::Read + io::Seek, W: io::Write> {
     is_constructor: bool,
     is_method: bool,
     fields_count: i32,
+    class_index: Option<Arc<ClassIndex>>,
+    errors: Vec<String>,
+    string_pool: Option<HashMap<String, i32>>,
+    std_mode: bool,
+    precedence_mode: bool,
+    warnings: Vec<String>,
+    // (break label, continue label) of the enclosing loops
+    loop_labels: Vec<(String, String)>,
+    ast: Option<AstBuilder>,
+    // where each error and warning was found
+    error_spans: Vec<Span>,
+    warning_spans: Vec<Span>,
+    // spans of the previous and the current token
+    prev_span: Span,
+    span: Span,
+    // runtime check helpers used by the class, with --checked
+    checks: Option<BTreeSet<&'static str>>,
+    source: Option<SourceInfo>,
+    // "// File.jack:line statement" before each statement
+    annotate: bool,
 }
 
+// The Jack source, for annotations and the source map.
+struct SourceInfo {
+    file_name: String,
+    text: String,
+    lines: Vec<String>,
+    line_index: LineIndex,
+    // the spans of all tokens, read on the first annotation
+    tokens: Vec<Span>,
+}
+
+// Sys.error codes of the runtime checks.
+pub const ERR_NULL_ARRAY: i32 = 30;
+pub const ERR_ARRAY_BOUNDS: i32 = 31;
+pub const ERR_NULL_RECEIVER: i32 = 32;
+pub const ERR_NULL_THIS: i32 = 33;
+pub const ERR_DIVISION_BY_ZERO: i32 = 34;
+
+// Marks the header of an array from a checked Array.new.
+const ARRAY_MAGIC: i32 = 23294;
+
 enum NodeType {
     CLASS,
     CLASS_VAR_DEC,
@@ -36,7 +80,10 @@ enum NodeType {
     RETURN_STATEMENT,
     EXPRESSION_LIST,
     EXPRESSION,
-    TERM
+    TERM,
+    FOR_STATEMENT,
+    BREAK_STATEMENT,
+    CONTINUE_STATEMENT,
 }
 
 #[derive(Clone)]
@@ -126,14 +173,287 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
             is_constructor: false,
             is_method: false,
             fields_count: 0,
+            class_index: None,
+            errors: vec![],
+            string_pool: None,
+            std_mode: false,
+            precedence_mode: false,
+            warnings: vec![],
+            loop_labels: vec![],
+            ast: None,
+            error_spans: vec![],
+            warning_spans: vec![],
+            prev_span: Span { start: 0, end: 0 },
+            span: Span { start: 0, end: 0 },
+            checks: None,
+            source: None,
+            annotate: false,
+        }
+    }
+
+    // Build the parse tree along with the XML, for --emit ast-json/ast-sexp.
+    pub fn set_ast(&mut self, on: bool) {
+        self.ast = if on { Some(AstBuilder::new()) } else { None };
+    }
+
+    pub fn ast(&self) -> Option<&AstNode> {
+        self.ast.as_ref().and_then(|b| b.root())
+    }
+
+    // The tree so far, with the open nodes closed, e.g. after compileClass
+    // panicked on a syntax error.
+    pub fn take_ast(&mut self) -> Option<AstNode> {
+        self.ast.take().and_then(|b| b.finish())
+    }
+
+    // Span of the current token.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    // Reject the language extensions (for, else if, +=, -=, break,
+    // continue and string escapes) to keep to the course's Jack.
+    pub fn set_std(&mut self, on: bool) {
+        self.std_mode = on;
+        self.tokenizer.set_escapes(!on);
+    }
+
+    // Parse binary operators with conventional precedence instead of
+    // strictly left to right.
+    pub fn set_precedence(&mut self, on: bool) {
+        self.precedence_mode = on;
+    }
+
+    // Check for null pointers, division by zero and, on arrays from
+    // Array.new, out-of-range indexes at runtime. A failed check calls
+    // Sys.error with one of the ERR_ codes.
+    pub fn set_checked(&mut self, on: bool) {
+        self.checks = if on { Some(BTreeSet::new()) } else { None };
+    }
+
+    // Name of a check helper of the class, which is written at its end.
+    fn check_helper(&mut self, helper: &'static str) -> String {
+        self.checks.as_mut().unwrap().insert(helper);
+        get_classfunc_name(&self.class_name, helper)
+    }
+
+    // p on the stack is left there if it is not null.
+    fn write_null_check(&mut self, code: i32) {
+        if self.checks.is_some() {
+            self.vw.writePush(Segment::CONST, code);
+            let f = self.check_helper("$nonNull");
+            self.vw.writeCall(&f, 2);
+        }
+    }
+
+    // index and base on the stack are replaced with the address
+    fn write_array_address(&mut self) {
+        if self.checks.is_some() {
+            let f = self.check_helper("$index");
+            self.vw.writeCall(&f, 2);
+        }
+        else {
+            self.vw.writeArithmetic(Command::ADD);
+        }
+    }
+
+    // Array.new and Array.dispose of arrays with a size header.
+    fn checked_call_name(&mut self, class_name: &str, sr_name: &str) -> String {
+        if self.checks.is_some() && class_name == "Array" {
+            if sr_name == "new" {
+                return self.check_helper("$newArray");
+            }
+            if sr_name == "dispose" {
+                return self.check_helper("$disposeArray");
+            }
+        }
+        get_classfunc_name(class_name, sr_name)
+    }
+
+    // The source being compiled, which annotations and the source map
+    // refer to.
+    pub fn set_source(&mut self, file_name: &str, source: &str) {
+        self.source = Some(SourceInfo {
+            file_name: file_name.to_string(),
+            text: source.to_string(),
+            lines: source.lines().map(|l| l.trim().to_string()).collect(),
+            line_index: LineIndex::new(source),
+            tokens: vec![],
+        });
+    }
+
+    // Write each statement as a comment before its VM code. Needs set_source.
+    pub fn set_annotate(&mut self, on: bool) {
+        self.annotate = on;
+    }
+
+    // Needs set_source.
+    pub fn set_source_map(&mut self, on: bool) {
+        self.vw.set_source_map(on);
+    }
+
+    // "vm-line File.jack:line:column" for each VM command.
+    pub fn source_map(&self) -> Option<String> {
+        let (map, source) = match (self.vw.source_map(), &self.source) {
+            (Some(m), Some(s)) => (m, s),
+            _ => return None,
+        };
+        Some(map.iter().map(|(vm_line, line, column)| format!("{} {}:{}:{}\n", vm_line, source.file_name, line, column)).collect())
+    }
+
+    // The code from here on comes from the current token.
+    fn write_source_position(&mut self) {
+        let s = match &self.source {
+            Some(s) => s,
+            None => return,
+        };
+        let (line, column) = s.line_index.position(self.span.start);
+        let at = format!("{}:{}", s.file_name, line);
+        self.vw.set_position(line, column);
+        if self.annotate {
+            let comment = format!("{} {}", at, self.statement_text());
+            self.vw.writeComment(&comment);
+        }
+    }
+
+    // The source of the statement or subroutine declaration at the current
+    // token, on one line: up to its ';', or for if, while, for and
+    // declarations up to the ')' that closes its parentheses.
+    fn statement_text(&mut self) -> String {
+        let escapes = !self.std_mode;
+        let start = self.span.start;
+        let s = self.source.as_mut().unwrap();
+        if s.tokens.is_empty() {
+            let mut t = JackTokenizer::new(io::Cursor::new(s.text.clone().into_bytes()));
+            t.set_escapes(escapes);
+            while t.hasMoreTokens() {
+                t.advance();
+                s.tokens.push(t.span());
+            }
+        }
+        let text = &s.text;
+        let first = match s.tokens.iter().position(|t| t.start == start) {
+            Some(i) => i,
+            None => return s.lines.get(s.line_index.position(start).0 - 1).cloned().unwrap_or_default(),
+        };
+        let until_semicolon = ["let", "do", "return", "break", "continue"].contains(&&text[s.tokens[first].start..s.tokens[first].end]);
+        let mut depth = 0;
+        let mut end = s.tokens[first].end;
+        for t in &s.tokens[first..] {
+            let token = &text[t.start..t.end];
+            end = t.end;
+            match token {
+                "(" | "[" => depth += 1,
+                ")" | "]" => depth -= 1,
+                _ => {},
+            }
+            let done = if until_semicolon { token == ";" && depth == 0 } else { token == ")" && depth == 0 };
+            if done || (!until_semicolon && token == "{") {
+                break;
+            }
+        }
+        text[start..end].lines().map(|l| l.trim()).collect::<Vec<&str>>().join(" ")
+    }
+
+    pub fn warnings(&self) -> &Vec<String> {
+        &self.warnings
+    }
+
+    // Same order as warnings().
+    pub fn warning_spans(&self) -> &Vec<Span> {
+        &self.warning_spans
+    }
+
+    fn warning(&mut self, message: String) {
+        let s = format!("{}: {}", get_classfunc_name(&self.class_name, &self.subroutine_name), message);
+        self.warnings.push(s);
+        self.warning_spans.push(self.span);
+    }
+
+    fn check_extension(&mut self, what: &str) {
+        if self.std_mode {
+            self.error(format!("{} are not part of standard Jack", what));
+        }
+    }
+
+    // Build each distinct string constant once, in an accessor function that
+    // keeps it in a static of the class. Pooled strings are shared, so they
+    // must not be disposed.
+    pub fn set_string_pool(&mut self, on: bool) {
+        self.string_pool = if on { Some(HashMap::new()) } else { None };
+    }
+
+    // Check calls into other classes against the declarations in the index.
+    pub fn set_class_index(&mut self, index: Arc<ClassIndex>) {
+        self.class_index = Some(index);
+    }
+
+    pub fn errors(&self) -> &Vec<String> {
+        &self.errors
+    }
+
+    // Same order as errors().
+    pub fn error_spans(&self) -> &Vec<Span> {
+        &self.error_spans
+    }
+
+    fn error(&mut self, message: String) {
+        let s = format!("{}: {}", get_classfunc_name(&self.class_name, &self.subroutine_name), message);
+        self.errors.push(s);
+        self.error_spans.push(self.span);
+    }
+
+    // subroutineName(...) is a method call on this.
+    fn check_local_call(&mut self, sr_name: &str, args: i32) {
+        let class_name = self.class_name.clone();
+        let errors = self.errors.len();
+        self.check_call(&class_name, sr_name, args, true);
+        if errors == self.errors.len() && !self.is_method && !self.is_constructor
+        && self.class_index.as_ref().map_or(false, |i| i.contains(&class_name)) {
+            self.error(format!("method {} is called from a function", sr_name));
         }
     }
 
+    // Classes missing from the index (e.g. the OS when it is not part of
+    // the input) are not checked.
+    fn check_call(&mut self, class_name: &str, sr_name: &str, args: i32, is_instance: bool) {
+        let index = match &self.class_index {
+            Some(i) => i.clone(),
+            None => return,
+        };
+        let class = match index.get(class_name) {
+            Some(c) => c,
+            None => return,
+        };
+        let name = get_classfunc_name(class_name, sr_name);
+        let sr = match class.subroutine(sr_name) {
+            Some(sr) => sr,
+            None => {
+                self.error(format!("{} is not defined", name));
+                return;
+            },
+        };
+        if is_instance && sr.kind != SubroutineKind::METHOD {
+            self.error(format!("{} is a {}, but is called as a method", name, sr.kind));
+        }
+        else if !is_instance && sr.kind == SubroutineKind::METHOD {
+            self.error(format!("{} is a method, but is called without an object", name));
+        }
+        if sr.parameter_types.len() as i32 != args {
+            self.error(format!("{} expects {} arguments, but got {}", name, sr.parameter_types.len(), args));
+        }
+    }
+
+    // Fold constants and simplify expressions before they reach the .vm file.
+    pub fn set_optimize(&mut self, on: bool) {
+        self.vw.set_optimize(on);
+    }
+
     fn consume_eq(&mut self, tk: &Token) -> bool {
         if self.current_token != *tk {
             return false;
         }
-        self.current_token = self.tokenizer.next().unwrap();
+        self.next_token();
         true
     }
 
@@ -141,17 +461,26 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
         if self.is_lookahead {
             return;
         }
-        self.current_token = self.tokenizer.next().unwrap();
+        self.next_token();
         self.is_lookahead = true;
     }
 
+    fn next_token(&mut self) {
+        self.current_token = self.tokenizer.next().unwrap();
+        self.prev_span = self.span;
+        self.span = self.tokenizer.span();
+    }
+
     fn write_node_start(&mut self, node_type: NodeType) {
-        let s = indentation(&create_open_tag(&convert_node(node_type)), self.level);
+        let name = convert_node(node_type);
+        if let Some(b) = &mut self.ast { b.open(&name); }
+        let s = indentation(&create_open_tag(&name), self.level);
         if let Some(w) = &mut self.fs { w.write_all(s.as_bytes()); }
         self.level += 2;
     }
 
     fn write_node_end(&mut self, node_type: NodeType) {
+        if let Some(b) = &mut self.ast { b.close(); }
         self.level -= 2;
         let s = indentation(&create_close_tag(&convert_node(node_type)), self.level);
         if let Some(w) = &mut self.fs { w.write_all(s.as_bytes()); }
@@ -173,6 +502,19 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
     }
 
     fn write_identifier_info(&mut self, info: &IdentifierInfo) {
+        // The identifier may be followed by the token that was read ahead
+        // to tell a call from a variable.
+        let span = if self.current_token == Token::Identifier(info.name.clone()) { self.span } else { self.prev_span };
+        if let Some(b) = &mut self.ast {
+            b.add(AstNode::Identifier {
+                name: info.name.clone(),
+                category: info.cat.to_string(),
+                usage: info.usage.to_string(),
+                var_kind: info.varKind.as_ref().map(|k| k.to_string()),
+                index: info.index,
+                span: span,
+            });
+        }
         let l = self.level;
         let s = to_identifier_xml_elem(info, l);
         if let Some(w) = &mut self.fs { w.write_all(s.as_bytes()); }
@@ -182,6 +524,16 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
     fn write_token_with_consume(&mut self) {
         let l = self.level;
         let s = to_xml_elem(self.get_current_token(), l);
+        if let Some(b) = &mut self.ast {
+            let (kind, value) = match &self.current_token {
+                Token::Symbol(sym) => ("symbol".to_string(), sym.clone()),
+                t => {
+                    let [kind, value] = convert_token_to_strings(t);
+                    (kind, value)
+                },
+            };
+            b.add(AstNode::Token { kind: kind, value: value, span: self.span });
+        }
         if let Some(w) = &mut self.fs { w.write_all(s.as_bytes()); }
         self.is_lookahead = false;
     }
@@ -230,6 +582,10 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
         self.write_token_with_consume();
 
         self.write_node_end(NodeType::CLASS);
+
+        self.write_string_accessors();
+        self.write_check_helpers();
+        self.vw.flush_pending();
     }
 
     pub fn compileClassVarDec(&mut self) {
@@ -316,6 +672,8 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
         // Clear Subroutine Symbol Table
         self.table.startSubroutine();
 
+        self.write_source_position();
+
         // constructor/function/method
         self.write_token_with_consume();
 
@@ -500,6 +858,7 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
         // this = arg0
         if self.is_method {
             self.vw.writePush(Segment::ARG, 0);
+            self.write_null_check(ERR_NULL_THIS);
             self.vw.writePop(Segment::POINTER, 0);
         }
 
@@ -583,12 +942,23 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
         
         loop {
             self.consume();
+            let is_statement = match &self.current_token {
+                Token::Keyword(k) => [KeywordType::LET, KeywordType::IF, KeywordType::WHILE, KeywordType::DO, KeywordType::RETURN].contains(k),
+                Token::Identifier(s) => s == "for" || s == "break" || s == "continue",
+                _ => false,
+            };
+            if is_statement {
+                self.write_source_position();
+            }
             match self.current_token {
                 Token::Keyword(KeywordType::LET) => self.compileLet(),
                 Token::Keyword(KeywordType::IF) => self.compileIf(),
                 Token::Keyword(KeywordType::WHILE) => self.compileWhile(),
                 Token::Keyword(KeywordType::DO) => self.compileDo(),
                 Token::Keyword(KeywordType::RETURN) => self.compileReturn(),
+                // extensions, "for", "break" and "continue" are not reserved words
+                Token::Identifier(ref s) if s == "for" => self.compileFor(),
+                Token::Identifier(ref s) if s == "break" || s == "continue" => self.compileBreak(),
                 _ => break, // should be }
             }
         }
@@ -602,6 +972,16 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
         // let
         self.write_token_with_consume();
 
+        self.compileAssignment();
+
+        // ;
+        self.write_token_with_consume();
+
+        self.write_node_end(NodeType::LET_STATEMENT);
+    }
+
+    // varName ([expression])? (=|+=|-=) expression
+    fn compileAssignment(&mut self) {
         // varName
         self.consume();
         let var_name = self.get_current_token_name();
@@ -631,20 +1011,40 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
 
             // convert address calculation
             self.vw.writePush(convert_varKind_to_segment(&info.varKind.as_ref().unwrap()), info.index.clone().unwrap());
-            self.vw.writeArithmetic(Command::ADD);
+            self.write_array_address();
+        }
 
-            // = 
+        // + or - of += and -=
+        self.consume();
+        let mut compound_op = None;
+        if self.current_token == Token::Symbol("+".to_string())
+        || self.current_token == Token::Symbol("-".to_string()) {
+            self.check_extension("compound assignments");
+            compound_op = Some(self.current_token.clone());
             self.write_token_with_consume();
+
+            // current value
+            if is_array {
+                // keep the address and read through it
+                self.vw.writePop(Segment::TEMP, 0);
+                self.vw.writePush(Segment::TEMP, 0);
+                self.vw.writePush(Segment::TEMP, 0);
+                self.vw.writePop(Segment::POINTER, 1);
+                self.vw.writePush(Segment::THAT, 0);
+            }
+            else {
+                self.vw.writePush(convert_varKind_to_segment(&info.varKind.as_ref().unwrap()), info.index.unwrap());
+            }
         }
-        else {
-            // = 
-            self.write_token_with_consume();        
-        }
+
+        // = 
+        self.write_token_with_consume();
 
         self.compileExpression();
 
-        // ;
-        self.write_token_with_consume();
+        if let Some(op) = compound_op {
+            self.write_arithmetic(&op);
+        }
 
         if is_array {
             // save expression result to temp 
@@ -659,8 +1059,6 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
             // assign
             self.vw.writePop(convert_varKind_to_segment(&info.varKind.unwrap()), info.index.unwrap());
         }
-        
-        self.write_node_end(NodeType::LET_STATEMENT);
     }
 
     pub fn compileIf(&mut self) {
@@ -703,13 +1101,24 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
             // else
             self.write_token_with_consume();
 
-            // {
-            self.write_token_with_consume();
+            self.consume();
+            if self.current_token == Token::Keyword(KeywordType::IF) {
+                // else if: the nested if is the whole else block
+                self.check_extension("else if chains");
+                self.write_node_start(NodeType::STATEMENTS);
+                self.write_source_position();
+                self.compileIf();
+                self.write_node_end(NodeType::STATEMENTS);
+            }
+            else {
+                // {
+                self.write_token_with_consume();
 
-            self.compileStatementes();
+                self.compileStatementes();
 
-            // }
-            self.write_token_with_consume();
+                // }
+                self.write_token_with_consume();
+            }
 
             self.vw.writeLabel(&format!("IF_END{}", if_label));
         }
@@ -742,7 +1151,9 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
         // {
         self.write_token_with_consume();
 
+        self.loop_labels.push((format!("WHILE_END{}", while_label), format!("WHILE_EXP{}", while_label)));
         self.compileStatementes();
+        self.loop_labels.pop();
         self.vw.writeGoto(&format!("WHILE_EXP{}", while_label)); 
 
         // }
@@ -752,6 +1163,100 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
         self.write_node_end(NodeType::WHILE_STATEMENT);
     }
 
+    // for (init; condition; step) { statements }
+    // vm
+    // (init)
+    // label WHILE_EXPX
+    // (condition)
+    // not
+    // if-goto WHILE_ENDX
+    // (statements)
+    // label WHILE_STEPX
+    // (step)
+    // goto WHILE_EXPX
+    // label WHILE_ENDX
+    pub fn compileFor(&mut self) {
+        self.write_node_start(NodeType::FOR_STATEMENT);
+        self.check_extension("for statements");
+        let while_label = self.next_while_label;
+        self.next_while_label += 1;
+
+        // for
+        self.write_token_with_consume();
+
+        // (
+        self.write_token_with_consume();
+
+        // init
+        self.consume();
+        if self.current_token != Token::Symbol(";".to_string()) {
+            self.compileAssignment();
+        }
+
+        // ;
+        self.write_token_with_consume();
+
+        self.vw.writeLabel(&format!("WHILE_EXP{}", while_label));
+        self.compileExpression();
+        self.vw.writeArithmetic(Command::NOT);
+        self.vw.writeIf(&format!("WHILE_END{}", while_label));
+
+        // ;
+        self.write_token_with_consume();
+
+        // step is written after the body
+        self.vw.begin_capture();
+        self.consume();
+        if self.current_token != Token::Symbol(")".to_string()) {
+            self.compileAssignment();
+        }
+        let step = self.vw.end_capture();
+
+        // )
+        self.write_token_with_consume();
+
+        // {
+        self.write_token_with_consume();
+
+        self.loop_labels.push((format!("WHILE_END{}", while_label), format!("WHILE_STEP{}", while_label)));
+        self.compileStatementes();
+        self.loop_labels.pop();
+
+        // }
+        self.write_token_with_consume();
+
+        self.vw.writeLabel(&format!("WHILE_STEP{}", while_label));
+        self.vw.write_commands(step);
+        self.vw.writeGoto(&format!("WHILE_EXP{}", while_label));
+        self.vw.writeLabel(&format!("WHILE_END{}", while_label));
+        self.write_node_end(NodeType::FOR_STATEMENT);
+    }
+
+    // break; or continue; inside while and for
+    pub fn compileBreak(&mut self) {
+        let is_break = self.current_token == Token::Identifier("break".to_string());
+        let node_type = if is_break { NodeType::BREAK_STATEMENT } else { NodeType::CONTINUE_STATEMENT };
+        let node_end = if is_break { NodeType::BREAK_STATEMENT } else { NodeType::CONTINUE_STATEMENT };
+        self.write_node_start(node_type);
+        self.check_extension("break and continue statements");
+
+        // break or continue
+        let name = self.get_current_token_name();
+        self.write_token_with_consume();
+
+        match self.loop_labels.last().cloned() {
+            Some((break_label, continue_label)) => {
+                self.vw.writeGoto(if is_break { &break_label } else { &continue_label });
+            },
+            None => self.error(format!("{} is not inside a loop", name)),
+        }
+
+        // ;
+        self.write_token_with_consume();
+
+        self.write_node_end(node_end);
+    }
+
     pub fn compileDo(&mut self) {
         self.write_node_start(NodeType::DO_STATEMENT);
 
@@ -787,6 +1292,8 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
             // )
             self.write_token_with_consume();
 
+            self.check_local_call(&info.name, args);
+
             // argument conatins this.
             self.vw.writeCall(&get_classfunc_name(&self.class_name, &info.name), args + 1);
         } 
@@ -824,6 +1331,7 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
             if info.cat != IdentifierCategory::CLASS {
                 // instance call
                 self.vw.writePush(convert_varKind_to_segment(&info.varKind.unwrap()), info.index.unwrap());
+                self.write_null_check(ERR_NULL_RECEIVER);
             }
             let args = self.compileExpressionList();
 
@@ -831,11 +1339,16 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
             self.write_token_with_consume();
 
             if info.cat == IdentifierCategory::CLASS {
-                self.vw.writeCall(&get_classfunc_name(&info.name, &sr_info.name), args);
+                self.check_call(&info.name, &sr_info.name, args, false);
+                let f = self.checked_call_name(&info.name, &sr_info.name);
+                self.vw.writeCall(&f, args);
             }
             else {
+                let type_name = self.table.typeOf(&info.name);
+                self.check_call(&type_name, &sr_info.name, args, true);
                 // args must contain instance
-                self.vw.writeCall(&get_classfunc_name(&self.table.typeOf(&info.name), &sr_info.name), args + 1);    
+                let f = self.checked_call_name(&type_name, &sr_info.name);
+                self.vw.writeCall(&f, args + 1);    
             }
         } 
 
@@ -903,19 +1416,29 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
     pub fn compileExpression(&mut self) {
         self.write_node_start(NodeType::EXPRESSION);
 
+        if self.precedence_mode {
+            self.compileBinary(0);
+            self.write_node_end(NodeType::EXPRESSION);
+            return;
+        }
+
         self.compileTerm();
 
+        // lowest precedence of the operators so far, to warn about
+        // a + b * c which Jack evaluates as (a + b) * c
+        let mut lowest: Option<i32> = None;
+        let mut warned = false;
         while {self.consume();
-        self.current_token == Token::Symbol("+".to_string())
-        || self.current_token == Token::Symbol("-".to_string()) 
-        || self.current_token == Token::Symbol("*".to_string()) 
-        || self.current_token == Token::Symbol("/".to_string()) 
-        || self.current_token == Token::Symbol("&".to_string()) 
-        || self.current_token == Token::Symbol("|".to_string()) 
-        || self.current_token == Token::Symbol("<".to_string()) 
-        || self.current_token == Token::Symbol(">".to_string()) 
-        || self.current_token == Token::Symbol("=".to_string())} {
+        is_binary_op(&self.current_token)} {
             let op_token = self.current_token.clone();
+            let p = precedence(&op_token);
+            if !warned && lowest.map_or(false, |l| p > l) {
+                warned = true;
+                let op = if let Token::Symbol(s) = &op_token { s.clone() } else { String::new() };
+                self.warning(format!("'{}' is evaluated after the operators on its left; add parentheses or use --precedence", op));
+            }
+            lowest = Some(lowest.map_or(p, |l| l.min(p)));
+
             // op
             self.write_token_with_consume();
 
@@ -927,6 +1450,25 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
         self.write_node_end(NodeType::EXPRESSION);
     }
 
+    // precedence climbing
+    // term (op term)* where only operators of at least min_precedence are
+    // taken at this level
+    fn compileBinary(&mut self, min_precedence: i32) {
+        self.compileTerm();
+
+        while {self.consume();
+        is_binary_op(&self.current_token) && precedence(&self.current_token) >= min_precedence} {
+            let op_token = self.current_token.clone();
+
+            // op
+            self.write_token_with_consume();
+
+            self.compileBinary(precedence(&op_token) + 1);
+
+            self.write_arithmetic(&op_token);
+        }
+    }
+
     pub fn compileTerm(&mut self) {
         self.write_node_start(NodeType::TERM);
 
@@ -939,15 +1481,12 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
         }
         else if let Token::StringConst(s) = &self.current_token {
             // StringConst
-            
-            // String.new(length)
-            self.vw.writePush(Segment::CONST, s.len() as i32);
-            self.vw.writeCall("String.new", 1);
-
-            // String.appendChar(asciicode)
-            for c in s.chars() {
-                self.vw.writePush(Segment::CONST, c as i32);
-                self.vw.writeCall("String.appendChar", 2);
+            let s = s.clone();
+            if self.string_pool.is_some() {
+                self.write_pooled_string(&s);
+            }
+            else {
+                self.write_string(&s);
             }
 
             self.write_token_with_consume();
@@ -1026,6 +1565,7 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
                 if info.cat != IdentifierCategory::CLASS {
                     // instance call
                     self.vw.writePush(convert_varKind_to_segment(&info.varKind.unwrap()), info.index.unwrap());
+                    self.write_null_check(ERR_NULL_RECEIVER);
                 }
 
                 let args = self.compileExpressionList();
@@ -1034,11 +1574,16 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
                 self.write_token_with_consume();
 
                 if info.cat == IdentifierCategory::CLASS {
-                    self.vw.writeCall(&get_classfunc_name(&info.name, &sr_info.name), args);
+                    self.check_call(&info.name, &sr_info.name, args, false);
+                    let f = self.checked_call_name(&info.name, &sr_info.name);
+                    self.vw.writeCall(&f, args);
                 }
                 else {
+                    let type_name = self.table.typeOf(&info.name);
+                    self.check_call(&type_name, &sr_info.name, args, true);
                     // args must contain instance
-                    self.vw.writeCall(&get_classfunc_name(&self.table.typeOf(&info.name), &sr_info.name), args + 1);    
+                    let f = self.checked_call_name(&type_name, &sr_info.name);
+                    self.vw.writeCall(&f, args + 1);    
                 }
             } 
             else if self.current_token == Token::Symbol("(".to_string()) {
@@ -1064,6 +1609,8 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
                 // )
                 self.write_token_with_consume();
 
+                self.check_local_call(&info.name, args);
+
                 // argument conatins this.
                 self.vw.writeCall(&get_classfunc_name(&self.class_name, &info.name), if self.is_method { args + 1 } else { args });
             } 
@@ -1089,7 +1636,7 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
 
                 // convert address calculation
                 self.vw.writePush(convert_varKind_to_segment(&info.varKind.unwrap()), info.index.unwrap());
-                self.vw.writeArithmetic(Command::ADD);
+                self.write_array_address();
                 // access address
                 self.vw.writePop(Segment::POINTER, 1);
                 self.vw.writePush(Segment::THAT, 0);
@@ -1118,13 +1665,187 @@ impl<R: io::Read + io::Seek, W: io::Write> CompilationEngine<R, W> {
         self.write_node_end(NodeType::TERM);
     }
 
+    fn write_string(&mut self, s: &str) {
+        // String.new(length)
+        self.vw.writePush(Segment::CONST, s.chars().count() as i32);
+        self.vw.writeCall("String.new", 1);
+
+        // String.appendChar(asciicode)
+        for c in s.chars() {
+            self.vw.writePush(Segment::CONST, c as i32);
+            self.vw.writeCall("String.appendChar", 2);
+        }
+    }
+
+    fn write_pooled_string(&mut self, s: &str) {
+        let pool = self.string_pool.as_mut().unwrap();
+        let next_index = pool.len() as i32;
+        let index = *pool.entry(s.to_string()).or_insert(next_index);
+        self.vw.writeCall(&get_string_accessor_name(&self.class_name, index), 0);
+    }
+
+    // '$' cannot appear in Jack identifiers, so the accessors never clash
+    // with subroutines of the class.
+    // vm
+    // function Xxx.$stringN 0
+    // push static X
+    // if-goto READY
+    // (build the string)
+    // pop static X
+    // label READY
+    // push static X
+    // return
+    fn write_string_accessors(&mut self) {
+        let mut strings = match &self.string_pool {
+            Some(pool) => pool.iter().map(|(s, i)| (*i, s.clone())).collect::<Vec<(i32, String)>>(),
+            None => return,
+        };
+        strings.sort();
+
+        // pooled strings live after the statics declared in the class
+        let statics = self.table.varCount(&VarKind::STATIC);
+        for (i, s) in strings {
+            self.vw.writeFunction(&get_string_accessor_name(&self.class_name, i), 0);
+            self.vw.writePush(Segment::STATIC, statics + i);
+            self.vw.writeIf("READY");
+            self.write_string(&s);
+            self.vw.writePop(Segment::STATIC, statics + i);
+            self.vw.writeLabel("READY");
+            self.vw.writePush(Segment::STATIC, statics + i);
+            self.vw.writeReturn();
+        }
+    }
+
+    // The helpers are functions of the class, as the string accessors are,
+    // so a checked class needs nothing else at runtime.
+    fn write_check_helpers(&mut self) {
+        let helpers = match &self.checks {
+            Some(h) => h.clone(),
+            None => return,
+        };
+        for helper in helpers {
+            self.vw.writeFunction(&get_classfunc_name(&self.class_name, helper), 0);
+            match helper {
+                // $nonNull(p, code) returns p
+                "$nonNull" => {
+                    self.vw.writePush(Segment::ARG, 0);
+                    self.vw.writeIf("OK");
+                    self.vw.writePush(Segment::ARG, 1);
+                    self.write_error_call();
+                    self.vw.writeLabel("OK");
+                    self.vw.writePush(Segment::ARG, 0);
+                },
+                // $nonZero(y) returns y
+                "$nonZero" => {
+                    self.vw.writePush(Segment::ARG, 0);
+                    self.vw.writeIf("OK");
+                    self.vw.writePush(Segment::CONST, ERR_DIVISION_BY_ZERO);
+                    self.write_error_call();
+                    self.vw.writeLabel("OK");
+                    self.vw.writePush(Segment::ARG, 0);
+                },
+                // $index(i, base) returns base + i
+                "$index" => {
+                    self.vw.writePush(Segment::ARG, 1);
+                    self.vw.writeIf("NOT_NULL");
+                    self.vw.writePush(Segment::CONST, ERR_NULL_ARRAY);
+                    self.write_error_call();
+                    self.vw.writeLabel("NOT_NULL");
+                    self.write_header_check(1, "DONE");
+                    // 0 <= i < size
+                    self.vw.writePush(Segment::ARG, 0);
+                    self.vw.writePush(Segment::CONST, 0);
+                    self.vw.writeArithmetic(Command::LT);
+                    self.vw.writePush(Segment::ARG, 0);
+                    self.vw.writePush(Segment::THAT, 1);
+                    self.vw.writeArithmetic(Command::LT);
+                    self.vw.writeArithmetic(Command::NOT);
+                    self.vw.writeArithmetic(Command::OR);
+                    self.vw.writeArithmetic(Command::NOT);
+                    self.vw.writeIf("DONE");
+                    self.vw.writePush(Segment::CONST, ERR_ARRAY_BOUNDS);
+                    self.write_error_call();
+                    self.vw.writeLabel("DONE");
+                    self.vw.writePush(Segment::ARG, 1);
+                    self.vw.writePush(Segment::ARG, 0);
+                    self.vw.writeArithmetic(Command::ADD);
+                },
+                // $newArray(size) returns an array after a [magic, size] header
+                "$newArray" => {
+                    self.vw.writePush(Segment::ARG, 0);
+                    self.vw.writePush(Segment::CONST, 2);
+                    self.vw.writeArithmetic(Command::ADD);
+                    self.vw.writeCall("Array.new", 1);
+                    self.vw.writePop(Segment::POINTER, 1);
+                    self.vw.writePush(Segment::CONST, ARRAY_MAGIC);
+                    self.vw.writePop(Segment::THAT, 0);
+                    self.vw.writePush(Segment::ARG, 0);
+                    self.vw.writePop(Segment::THAT, 1);
+                    self.vw.writePush(Segment::POINTER, 1);
+                    self.vw.writePush(Segment::CONST, 2);
+                    self.vw.writeArithmetic(Command::ADD);
+                },
+                // $disposeArray(a) frees an array with or without a header
+                "$disposeArray" => {
+                    self.write_header_check(0, "PLAIN");
+                    // a stale pointer must not pass for a checked array
+                    self.vw.writePush(Segment::CONST, 0);
+                    self.vw.writePop(Segment::THAT, 0);
+                    self.vw.writePush(Segment::POINTER, 1);
+                    self.vw.writeCall("Array.dispose", 1);
+                    self.vw.writeReturn();
+                    self.vw.writeLabel("PLAIN");
+                    self.vw.writePush(Segment::ARG, 0);
+                    self.vw.writeCall("Array.dispose", 1);
+                },
+                _ => {},
+            }
+            self.vw.writeReturn();
+        }
+    }
+
+    fn write_error_call(&mut self) {
+        self.vw.writeCall("Sys.error", 1);
+        self.vw.writePop(Segment::TEMP, 0);
+    }
+
+    // Go to skip unless the array in argument arg is on the heap and has
+    // a header; otherwise that points to the header.
+    fn write_header_check(&mut self, arg: i32, skip: &str) {
+        // the header is at base - 2, within the heap at 2048..16383
+        self.vw.writePush(Segment::ARG, arg);
+        self.vw.writePush(Segment::CONST, 2050);
+        self.vw.writeArithmetic(Command::LT);
+        self.vw.writeIf(skip);
+        self.vw.writePush(Segment::ARG, arg);
+        self.vw.writePush(Segment::CONST, 16384);
+        self.vw.writeArithmetic(Command::LT);
+        self.vw.writeArithmetic(Command::NOT);
+        self.vw.writeIf(skip);
+        self.vw.writePush(Segment::ARG, arg);
+        self.vw.writePush(Segment::CONST, 2);
+        self.vw.writeArithmetic(Command::SUB);
+        self.vw.writePop(Segment::POINTER, 1);
+        self.vw.writePush(Segment::THAT, 0);
+        self.vw.writePush(Segment::CONST, ARRAY_MAGIC);
+        self.vw.writeArithmetic(Command::EQ);
+        self.vw.writeArithmetic(Command::NOT);
+        self.vw.writeIf(skip);
+    }
+
     fn write_arithmetic(&mut self, tk: &Token) {
         match tk {
             Token::Symbol(s) => match s.as_str() {
                 "+" => { self.vw.writeArithmetic(Command::ADD); },
                 "-" => { self.vw.writeArithmetic(Command::SUB); },
                 "*" => { self.vw.writeCall("Math.multiply", 2); },
-                "/" => { self.vw.writeCall("Math.divide", 2); },
+                "/" => {
+                    if self.checks.is_some() {
+                        let f = self.check_helper("$nonZero");
+                        self.vw.writeCall(&f, 1);
+                    }
+                    self.vw.writeCall("Math.divide", 2);
+                },
                 "&" => { self.vw.writeArithmetic(Command::AND); },
                 "|" => { self.vw.writeArithmetic(Command::OR); },
                 "<" => { self.vw.writeArithmetic(Command::LT); },
@@ -1174,6 +1895,29 @@ fn convert_keyword(keyword_type: KeywordType) -> String {
     }.to_string()
 }
 
+fn is_binary_op(tk: &Token) -> bool {
+    match tk {
+        Token::Symbol(s) => match s.as_str() {
+            "+" | "-" | "*" | "/" | "&" | "|" | "<" | ">" | "=" => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// * / over + - over comparisons over & |
+fn precedence(tk: &Token) -> i32 {
+    match tk {
+        Token::Symbol(s) => match s.as_str() {
+            "*" | "/" => 3,
+            "+" | "-" => 2,
+            "<" | ">" | "=" => 1,
+            _ => 0,
+        },
+        _ => 0,
+    }
+}
+
 fn convert_node(node_type: NodeType) -> String {
     match node_type {
         NodeType::CLASS => "class",
@@ -1191,6 +1935,9 @@ fn convert_node(node_type: NodeType) -> String {
         NodeType::EXPRESSION_LIST => "expressionList",
         NodeType::EXPRESSION => "expression",
         NodeType::TERM => "term",
+        NodeType::FOR_STATEMENT => "forStatement",
+        NodeType::BREAK_STATEMENT => "breakStatement",
+        NodeType::CONTINUE_STATEMENT => "continueStatement",
     }.to_string()
 }
 
@@ -1265,6 +2012,10 @@ fn get_classfunc_name(class_name: &str, func_name: &str) -> String {
     [class_name, func_name].join(".")
 }
 
+fn get_string_accessor_name(class_name: &str, index: i32) -> String {
+    format!("{}.$string{}", class_name, index)
+}
+
 #[cfg(test)]
 mod tests{
     use super::*;
@@ -1766,6 +2517,393 @@ return
         assert_eq!(c.vw.dump_string(), r);
     }
 
+    #[test]
+    fn CodeGenaration_Optimize() {
+        let s = io::Cursor::new(rawstr_to_code(r#"
+class Main {
+    
+    function int main(int x) {
+        do Output.printInt(1 + (2 * 3));
+        if (~~(x = -1)) {
+            return x * 2;
+        }
+        return (x + 0) - -4;
+    }
+    
+}
+"#));
+        let w = io::Cursor::new(Vec::new());
+        let mut c = CompilationEngine::new(s, w, None);
+        c.xml_mode = false;
+        c.set_optimize(true);
+        c.compileClass();
+
+        let r = rawstr_to_code(r#"
+function Main.main 0
+push constant 7
+call Output.printInt 1
+pop temp 0
+push argument 0
+push constant 0
+not
+eq
+if-goto IF_TRUE0
+goto IF_FALSE0
+label IF_TRUE0
+push argument 0
+push argument 0
+add
+return
+label IF_FALSE0
+push argument 0
+push constant 4
+add
+return
+"#);
+        assert_eq!(c.vw.dump_string(), r);
+    }
+
+    #[test]
+    fn CrossClassErrors() {
+        let square = "class Square { field int x; \
+            constructor Square new(int Ax) { let x = Ax; return this; } \
+            method void draw() { return; } }";
+        let mut index = ClassIndex::new();
+        index.add(scan_class(io::Cursor::new(square)).unwrap()).unwrap();
+
+        let s = io::Cursor::new(rawstr_to_code(r#"
+class Main {
+    function void main() {
+        var Square s;
+        let s = Square.new();
+        do s.draw();
+        do s.erase();
+        do Square.draw();
+        do Output.printInt(1);
+        return;
+    }
+}
+"#));
+        let w = io::Cursor::new(Vec::new());
+        let mut c = CompilationEngine::new(s, w, None);
+        c.set_class_index(Arc::new(index));
+        c.compileClass();
+
+        assert_eq!(c.errors(), &vec![
+            "Main.main: Square.new expects 1 arguments, but got 0".to_string(),
+            "Main.main: Square.erase is not defined".to_string(),
+            "Main.main: Square.draw is a method, but is called without an object".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn CodeGenaration_StringPool() {
+        let s = io::Cursor::new(rawstr_to_code(r#"
+class Main {
+    static int count;
+
+    function void main() {
+        do Output.printString("ab");
+        do Output.printString("ab");
+        return;
+    }
+}
+"#));
+        let w = io::Cursor::new(Vec::new());
+        let mut c = CompilationEngine::new(s, w, None);
+        c.xml_mode = false;
+        c.set_string_pool(true);
+        c.compileClass();
+
+        let r = rawstr_to_code(r#"
+function Main.main 0
+call Main.$string0 0
+call Output.printString 1
+pop temp 0
+call Main.$string0 0
+call Output.printString 1
+pop temp 0
+push constant 0
+return
+function Main.$string0 0
+push static 1
+if-goto READY
+push constant 2
+call String.new 1
+push constant 97
+call String.appendChar 2
+push constant 98
+call String.appendChar 2
+pop static 1
+label READY
+push static 1
+return
+"#);
+        assert_eq!(c.vw.dump_string(), r);
+    }
+
+    #[test]
+    fn CodeGenaration_Extensions() {
+        let s = io::Cursor::new(rawstr_to_code(r#"
+class Main {
+    function void main(Array a, int n) {
+        var int i;
+        for (i = 0; i < n; i += 1) {
+            if (a[i] = 0) {
+                continue;
+            }
+            else if (a[i] < 0) {
+                break;
+            }
+            let a[i] -= 1;
+        }
+        return;
+    }
+}
+"#));
+        let w = io::Cursor::new(Vec::new());
+        let mut c = CompilationEngine::new(s, w, None);
+        c.xml_mode = false;
+        c.compileClass();
+
+        let r = rawstr_to_code(r#"
+function Main.main 1
+push constant 0
+pop local 0
+label WHILE_EXP0
+push local 0
+push argument 1
+lt
+not
+if-goto WHILE_END0
+push local 0
+push argument 0
+add
+pop pointer 1
+push that 0
+push constant 0
+eq
+if-goto IF_TRUE0
+goto IF_FALSE0
+label IF_TRUE0
+goto WHILE_STEP0
+goto IF_END0
+label IF_FALSE0
+push local 0
+push argument 0
+add
+pop pointer 1
+push that 0
+push constant 0
+lt
+if-goto IF_TRUE1
+goto IF_FALSE1
+label IF_TRUE1
+goto WHILE_END0
+label IF_FALSE1
+label IF_END0
+push local 0
+push argument 0
+add
+pop temp 0
+push temp 0
+push temp 0
+pop pointer 1
+push that 0
+push constant 1
+sub
+pop temp 0
+pop pointer 1
+push temp 0
+pop that 0
+label WHILE_STEP0
+push local 0
+push constant 1
+add
+pop local 0
+goto WHILE_EXP0
+label WHILE_END0
+push constant 0
+return
+"#);
+        assert_eq!(c.vw.dump_string(), r);
+        assert!(c.errors().is_empty());
+    }
+
+    #[test]
+    fn StdRejectsExtensions() {
+        let s = io::Cursor::new(rawstr_to_code(r#"
+class Main {
+    function void main() {
+        var int i;
+        while (true) {
+            let i += 1;
+            break;
+        }
+        return;
+    }
+}
+"#));
+        let w = io::Cursor::new(Vec::new());
+        let mut c = CompilationEngine::new(s, w, None);
+        c.set_std(true);
+        c.compileClass();
+
+        assert_eq!(c.errors(), &vec![
+            "Main.main: compound assignments are not part of standard Jack".to_string(),
+            "Main.main: break and continue statements are not part of standard Jack".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn CodeGenaration_Precedence() {
+        let code = rawstr_to_code(r#"
+class Main {
+    function boolean main(int x) {
+        return 1 + x * 3 < 7 & x > 0;
+    }
+}
+"#);
+        let w = io::Cursor::new(Vec::new());
+        let mut c = CompilationEngine::new(io::Cursor::new(code.clone()), w, None);
+        c.xml_mode = false;
+        c.set_precedence(true);
+        c.compileClass();
+
+        let r = rawstr_to_code(r#"
+function Main.main 0
+push constant 1
+push argument 0
+push constant 3
+call Math.multiply 2
+add
+push constant 7
+lt
+push argument 0
+push constant 0
+gt
+and
+return
+"#);
+        assert_eq!(c.vw.dump_string(), r);
+        assert!(c.warnings().is_empty());
+
+        // left to right by default, with a warning
+        let w = io::Cursor::new(Vec::new());
+        let mut c = CompilationEngine::new(io::Cursor::new(code), w, None);
+        c.compileClass();
+        assert_eq!(c.warnings(), &vec![
+            "Main.main: '*' is evaluated after the operators on its left; add parentheses or use --precedence".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn CodeGenaration_Annotate() {
+        let code = "class Main {\n  function int main() {\n    var int x;\n    let x = 1; return x;\n  }\n}\n";
+        let w = io::Cursor::new(Vec::new());
+        let mut c = CompilationEngine::new(io::Cursor::new(code.as_bytes().to_vec()), w, None);
+        c.xml_mode = false;
+        c.set_source("Main.jack", code);
+        c.set_annotate(true);
+        c.set_source_map(true);
+        c.compileClass();
+
+        let r = rawstr_to_code(r#"
+// Main.jack:2 function int main()
+function Main.main 1
+// Main.jack:4 let x = 1;
+push constant 1
+pop local 0
+// Main.jack:4 return x;
+push local 0
+return
+"#);
+        assert_eq!(c.vw.dump_string(), r);
+        assert_eq!(c.source_map().unwrap(), "2 Main.jack:2:3\n4 Main.jack:4:5\n5 Main.jack:4:5\n7 Main.jack:4:16\n8 Main.jack:4:16\n");
+    }
+
+    #[test]
+    fn CodeGenaration_Annotate_Statements() {
+        // each statement of a line gets its own comment, else if included
+        let code = "class Main {\n  function void main() {\n    var int x;\n    \
+            if (x = (1)) { let x = 2; } else if (x = 3) { let x = 4; } else { do Main.f(x, \";\"); }\n    \
+            while (x < 10) {\n      let x = x +\n        1;\n    }\n    return;\n  }\n}\n";
+        let w = io::Cursor::new(Vec::new());
+        let mut c = CompilationEngine::new(io::Cursor::new(code.as_bytes().to_vec()), w, None);
+        c.xml_mode = false;
+        c.set_source("Main.jack", code);
+        c.set_annotate(true);
+        c.compileClass();
+
+        let vm = c.vw.dump_string();
+        let comments: Vec<&str> = vm.lines().filter(|l| l.starts_with("//")).collect();
+        assert_eq!(comments, vec![
+            "// Main.jack:2 function void main()",
+            "// Main.jack:4 if (x = (1))",
+            "// Main.jack:4 let x = 2;",
+            "// Main.jack:4 if (x = 3)",
+            "// Main.jack:4 let x = 4;",
+            "// Main.jack:4 do Main.f(x, \";\");",
+            "// Main.jack:5 while (x < 10)",
+            "// Main.jack:6 let x = x + 1;",
+            "// Main.jack:9 return;",
+        ]);
+    }
+
+    #[test]
+    fn CodeGenaration_Checked() {
+        let s = io::Cursor::new(rawstr_to_code(r#"
+class Main {
+    method int get(Array a, Main m) {
+        let a = Array.new(2);
+        do m.get(a, m);
+        return a[1] / 2;
+    }
+}
+"#));
+        let w = io::Cursor::new(Vec::new());
+        let mut c = CompilationEngine::new(s, w, None);
+        c.xml_mode = false;
+        c.set_checked(true);
+        c.compileClass();
+
+        let r = rawstr_to_code(r#"
+function Main.get 0
+push argument 0
+push constant 33
+call Main.$nonNull 2
+pop pointer 0
+push constant 2
+call Main.$newArray 1
+pop argument 1
+push argument 2
+push constant 32
+call Main.$nonNull 2
+push argument 1
+push argument 2
+call Main.get 3
+pop temp 0
+push constant 1
+push argument 1
+call Main.$index 2
+pop pointer 1
+push that 0
+push constant 2
+call Main.$nonZero 1
+call Math.divide 2
+return
+"#);
+        let vm = c.vw.dump_string();
+        assert!(vm.starts_with(&r));
+        let helpers: Vec<&str> = vm.lines().filter(|l| l.starts_with("function Main.$")).collect();
+        assert_eq!(helpers, vec![
+            "function Main.$index 0",
+            "function Main.$newArray 0",
+            "function Main.$nonNull 0",
+            "function Main.$nonZero 0",
+        ]);
+    }
+
     #[test]
     fn compile_Seven() {
         // CompilationEngine must be scoped to drop.
@@ -1947,6 +3085,54 @@ return
         assert_eq!(result_string, al);
     }
 
+    #[test]
+    fn AstDump() {
+        let code = "class Main {\n  function void f(int a) {\n    do g(a[1]);\n    return;\n  }\n}\n";
+        let w = io::Cursor::new(Vec::new());
+        let mut c = CompilationEngine::new(io::Cursor::new(code), w, None);
+        c.set_ast(true);
+        c.compileClass();
+
+        let r = r#"(class :span (1 1 6 2)
+  (keyword "class" :span (1 1 1 6))
+  (identifier "Main" :category class :usage defined :span (1 7 1 11))
+  (symbol "{" :span (1 12 1 13))
+  (subroutineDec :span (2 3 5 4)
+    (keyword "function" :span (2 3 2 11))
+    (keyword "void" :span (2 12 2 16))
+    (identifier "f" :category subroutine :usage defined :span (2 17 2 18))
+    (symbol "(" :span (2 18 2 19))
+    (parameterList :span (2 19 2 24)
+      (keyword "int" :span (2 19 2 22))
+      (identifier "a" :category argument :usage defined :kind argument :index 0 :span (2 23 2 24)))
+    (symbol ")" :span (2 24 2 25))
+    (subroutineBody :span (2 26 5 4)
+      (symbol "{" :span (2 26 2 27))
+      (statements :span (3 5 4 12)
+        (doStatement :span (3 5 3 16)
+          (keyword "do" :span (3 5 3 7))
+          (identifier "g" :category subroutine :usage used :span (3 8 3 9))
+          (symbol "(" :span (3 9 3 10))
+          (expressionList :span (3 10 3 14)
+            (expression :span (3 10 3 14)
+              (term :span (3 10 3 14)
+                (identifier "a" :category argument :usage used :kind argument :index 0 :span (3 10 3 11))
+                (symbol "[" :span (3 11 3 12))
+                (expression :span (3 12 3 13)
+                  (term :span (3 12 3 13)
+                    (integerConstant 1 :span (3 12 3 13))))
+                (symbol "]" :span (3 13 3 14)))))
+          (symbol ")" :span (3 14 3 15))
+          (symbol ";" :span (3 15 3 16)))
+        (returnStatement :span (4 5 4 12)
+          (keyword "return" :span (4 5 4 11))
+          (symbol ";" :span (4 11 4 12))))
+      (symbol "}" :span (5 3 5 4))))
+  (symbol "}" :span (6 1 6 2)))
+"#;
+        assert_eq!(c.ast().unwrap().to_sexp(&LineIndex::new(code)), r);
+    }
+
     #[test]
     fn compile_ComplexArrays() {
         // CompilationEngine must be scoped to drop.
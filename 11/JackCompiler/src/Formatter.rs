@@ -0,0 +1,266 @@
+use std::io;
+use super::JackTokenizer::*;
+
+const INDENT: &str = "    ";
+
+// Re-indents a class, normalizes spaces between tokens and keeps the
+// comments where they were: on their own line or at the end of a line.
+// Blank lines between statements and declarations are kept, at most one.
+pub fn format(source: &str) -> String {
+    let mut tokenizer = JackTokenizer::new(io::Cursor::new(source.as_bytes()));
+    tokenizer.set_keep_comments(true);
+
+    let mut p = Printer::new(source);
+    while let Some(token) = tokenizer.next() {
+        for c in tokenizer.take_comments() {
+            p.comment(&c);
+        }
+        p.token(&token, tokenizer.span());
+    }
+    for c in tokenizer.take_comments() {
+        p.comment(&c);
+    }
+    p.finish()
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    newline_str: &'static str,
+    out: String,
+    level: usize,
+    paren_depth: i32,
+    prev: Option<Token>,
+    prev_span: Span,
+    prev_unary: bool,
+    // end of the last token or comment
+    prev_end: usize,
+    // the next token starts a new line
+    newline: bool,
+    // the last item written was { or }
+    after_open: bool,
+    after_close: bool,
+}
+
+impl<'a> Printer<'a> {
+    fn new(source: &'a str) -> Self {
+        Printer {
+            source: source,
+            newline_str: if source.contains("\r\n") { "\r\n" } else { "\n" },
+            out: String::new(),
+            level: 0,
+            paren_depth: 0,
+            prev: None,
+            prev_span: Span { start: 0, end: 0 },
+            prev_unary: false,
+            prev_end: 0,
+            newline: false,
+            after_open: false,
+            after_close: false,
+        }
+    }
+
+    fn newlines_before(&self, start: usize) -> usize {
+        self.source[self.prev_end..start].matches('\n').count()
+    }
+
+    fn start_line(&mut self, blank: bool) {
+        if !self.out.is_empty() {
+            self.out.push_str(self.newline_str);
+            if blank {
+                self.out.push_str(self.newline_str);
+            }
+        }
+        self.out.push_str(&INDENT.repeat(self.level));
+    }
+
+    fn token(&mut self, token: &Token, span: Span) {
+        let text = &self.source[span.start..span.end];
+        let blank = self.newlines_before(span.start) >= 2;
+        let is_close = *token == symbol("}");
+
+        if is_close {
+            self.level = self.level.saturating_sub(1);
+            self.newline = true;
+        }
+        if *token == Token::Keyword(KeywordType::ELSE) && self.after_close {
+            // } else {
+            self.newline = false;
+        }
+
+        let unary = is_unary(&self.prev, token);
+        if self.newline || self.out.is_empty() {
+            self.start_line(blank && !self.after_open && !is_close);
+        }
+        else if self.space_before(token, span) {
+            self.out.push(' ');
+        }
+        self.out.push_str(text);
+
+        if *token == symbol("(") {
+            self.paren_depth += 1;
+        }
+        else if *token == symbol(")") {
+            self.paren_depth -= 1;
+        }
+        self.after_open = *token == symbol("{");
+        self.after_close = is_close;
+        if self.after_open {
+            self.level += 1;
+        }
+        self.newline = self.after_open || is_close || (*token == symbol(";") && self.paren_depth <= 0);
+        self.prev = Some(token.clone());
+        self.prev_span = span;
+        self.prev_unary = unary;
+        self.prev_end = span.end;
+    }
+
+    fn space_before(&self, token: &Token, span: Span) -> bool {
+        let prev = match &self.prev {
+            Some(p) => p,
+            None => return false,
+        };
+        // += and -=
+        if (*prev == symbol("+") || *prev == symbol("-")) && *token == symbol("=") && self.prev_span.end == span.start {
+            return false;
+        }
+        if [";", ",", ")", "]", "."].iter().any(|s| *token == symbol(s)) {
+            return false;
+        }
+        if [ "(", "[", "."].iter().any(|s| *prev == symbol(s)) || self.prev_unary {
+            return false;
+        }
+        if *token == symbol("[") {
+            return false;
+        }
+        if *token == symbol("(") {
+            // a call, unless it is the for extension
+            if let Token::Identifier(name) = prev {
+                return name == "for";
+            }
+        }
+        true
+    }
+
+    fn comment(&mut self, comment: &Comment) {
+        let newlines = self.newlines_before(comment.span.start);
+        if newlines == 0 && !self.out.is_empty() {
+            // at the end of a line
+            self.out.push(' ');
+            self.push_comment(&comment.text);
+            if comment.text.starts_with("//") {
+                self.newline = true;
+            }
+        }
+        else {
+            self.start_line(newlines >= 2 && !self.after_open);
+            self.push_comment(&comment.text);
+            self.newline = true;
+        }
+        self.after_open = false;
+        self.after_close = false;
+        self.prev_end = comment.span.end;
+    }
+
+    // Lines of a block comment starting with * are aligned under the /*.
+    fn push_comment(&mut self, text: &str) {
+        let indent = INDENT.repeat(self.level);
+        for (i, line) in text.split('\n').enumerate() {
+            let line = line.trim_end();
+            if i > 0 {
+                self.out.push_str(self.newline_str);
+                let trimmed = line.trim_start();
+                if trimmed.starts_with('*') {
+                    self.out.push_str(&indent);
+                    self.out.push(' ');
+                    self.out.push_str(trimmed);
+                    continue;
+                }
+            }
+            self.out.push_str(line);
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if !self.out.is_empty() {
+            self.out.push_str(self.newline_str);
+        }
+        self.out
+    }
+}
+
+fn symbol(s: &str) -> Token {
+    Token::Symbol(s.to_string())
+}
+
+// - is a negation after an operator, an opening bracket, a comma or return.
+fn is_unary(prev: &Option<Token>, token: &Token) -> bool {
+    if *token == symbol("~") {
+        return true;
+    }
+    if *token != symbol("-") {
+        return false;
+    }
+    match prev {
+        None => true,
+        Some(Token::Symbol(s)) => s != ")" && s != "]",
+        Some(Token::Keyword(KeywordType::RETURN)) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn format_class() {
+        let code = r#"/** A class. */
+class Main{
+field int x,y; // position
+
+
+  /**
+     * Doc.
+     */
+   method void move(int dx){
+let x=x+dx;let y[1]=-y*(~dx);
+        if(x>0){do Output.printInt(-x,dx);}
+        else{
+            // nothing
+        }
+  let x+=1;
+   return;}
+}
+"#;
+        let r = r#"/** A class. */
+class Main {
+    field int x, y; // position
+
+    /**
+     * Doc.
+     */
+    method void move(int dx) {
+        let x = x + dx;
+        let y[1] = -y * (~dx);
+        if (x > 0) {
+            do Output.printInt(-x, dx);
+        } else {
+            // nothing
+        }
+        let x += 1;
+        return;
+    }
+}
+"#;
+        assert_eq!(format(code), r);
+        assert_eq!(format(r), r);
+    }
+
+    #[test]
+    fn format_for() {
+        let r = "class A {\r\n    function void f() {\r\n        for (i = 0; i < 10; i -= -1) { }\r\n    }\r\n}\r\n";
+        let f = format(r);
+        assert_eq!(f, "class A {\r\n    function void f() {\r\n        for (i = 0; i < 10; i -= -1) {\r\n        }\r\n    }\r\n}\r\n");
+        assert_eq!(format(&f), f);
+    }
+}
@@ -9,6 +9,28 @@ pub struct JackTokenizer<R: io::Read + io::Seek> {
     symbol: Option<String>,
     int_val: Option<i32>,
     string_val: Option<String>,
+    escapes: bool,
+    span: Span,
+    keep_comments: bool,
+    comments: Vec<Comment>,
+}
+
+// A comment skipped between tokens, kept when keep_comments is on.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
+
+// Byte offsets of a token in the source, end exclusive.
+#[derive(Clone, Copy)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
 }
 
 #[derive(Clone)]
@@ -71,12 +93,43 @@ impl<R: io::Read + io::Seek> JackTokenizer<R> {
             symbol: None,
             int_val: None,
             string_val: None,
+            escapes: true,
+            span: Span { start: 0, end: 0 },
+            keep_comments: false,
+            comments: vec![],
         }
     }
 
+    // Collect the comments instead of throwing them away, e.g. for the
+    // formatter. They are taken out with take_comments().
+    pub fn set_keep_comments(&mut self, on: bool) {
+        self.keep_comments = on;
+    }
+
+    // Comments read since the last call, in source order.
+    pub fn take_comments(&mut self) -> Vec<Comment> {
+        std::mem::replace(&mut self.comments, vec![])
+    }
+
+    fn position(&mut self) -> usize {
+        self.fs.stream_position().unwrap() as usize
+    }
+
+    // Span of the token read by the last advance().
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    // Standard Jack has no escape sequences in string constants.
+    pub fn set_escapes(&mut self, on: bool) {
+        self.escapes = on;
+    }
+
     pub fn hasMoreTokens(&mut self) -> bool {
         let mut is_in_inline_comment = false;
         let mut is_in_block_comment = false;
+        let mut comment_start = 0;
+        let mut comment = vec![];
 
         let get_next_char = |fs: &mut io::BufReader<R>| {
             let r = fs.bytes().next();
@@ -94,6 +147,11 @@ impl<R: io::Read + io::Seek> JackTokenizer<R> {
                 let mut s = String::new();
                 self.fs.read_line(&mut s);
                 is_in_inline_comment = false;
+                if self.keep_comments {
+                    let text = format!("//{}", s.trim_end_matches(|c| c == '\r' || c == '\n'));
+                    let span = Span { start: comment_start, end: comment_start + text.len() };
+                    self.comments.push(Comment { text: text, span: span });
+                }
                 continue;
             }
             
@@ -107,6 +165,7 @@ impl<R: io::Read + io::Seek> JackTokenizer<R> {
             
             // ブロックコメントなら"*/"まですすめる 
             if is_in_block_comment {
+                comment.push(c);
                 if c == b'*' {
                     let r1 = get_next_char(&mut self.fs);
                     if r1.is_none() {
@@ -114,8 +173,14 @@ impl<R: io::Read + io::Seek> JackTokenizer<R> {
                         break;
                     }
                     let c1 = r1.unwrap();
+                    comment.push(c1);
                     if c1 == b'/' {
                         is_in_block_comment = false;
+                        if self.keep_comments {
+                            let text = String::from_utf8_lossy(&comment).to_string();
+                            let span = Span { start: comment_start, end: comment_start + comment.len() };
+                            self.comments.push(Comment { text: text, span: span });
+                        }
                     }
                     continue;
                 }
@@ -140,9 +205,12 @@ impl<R: io::Read + io::Seek> JackTokenizer<R> {
                     Some(c1) => {
                         if c1 == b'/' {
                             is_in_inline_comment = true;
+                            comment_start = self.position() - 2;
                             continue;
                         } else if c1 == b'*' {
                             is_in_block_comment = true;
+                            comment_start = self.position() - 2;
+                            comment = b"/*".to_vec();
                             continue;
                         } else {
                             // コメントでないので一つ戻してカレントにする
@@ -159,10 +227,19 @@ impl<R: io::Read + io::Seek> JackTokenizer<R> {
             break;
         }
 
+        if self.cur_char.is_some() {
+            // the current char has already been read
+            self.span.start = self.position() - 1;
+        }
         self.cur_char.is_some()
     }
 
     pub fn advance(&mut self) {
+        self.read_token();
+        self.span.end = self.position();
+    }
+
+    fn read_token(&mut self) {
         let cur_char = self.cur_char.unwrap();
         
         let read_word = |cur_char_ref: &mut Option<u8>, until_cond: fn(u8) -> bool, fs: &mut io::BufReader<R>| {
@@ -186,26 +263,6 @@ impl<R: io::Read + io::Seek> JackTokenizer<R> {
             String::from_utf8(buf).unwrap()
         };
 
-        let read_word_to_end_cond = |cur_char_ref: &mut Option<u8>, end_cond: fn(u8) -> bool, fs: &mut io::BufReader<R>| {
-            let mut buf = vec![];
-            buf.push(cur_char_ref.unwrap());
-            // read until delimter
-            loop {
-                let r = fs.by_ref().bytes().next();
-                if r.is_none() {
-                    *cur_char_ref = Some(buf.last().unwrap().clone());
-                    break;
-                }
-                let c = r.unwrap().unwrap();
-                buf.push(c);
-                if end_cond(c) {
-                    *cur_char_ref = Some(buf.last().unwrap().clone());
-                    break;
-                }
-            }
-            String::from_utf8(buf).unwrap()
-        };
-
         // try symbol
         match cur_char {
             b';' | b'=' | b'.' | b'(' | b')' | b'[' | b']' | b'{' | b'}' |
@@ -227,10 +284,8 @@ impl<R: io::Read + io::Seek> JackTokenizer<R> {
 
         // try string
         if cur_char == b'"' {
-            let mut word = read_word_to_end_cond(&mut self.cur_char, |c: u8| { c == b'"' }, &mut self.fs);
-            // remove double quaotes
-            word.remove(0);
-            word.pop();
+            let word = read_string(&mut self.fs, self.escapes);
+            self.cur_char = Some(b'"');
             self.token_type = Some(TokenType::STRING_CONST);
             self.string_val = Some(word);
             return;
@@ -314,6 +369,31 @@ impl<R: io::Read + io::Seek> JackTokenizer<R> {
         s
     }
 }
+// Hack character code of newline.
+pub const NEWLINE: char = '\u{80}';
+
+// Read a string constant after the opening double quote, up to and
+// including the closing one. \" \\ and \n are escapes; any other
+// backslash is kept as it is.
+fn read_string<R: io::Read>(fs: &mut io::BufReader<R>, escapes: bool) -> String {
+    let mut buf = vec![];
+    let mut next = || fs.by_ref().bytes().next().map(|r| r.unwrap());
+    loop {
+        match next() {
+            None | Some(b'"') => break,
+            Some(b'\\') if escapes => match next() {
+                Some(b'"') => buf.push(b'"'),
+                Some(b'\\') => buf.push(b'\\'),
+                Some(b'n') => buf.extend(NEWLINE.to_string().as_bytes()),
+                Some(c) => { buf.push(b'\\'); buf.push(c); },
+                None => { buf.push(b'\\'); break; },
+            },
+            Some(c) => buf.push(c),
+        }
+    }
+    String::from_utf8(buf).unwrap()
+}
+
 fn create_open_tag(name: &str) -> String {
     format!("<{}>", name) 
 }
@@ -322,7 +402,7 @@ fn create_close_tag(name: &str) -> String {
     format!("</{}>", name) 
 }
 
-fn convert_keyword(keyword_type: KeywordType) -> String {
+pub fn convert_keyword(keyword_type: KeywordType) -> String {
     match keyword_type {
         KeywordType::CLASS => "class",
         KeywordType::METHOD => "method",
@@ -806,6 +886,61 @@ mod tests{
         assert_eq!(t.hasMoreTokens(), false);
     }
 
+    #[test]
+    fn advance_string_escape() {
+        let s = io::Cursor::new(r#"
+        "say \"hi\"\\\n[\]" ;
+        "#);
+        let mut t = JackTokenizer::new(s);
+
+        assert_eq!(t.hasMoreTokens(), true);
+        t.advance();
+        assert_eq!(t.tokenType(), Some(TokenType::STRING_CONST));
+        assert_eq!(t.stringVal(), Some(String::from("say \"hi\"\\\u{80}[\\]")));
+        assert_eq!(t.cur_char, Some(b'"'));
+
+        assert_eq!(t.hasMoreTokens(), true);
+        t.advance();
+        assert_eq!(t.symbol(), Some(String::from(";")));
+        assert_eq!(t.hasMoreTokens(), false);
+    }
+
+    #[test]
+    fn advance_span() {
+        let s = io::Cursor::new("let x/*c*/= \"ab\";// x\r\n1234");
+        let mut t = JackTokenizer::new(s);
+        let mut spans = vec![];
+        while t.hasMoreTokens() {
+            t.advance();
+            spans.push(t.span());
+        }
+        assert_eq!(spans, vec![
+            Span { start: 0, end: 3 },
+            Span { start: 4, end: 5 },
+            Span { start: 10, end: 11 },
+            Span { start: 12, end: 16 },
+            Span { start: 16, end: 17 },
+            Span { start: 23, end: 27 },
+        ]);
+    }
+
+    #[test]
+    fn keep_comments() {
+        let s = io::Cursor::new("/** doc */ class // end\r\n/* a\r\n*/ x");
+        let mut t = JackTokenizer::new(s);
+        t.set_keep_comments(true);
+        assert_eq!(t.next(), Some(Token::Keyword(KeywordType::CLASS)));
+        assert_eq!(t.take_comments(), vec![
+            Comment { text: "/** doc */".to_string(), span: Span { start: 0, end: 10 } },
+        ]);
+        assert_eq!(t.next(), Some(Token::Identifier("x".to_string())));
+        assert_eq!(t.take_comments(), vec![
+            Comment { text: "// end".to_string(), span: Span { start: 17, end: 23 } },
+            Comment { text: "/* a\r\n*/".to_string(), span: Span { start: 25, end: 33 } },
+        ]);
+        assert_eq!(t.next(), None);
+    }
+
     // #[test]
     fn tokenizer_square_main() {
         let s = std::fs::File::open("Square/Main.jack");
@@ -0,0 +1,289 @@
+// A small JSON value with a parser and a serializer, for the language
+// server protocol.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    // keys keep their order
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(s: &str) -> Result<Json, String> {
+        let mut p = JsonParser { chars: s.chars().collect(), pos: 0 };
+        let v = p.value()?;
+        p.skip_whitespace();
+        if p.pos != p.chars.len() {
+            return Err(format!("unexpected {} at {}", p.chars[p.pos], p.pos));
+        }
+        Ok(v)
+    }
+
+    pub fn object(members: Vec<(&str, Json)>) -> Json {
+        Json::Object(members.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    pub fn str(s: &str) -> Json {
+        Json::String(s.to_string())
+    }
+
+    pub fn int(i: i64) -> Json {
+        Json::Number(i as f64)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    // get("a.b.c")
+    pub fn path(&self, path: &str) -> Option<&Json> {
+        path.split('.').try_fold(self, |v, key| v.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(n) => Some(*n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            Json::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) => {
+                if n.fract() == 0.0 && n.abs() < 1e15 { write!(f, "{}", *n as i64) } else { write!(f, "{}", n) }
+            },
+            Json::String(s) => write_string(f, s),
+            Json::Array(a) => {
+                write!(f, "[")?;
+                for (i, v) in a.iter().enumerate() {
+                    if i > 0 { write!(f, ",")?; }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in members.iter().enumerate() {
+                    if i > 0 { write!(f, ",")?; }
+                    write_string(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+fn write_string(f: &mut std::fmt::Formatter, s: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl JsonParser {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        }
+        else {
+            Err(format!("expected {} at {}", c, self.pos))
+        }
+    }
+
+    fn literal(&mut self, word: &str, v: Json) -> Result<Json, String> {
+        let end = self.pos + word.len();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().collect::<String>() == word {
+            self.pos = end;
+            Ok(v)
+        }
+        else {
+            Err(format!("unexpected token at {}", self.pos))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            None => Err("unexpected end of input".to_string()),
+            Some('n') => self.literal("null", Json::Null),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') => {
+                self.pos += 1;
+                let mut a = vec![];
+                if self.peek() == Some(']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(a));
+                }
+                loop {
+                    a.push(self.value()?);
+                    if self.peek() == Some(',') {
+                        self.pos += 1;
+                        continue;
+                    }
+                    self.expect(']')?;
+                    return Ok(Json::Array(a));
+                }
+            },
+            Some('{') => {
+                self.pos += 1;
+                let mut members = vec![];
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let k = self.string()?;
+                    self.expect(':')?;
+                    members.push((k, self.value()?));
+                    if self.peek() == Some(',') {
+                        self.pos += 1;
+                        continue;
+                    }
+                    self.expect('}')?;
+                    return Ok(Json::Object(members));
+                }
+            },
+            Some(_) => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while self.pos < self.chars.len() && "+-0123456789.eE".contains(self.chars[self.pos]) {
+            self.pos += 1;
+        }
+        let s: String = self.chars[start..self.pos].iter().collect();
+        s.parse::<f64>().map(Json::Number).map_err(|_| format!("invalid number at {}", start))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.chars.get(self.pos) != Some(&'"') {
+            return Err(format!("expected string at {}", self.pos));
+        }
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            let c = *self.chars.get(self.pos).ok_or("unterminated string")?;
+            self.pos += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let e = *self.chars.get(self.pos).ok_or("unterminated string")?;
+                    self.pos += 1;
+                    match e {
+                        'n' => s.push('\n'),
+                        'r' => s.push('\r'),
+                        't' => s.push('\t'),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            // surrogate pair, high then low
+                            if (0xD800..0xDC00).contains(&code) && self.chars.get(self.pos..self.pos + 2) == Some(&['\\', 'u'][..]) {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err("invalid surrogate pair".to_string());
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            s.push(std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                        },
+                        c => s.push(c),
+                    }
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let end = self.pos + 4;
+        if end > self.chars.len() {
+            return Err("invalid escape".to_string());
+        }
+        let h: String = self.chars[self.pos..end].iter().collect();
+        self.pos = end;
+        u32::from_str_radix(&h, 16).map_err(|_| "invalid escape".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn parse_and_print() {
+        let s = r#" {"id": 1, "params": {"text": "a\"b\né", "list": [true, null, -2.5e1, []]}} "#;
+        let v = Json::parse(s).unwrap();
+        assert_eq!(v.path("params.text").and_then(|t| t.as_str()), Some("a\"b\né"));
+        assert_eq!(v.get("id").and_then(|i| i.as_i64()), Some(1));
+        assert_eq!(v.to_string(), r#"{"id":1,"params":{"text":"a\"b\né","list":[true,null,-25,[]]}}"#);
+        assert!(Json::parse("{\"a\": }").is_err());
+        assert!(Json::parse("[1] 2").is_err());
+    }
+
+    #[test]
+    fn surrogates() {
+        assert_eq!(Json::parse(r#""\ud83d\ude00""#).unwrap().as_str(), Some("\u{1f600}"));
+        // a lone high surrogate is replaced, unless a \u escape follows, which must then be a low surrogate
+        assert_eq!(Json::parse(r#""\ud83d\n""#).unwrap().as_str(), Some("\u{fffd}\n"));
+        assert!(Json::parse(r#""\ud83d\u0041""#).is_err());
+        assert!(Json::parse(r#""\ud83d\ud83d""#).is_err());
+    }
+}
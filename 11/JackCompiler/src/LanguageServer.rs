@@ -0,0 +1,713 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::panic;
+use std::path;
+use std::sync::Arc;
+use super::Ast::*;
+use super::ClassIndex::*;
+use super::CompilationEngine::CompilationEngine;
+use super::JackTokenizer::Span;
+use super::Json::Json;
+
+// The Jack OS is built in, so OS calls are checked and completed without
+// its sources in the project.
+const OS_SOURCES: [&str; 8] = [
+    include_str!("../../../12/Array.jack"),
+    include_str!("../../../12/Keyboard.jack"),
+    include_str!("../../../12/Math.jack"),
+    include_str!("../../../12/Memory.jack"),
+    include_str!("../../../12/Output.jack"),
+    include_str!("../../../12/Screen.jack"),
+    include_str!("../../../12/String.jack"),
+    include_str!("../../../12/Sys.jack"),
+];
+
+// An identifier as the compiler resolved it.
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    // var, argument, static, field, class or subroutine
+    pub category: String,
+    pub defined: bool,
+    pub var_kind: Option<String>,
+    pub index: Option<i32>,
+    // type of a variable, at its definition
+    pub type_name: Option<String>,
+    // enclosing subroutine
+    pub subroutine: Option<String>,
+    // x of x.f() for a subroutine
+    pub receiver: Option<String>,
+    pub span: Span,
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct SubroutineDecl {
+    pub name: String,
+    pub kind: String,
+    pub return_type: String,
+    pub parameters: Vec<(String, String)>,
+    pub span: Span,
+    pub name_span: Span,
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+    pub error: bool,
+}
+
+pub struct Analysis {
+    pub class_name: Option<String>,
+    pub class_name_span: Option<Span>,
+    pub class_span: Option<Span>,
+    pub symbols: Vec<Symbol>,
+    pub subroutines: Vec<SubroutineDecl>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Analysis {
+    pub fn symbol_at(&self, offset: usize) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.span.start <= offset && offset <= s.span.end)
+    }
+
+    // Definition of a variable seen from a subroutine.
+    pub fn variable(&self, name: &str, subroutine: &Option<String>) -> Option<&Symbol> {
+        let defined = |s: &&Symbol| s.defined && s.name == name && s.var_kind.is_some();
+        self.symbols.iter().filter(defined).find(|s| s.subroutine == *subroutine && s.subroutine.is_some())
+            .or_else(|| self.symbols.iter().filter(defined).find(|s| s.subroutine.is_none()))
+    }
+
+    // The subroutine an offset belongs to. A subroutine cut short by a
+    // syntax error ends at its last token, so the last one started wins.
+    pub fn subroutine_at(&self, offset: usize) -> Option<&SubroutineDecl> {
+        self.subroutines.iter().filter(|s| s.span.start <= offset).last()
+    }
+
+    pub fn subroutine(&self, name: &str) -> Option<&SubroutineDecl> {
+        self.subroutines.iter().find(|s| s.name == name)
+    }
+}
+
+// Compile a class for its parse tree and diagnostics. A syntax error stops
+// the compiler, but the tree up to it is still used.
+pub fn analyze(source: &str, index: Arc<ClassIndex>) -> Analysis {
+    let mut c = CompilationEngine::new(io::Cursor::new(source.as_bytes().to_vec()), io::sink(), None);
+    c.set_ast(true);
+    c.set_class_index(index);
+    let r = panic::catch_unwind(panic::AssertUnwindSafe(|| c.compileClass()));
+
+    let mut a = Analysis {
+        class_name: None,
+        class_name_span: None,
+        class_span: None,
+        symbols: vec![],
+        subroutines: vec![],
+        diagnostics: vec![],
+    };
+    for (e, span) in c.errors().iter().zip(c.error_spans()) {
+        a.diagnostics.push(Diagnostic { span: *span, message: e.clone(), error: true });
+    }
+    for (w, span) in c.warnings().iter().zip(c.warning_spans()) {
+        a.diagnostics.push(Diagnostic { span: *span, message: w.clone(), error: false });
+    }
+    if r.is_err() {
+        a.diagnostics.push(Diagnostic { span: c.span(), message: "syntax error or undefined variable".to_string(), error: true });
+    }
+    if let Some(root) = c.take_ast() {
+        collect(&root, &None, &mut a);
+    }
+    a
+}
+
+fn token_text(node: &AstNode) -> Option<String> {
+    match node {
+        AstNode::Token { value, .. } => Some(value.clone()),
+        AstNode::Identifier { name, .. } => Some(name.clone()),
+        _ => None,
+    }
+}
+
+fn collect(node: &AstNode, subroutine: &Option<String>, a: &mut Analysis) {
+    let (kind, span, children) = match node {
+        AstNode::Node { kind, span, children } => (kind, span, children),
+        _ => return,
+    };
+    let mut subroutine = subroutine.clone();
+    if kind == "class" {
+        a.class_span = *span;
+    }
+    if kind == "subroutineDec" && children.len() > 2 {
+        let name_span = children[2].span().unwrap();
+        let mut parameters = vec![];
+        if let Some(AstNode::Node { children: params, .. }) = children.get(4) {
+            for p in params.chunks(3) {
+                if p.len() >= 2 {
+                    parameters.push((token_text(&p[0]).unwrap_or_default(), token_text(&p[1]).unwrap_or_default()));
+                }
+            }
+        }
+        let name = token_text(&children[2]).unwrap_or_default();
+        a.subroutines.push(SubroutineDecl {
+            name: name.clone(),
+            kind: token_text(&children[0]).unwrap_or_default(),
+            return_type: token_text(&children[1]).unwrap_or_default(),
+            parameters: parameters,
+            span: span.unwrap_or(name_span),
+            name_span: name_span,
+        });
+        subroutine = Some(name);
+    }
+
+    for (i, child) in children.iter().enumerate() {
+        match child {
+            AstNode::Identifier { name, category, usage, var_kind, index, span } => {
+                let defined = usage == "defined";
+                if kind == "class" && category == "class" && defined {
+                    a.class_name = Some(name.clone());
+                    a.class_name_span = Some(*span);
+                }
+                let type_name = if defined && var_kind.is_some() {
+                    // type name, or [static|field|var] type name (, name)*
+                    if kind == "parameterList" { token_text(&children[i - 1]) } else { children.get(1).and_then(token_text) }
+                }
+                else {
+                    None
+                };
+                let receiver = if category == "subroutine" && i >= 2 && token_text(&children[i - 1]) == Some(".".to_string()) {
+                    token_text(&children[i - 2])
+                }
+                else {
+                    None
+                };
+                a.symbols.push(Symbol {
+                    name: name.clone(),
+                    category: category.clone(),
+                    defined: defined,
+                    var_kind: var_kind.clone(),
+                    index: *index,
+                    type_name: type_name,
+                    subroutine: if kind == "classVarDec" { None } else { subroutine.clone() },
+                    receiver: receiver,
+                    span: *span,
+                });
+            },
+            AstNode::Node { .. } => collect(child, &subroutine, a),
+            _ => {},
+        }
+    }
+}
+
+// LSP positions are a line and a UTF-16 column, both 0-based.
+pub fn offset_to_position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].encode_utf16().count())
+}
+
+pub fn position_to_offset(text: &str, line: usize, character: usize) -> usize {
+    let line_start = if line == 0 { 0 } else { text.match_indices('\n').nth(line - 1).map_or(text.len(), |(i, _)| i + 1) };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn range(text: &str, span: Span) -> Json {
+    let position = |offset| {
+        let (line, character) = offset_to_position(text, offset);
+        Json::object(vec![("line", Json::int(line as i64)), ("character", Json::int(character as i64))])
+    };
+    Json::object(vec![("start", position(span.start)), ("end", position(span.end))])
+}
+
+pub fn uri_to_path(uri: &str) -> Option<path::PathBuf> {
+    let p = uri.strip_prefix("file://")?;
+    let bytes = p.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(b) = std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    Some(path::PathBuf::from(String::from_utf8_lossy(&decoded).to_string()))
+}
+
+pub fn path_to_uri(p: &path::Path) -> String {
+    format!("file://{}", p.to_string_lossy().replace('%', "%25").replace(' ', "%20"))
+}
+
+fn signature(class_name: &str, sr: &SubroutineInfo) -> String {
+    format!("{} {} {}.{}({})", sr.kind, sr.return_type, class_name, sr.name, sr.parameter_types.join(", "))
+}
+
+fn segment(var_kind: &str) -> &str {
+    match var_kind {
+        "var" => "local",
+        "field" => "this",
+        k => k,
+    }
+}
+
+// A class of the project: an open document or a .jack file next to it.
+struct ProjectFile {
+    uri: String,
+    text: String,
+}
+
+pub struct LanguageServer {
+    // text of the open documents by uri
+    documents: HashMap<String, String>,
+    os: Vec<ClassInfo>,
+}
+
+impl LanguageServer {
+    pub fn new() -> Self {
+        LanguageServer {
+            documents: HashMap::new(),
+            os: OS_SOURCES.iter().filter_map(|s| scan_class(io::Cursor::new(s.as_bytes())).ok()).collect(),
+        }
+    }
+
+    // Handle a request or a notification, and return the messages to send.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(|m| m.as_str()).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        let uri = params.path("textDocument.uri").and_then(|u| u.as_str()).unwrap_or("").to_string();
+        let result = match method {
+            "initialize" => Some(capabilities()),
+            "shutdown" => Some(Json::Null),
+            "textDocument/didOpen" => {
+                let text = params.path("textDocument.text").and_then(|t| t.as_str()).unwrap_or("");
+                self.documents.insert(uri.clone(), text.to_string());
+                return vec![self.publish_diagnostics(&uri)];
+            },
+            "textDocument/didChange" => {
+                // full document sync
+                let changes = params.get("contentChanges").and_then(|c| c.as_array());
+                if let Some(text) = changes.and_then(|c| c.last()).and_then(|c| c.get("text")).and_then(|t| t.as_str()) {
+                    self.documents.insert(uri, text.to_string());
+                }
+                return vec![];
+            },
+            "textDocument/didSave" => {
+                if let Some(text) = params.get("text").and_then(|t| t.as_str()) {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                return vec![self.publish_diagnostics(&uri)];
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![];
+            },
+            "textDocument/hover" => Some(self.hover(&uri, &params)),
+            "textDocument/definition" => Some(self.definition(&uri, &params)),
+            "textDocument/documentSymbol" => Some(self.document_symbols(&uri)),
+            "textDocument/completion" => Some(self.completion(&uri, &params)),
+            _ => None,
+        };
+
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            // a notification
+            None => return vec![],
+        };
+        let mut response = vec![("jsonrpc", Json::str("2.0")), ("id", id)];
+        match result {
+            Some(r) => response.push(("result", r)),
+            None => response.push(("error", Json::object(vec![
+                ("code", Json::int(-32601)),
+                ("message", Json::String(format!("{} is not supported", method))),
+            ]))),
+        }
+        vec![Json::object(response)]
+    }
+
+    fn text(&self, uri: &str) -> String {
+        match self.documents.get(uri) {
+            Some(t) => t.clone(),
+            None => uri_to_path(uri).and_then(|p| fs::read_to_string(p).ok()).unwrap_or_default(),
+        }
+    }
+
+    // The classes in the directory of a document, open ones as edited.
+    fn project(&self, uri: &str) -> Vec<ProjectFile> {
+        let mut files: Vec<ProjectFile> = vec![];
+        let dir = uri_to_path(uri).and_then(|p| p.parent().map(|d| d.to_path_buf()));
+        if let Some(entries) = dir.as_ref().and_then(|d| d.read_dir().ok()) {
+            let mut paths: Vec<path::PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path())
+                .filter(|p| p.extension().map_or(false, |e| e == "jack"))
+                .collect();
+            paths.sort();
+            for p in paths {
+                let file_uri = path_to_uri(&p);
+                files.push(ProjectFile { text: self.text(&file_uri), uri: file_uri });
+            }
+        }
+        for (doc_uri, text) in &self.documents {
+            let same_dir = uri_to_path(doc_uri).and_then(|p| p.parent().map(|d| d.to_path_buf())) == dir;
+            if (same_dir || doc_uri == uri) && !files.iter().any(|f| f.uri == *doc_uri) {
+                files.push(ProjectFile { uri: doc_uri.clone(), text: text.clone() });
+            }
+        }
+        files
+    }
+
+    fn class_index(&self, files: &[ProjectFile]) -> Arc<ClassIndex> {
+        let mut index = ClassIndex::new();
+        for f in files {
+            if let Ok(info) = scan_class(io::Cursor::new(f.text.as_bytes())) {
+                let _ = index.add(info);
+            }
+        }
+        for info in &self.os {
+            if !index.contains(&info.name) {
+                let _ = index.add(info.clone());
+            }
+        }
+        Arc::new(index)
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Json {
+        let text = self.text(uri);
+        let a = analyze(&text, self.class_index(&self.project(uri)));
+        let diagnostics = a.diagnostics.iter().map(|d| Json::object(vec![
+            ("range", range(&text, d.span)),
+            ("severity", Json::int(if d.error { 1 } else { 2 })),
+            ("source", Json::str("jack")),
+            ("message", Json::str(&d.message)),
+        ])).collect();
+        Json::object(vec![
+            ("jsonrpc", Json::str("2.0")),
+            ("method", Json::str("textDocument/publishDiagnostics")),
+            ("params", Json::object(vec![("uri", Json::str(uri)), ("diagnostics", Json::Array(diagnostics))])),
+        ])
+    }
+
+    fn offset(&self, text: &str, params: &Json) -> usize {
+        let line = params.path("position.line").and_then(|l| l.as_i64()).unwrap_or(0) as usize;
+        let character = params.path("position.character").and_then(|c| c.as_i64()).unwrap_or(0) as usize;
+        position_to_offset(text, line, character)
+    }
+
+    // Class of the subroutine called by a symbol: this class, the class
+    // named by the receiver, or the type of the receiver variable.
+    fn callee_class(&self, a: &Analysis, symbol: &Symbol) -> Option<String> {
+        match &symbol.receiver {
+            None => a.class_name.clone(),
+            Some(r) => match a.variable(r, &symbol.subroutine) {
+                Some(v) => v.type_name.clone(),
+                None => Some(r.clone()),
+            },
+        }
+    }
+
+    fn hover(&self, uri: &str, params: &Json) -> Json {
+        let text = self.text(uri);
+        let files = self.project(uri);
+        let index = self.class_index(&files);
+        let a = analyze(&text, index.clone());
+        let offset = self.offset(&text, params);
+        let symbol = match a.symbol_at(offset) {
+            Some(s) => s,
+            None => return Json::Null,
+        };
+
+        let contents = match symbol.category.as_str() {
+            "class" => format!("class {}", symbol.name),
+            "subroutine" => {
+                let class_name = self.callee_class(&a, symbol).unwrap_or_default();
+                match index.get(&class_name).and_then(|c| c.subroutine(&symbol.name)) {
+                    Some(sr) => signature(&class_name, sr),
+                    None => format!("subroutine {}", symbol.name),
+                }
+            },
+            _ => {
+                let kind = symbol.var_kind.clone().unwrap_or_default();
+                let type_name = a.variable(&symbol.name, &symbol.subroutine).and_then(|v| v.type_name.clone()).unwrap_or_default();
+                format!("{} {} {}\n```\n{} {}", kind, type_name, symbol.name, segment(&kind), symbol.index.unwrap_or(0))
+            },
+        };
+        // the variable case closes the code block itself
+        let value = if contents.contains("```") {
+            format!("```jack\n{}", contents)
+        }
+        else {
+            format!("```jack\n{}\n```", contents)
+        };
+        Json::object(vec![
+            ("contents", Json::object(vec![("kind", Json::str("markdown")), ("value", Json::String(value))])),
+            ("range", range(&text, symbol.span)),
+        ])
+    }
+
+    fn definition(&self, uri: &str, params: &Json) -> Json {
+        let text = self.text(uri);
+        let files = self.project(uri);
+        let index = self.class_index(&files);
+        let a = analyze(&text, index.clone());
+        let offset = self.offset(&text, params);
+        let symbol = match a.symbol_at(offset) {
+            Some(s) => s.clone(),
+            None => return Json::Null,
+        };
+        let location = |uri: &str, text: &str, span: Span| Json::object(vec![("uri", Json::str(uri)), ("range", range(text, span))]);
+
+        let (class_name, sr_name) = match symbol.category.as_str() {
+            "class" => (symbol.name.clone(), None),
+            "subroutine" => match self.callee_class(&a, &symbol) {
+                Some(c) => (c, Some(symbol.name.clone())),
+                None => return Json::Null,
+            },
+            _ => {
+                return match a.variable(&symbol.name, &symbol.subroutine) {
+                    Some(v) => location(uri, &text, v.span),
+                    None => Json::Null,
+                };
+            },
+        };
+
+        for f in &files {
+            let c = if f.uri == uri { None } else { Some(analyze(&f.text, index.clone())) };
+            let fa = c.as_ref().unwrap_or(&a);
+            if fa.class_name.as_ref() != Some(&class_name) {
+                continue;
+            }
+            let span = match &sr_name {
+                Some(name) => fa.subroutine(name).map(|s| s.name_span),
+                None => fa.class_name_span,
+            };
+            return match span {
+                Some(span) => location(&f.uri, &f.text, span),
+                None => Json::Null,
+            };
+        }
+        Json::Null
+    }
+
+    fn document_symbols(&self, uri: &str) -> Json {
+        let text = self.text(uri);
+        let a = analyze(&text, self.class_index(&self.project(uri)));
+        let (name, name_span) = match (&a.class_name, a.class_name_span) {
+            (Some(n), Some(s)) => (n.clone(), s),
+            _ => return Json::Array(vec![]),
+        };
+        let symbol = |name: &str, kind: i64, detail: &str, span: Span, name_span: Span, children: Vec<Json>| {
+            let mut members = vec![
+                ("name", Json::str(name)),
+                ("kind", Json::int(kind)),
+                ("range", range(&text, span)),
+                ("selectionRange", range(&text, name_span)),
+            ];
+            if !detail.is_empty() {
+                members.push(("detail", Json::str(detail)));
+            }
+            if !children.is_empty() {
+                members.push(("children", Json::Array(children)));
+            }
+            Json::object(members)
+        };
+
+        let mut children = vec![];
+        for v in a.symbols.iter().filter(|s| s.defined && s.subroutine.is_none() && s.var_kind.is_some()) {
+            // Field or Variable
+            let kind = if v.category == "field" { 8 } else { 13 };
+            let detail = format!("{} {}", v.category, v.type_name.clone().unwrap_or_default());
+            children.push(symbol(&v.name, kind, &detail, v.span, v.span, vec![]));
+        }
+        for s in &a.subroutines {
+            // Constructor, Method or Function
+            let kind = match s.kind.as_str() { "constructor" => 9, "method" => 6, _ => 12 };
+            let params: Vec<String> = s.parameters.iter().map(|(t, n)| format!("{} {}", t, n)).collect();
+            let detail = format!("{} {} {}({})", s.kind, s.return_type, s.name, params.join(", "));
+            children.push(symbol(&s.name, kind, &detail, s.span, s.name_span, vec![]));
+        }
+        let class_span = a.class_span.unwrap_or(name_span);
+        // Class
+        Json::Array(vec![symbol(&name, 5, "", class_span, name_span, children)])
+    }
+
+    fn completion(&self, uri: &str, params: &Json) -> Json {
+        let text = self.text(uri);
+        let files = self.project(uri);
+        let index = self.class_index(&files);
+        let a = analyze(&text, index.clone());
+        let offset = self.offset(&text, params);
+
+        // receiver.prefix before the cursor
+        let before = &text[..offset];
+        let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let prefix_start = before.rfind(|c: char| !is_ident(c)).map_or(0, |i| i + 1);
+        let prefix = &before[prefix_start..];
+        let receiver = if before[..prefix_start].ends_with('.') {
+            let r_end = prefix_start - 1;
+            let r_start = before[..r_end].rfind(|c: char| !is_ident(c)).map_or(0, |i| i + 1);
+            Some(&before[r_start..r_end])
+        }
+        else {
+            None
+        };
+        let subroutine = a.subroutine_at(offset).map(|s| s.name.clone());
+
+        let item = |label: &str, kind: i64, detail: &str| Json::object(vec![
+            ("label", Json::str(label)),
+            ("kind", Json::int(kind)),
+            ("detail", Json::str(detail)),
+        ]);
+        let mut items = vec![];
+        match receiver {
+            Some(r) => {
+                // methods of a variable's class, or functions of a class
+                let (class_name, methods) = match a.variable(r, &subroutine) {
+                    Some(v) => (v.type_name.clone().unwrap_or_default(), true),
+                    None => (r.to_string(), false),
+                };
+                if let Some(c) = index.get(&class_name) {
+                    for sr in &c.subroutines {
+                        if (sr.kind == SubroutineKind::METHOD) != methods || !sr.name.starts_with(prefix) {
+                            continue;
+                        }
+                        // Method, Function or Constructor
+                        let kind = match sr.kind { SubroutineKind::METHOD => 2, SubroutineKind::FUNCTION => 3, SubroutineKind::CONSTRUCTOR => 4 };
+                        items.push(item(&sr.name, kind, &signature(&class_name, sr)));
+                    }
+                }
+            },
+            None => {
+                for v in a.symbols.iter().filter(|s| s.defined && s.var_kind.is_some()) {
+                    let visible = v.subroutine.is_none() || v.subroutine == subroutine;
+                    if visible && v.name.starts_with(prefix) && v.span.end < offset {
+                        let detail = format!("{} {}", v.var_kind.clone().unwrap_or_default(), v.type_name.clone().unwrap_or_default());
+                        // Variable
+                        items.push(item(&v.name, 6, &detail));
+                    }
+                }
+                for c in index.class_names() {
+                    if c.starts_with(prefix) {
+                        // Class
+                        items.push(item(&c, 7, "class"));
+                    }
+                }
+            },
+        }
+        Json::Array(items)
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        ("capabilities", Json::object(vec![
+            ("textDocumentSync", Json::object(vec![
+                ("openClose", Json::Bool(true)),
+                // full text on change
+                ("change", Json::int(1)),
+                ("save", Json::object(vec![("includeText", Json::Bool(true))])),
+            ])),
+            ("hoverProvider", Json::Bool(true)),
+            ("definitionProvider", Json::Bool(true)),
+            ("documentSymbolProvider", Json::Bool(true)),
+            ("completionProvider", Json::object(vec![("triggerCharacters", Json::Array(vec![Json::str(".")]))])),
+        ])),
+        ("serverInfo", Json::object(vec![("name", Json::str("jacklsp"))])),
+    ])
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const MAIN: &str = "class Main {\n    function void main() {\n        var Counter c;\n        let c = Counter.new();\n        do c.add(1, 2);\n        do Output.printInt(c.get());\n        return;\n    }\n}\n";
+    const COUNTER: &str = "class Counter {\n    field int count;\n    constructor Counter new() {\n        let count = 0;\n        return this;\n    }\n    method void add(int n) {\n        let count = count + n;\n        return;\n    }\n    method int get() { return count; }\n}\n";
+
+    fn request(method: &str, uri: &str, line: i64, character: i64) -> Json {
+        Json::parse(&format!(r#"{{"jsonrpc": "2.0", "id": 1, "method": "{}", "params": {{"textDocument": {{"uri": "{}"}}, "position": {{"line": {}, "character": {}}}}}}}"#,
+            method, uri, line, character)).unwrap()
+    }
+
+    fn open(server: &mut LanguageServer, uri: &str, text: &str) -> Json {
+        let message = Json::object(vec![
+            ("jsonrpc", Json::str("2.0")),
+            ("method", Json::str("textDocument/didOpen")),
+            ("params", Json::object(vec![("textDocument", Json::object(vec![("uri", Json::str(uri)), ("text", Json::str(text))]))])),
+        ]);
+        server.handle(&message).remove(0)
+    }
+
+    #[test]
+    fn diagnostics() {
+        let mut server = LanguageServer::new();
+        let d = open(&mut server, "file:///nowhere/Counter.jack", COUNTER);
+        assert_eq!(d.path("params.diagnostics"), Some(&Json::Array(vec![])));
+
+        let d = open(&mut server, "file:///nowhere/Main.jack", MAIN);
+        let diagnostics = d.path("params.diagnostics").unwrap().as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].get("message"), Some(&Json::str("Main.main: Counter.add expects 1 arguments, but got 2")));
+        assert_eq!(diagnostics[0].path("range.start.line").and_then(|l| l.as_i64()), Some(4));
+    }
+
+    #[test]
+    fn hover_and_definition() {
+        let mut server = LanguageServer::new();
+        open(&mut server, "file:///nowhere/Counter.jack", COUNTER);
+        open(&mut server, "file:///nowhere/Main.jack", MAIN);
+
+        // c in "do c.add"
+        let r = server.handle(&request("textDocument/hover", "file:///nowhere/Main.jack", 4, 11)).remove(0);
+        assert_eq!(r.path("result.contents.value").and_then(|v| v.as_str()), Some("```jack\nvar Counter c\n```\nlocal 0"));
+        let r = server.handle(&request("textDocument/definition", "file:///nowhere/Main.jack", 4, 11)).remove(0);
+        assert_eq!(r.path("result.range.start.line").and_then(|v| v.as_i64()), Some(2));
+
+        // add in "do c.add"
+        let r = server.handle(&request("textDocument/hover", "file:///nowhere/Main.jack", 4, 14)).remove(0);
+        assert_eq!(r.path("result.contents.value").and_then(|v| v.as_str()), Some("```jack\nmethod void Counter.add(int)\n```"));
+        let r = server.handle(&request("textDocument/definition", "file:///nowhere/Main.jack", 4, 14)).remove(0);
+        assert_eq!(r.path("result.uri").and_then(|v| v.as_str()), Some("file:///nowhere/Counter.jack"));
+        assert_eq!(r.path("result.range.start.line").and_then(|v| v.as_i64()), Some(6));
+
+        // Counter in "Counter.new"
+        let r = server.handle(&request("textDocument/definition", "file:///nowhere/Main.jack", 3, 17)).remove(0);
+        assert_eq!(r.path("result.range.start.line").and_then(|v| v.as_i64()), Some(0));
+
+        let r = server.handle(&request("textDocument/documentSymbol", "file:///nowhere/Counter.jack", 0, 0)).remove(0);
+        let class = &r.get("result").unwrap().as_array().unwrap()[0];
+        let names: Vec<&str> = class.get("children").unwrap().as_array().unwrap().iter()
+            .map(|c| c.get("name").unwrap().as_str().unwrap()).collect();
+        assert_eq!(names, vec!["count", "new", "add", "get"]);
+    }
+
+    #[test]
+    fn completion() {
+        let mut server = LanguageServer::new();
+        open(&mut server, "file:///nowhere/Counter.jack", COUNTER);
+        let text = "class Main {\n    function void main() {\n        var Counter c;\n        do Math.m\n        do c.\n";
+        open(&mut server, "file:///nowhere/Main.jack", text);
+
+        let labels = |r: Json| -> Vec<String> {
+            r.get("result").unwrap().as_array().unwrap().iter().map(|i| i.get("label").unwrap().as_str().unwrap().to_string()).collect()
+        };
+        let r = server.handle(&request("textDocument/completion", "file:///nowhere/Main.jack", 3, 17)).remove(0);
+        assert_eq!(labels(r), vec!["multiply", "max", "min"]);
+        let r = server.handle(&request("textDocument/completion", "file:///nowhere/Main.jack", 4, 13)).remove(0);
+        assert_eq!(labels(r), vec!["add", "get"]);
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use super::Ast::*;
+use super::ClassIndex::*;
+use super::JackTokenizer::*;
+
+pub const RULES: [&str; 9] = [
+    "unused-variable",
+    "unused-parameter",
+    "unused-field",
+    "uninitialized-read",
+    "unreachable-code",
+    "shadowed-field",
+    "constructor-return",
+    "discarded-result",
+    "self-assignment",
+];
+
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct LintWarning {
+    pub rule: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+// Which rules are on. Every rule is on by default; a config file turns
+// them off or on again, one "rule = on|off" per line.
+#[derive(Clone)]
+#[derive(Debug)]
+pub struct Config {
+    disabled: HashSet<String>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config { disabled: HashSet::new() }
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (rule, value) = match line.find('=') {
+                Some(e) => (line[..e].trim(), line[e + 1..].trim()),
+                None => return Err(format!("line {}: expected rule = on|off", i + 1)),
+            };
+            if !RULES.contains(&rule) {
+                return Err(format!("line {}: unknown rule {}", i + 1, rule));
+            }
+            match value {
+                "on" => { config.disabled.remove(rule); },
+                "off" => { config.disabled.insert(rule.to_string()); },
+                _ => return Err(format!("line {}: expected on or off, got {}", i + 1, value)),
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn is_enabled(&self, rule: &str) -> bool {
+        !self.disabled.contains(rule)
+    }
+}
+
+// A variable declaration.
+struct Definition {
+    name: String,
+    var_kind: String,
+    type_name: String,
+    span: Span,
+}
+
+// An identifier naming a variable, in evaluation order.
+struct Occurrence {
+    name: String,
+    var_kind: String,
+    // the x of "let x = ..."
+    assigned: bool,
+    span: Span,
+}
+
+struct Linter<'a> {
+    index: Option<&'a ClassIndex>,
+    class_name: String,
+    class_vars: Vec<Definition>,
+    warnings: Vec<LintWarning>,
+}
+
+// Lint a class from the tree the compiler built. Warnings on a line with a
+// "// lint-ignore" comment, or below a "// lint-ignore-next-line" comment,
+// are dropped; both take an optional list of rules, e.g.
+// "// lint-ignore: unused-variable, self-assignment".
+pub fn lint(ast: &AstNode, source: &str, index: Option<&ClassIndex>, config: &Config) -> Vec<LintWarning> {
+    let mut l = Linter { index: index, class_name: String::new(), class_vars: vec![], warnings: vec![] };
+    l.class(ast);
+
+    let suppressions = suppressions(source);
+    let lines = LineIndex::new(source);
+    let mut warnings: Vec<LintWarning> = l.warnings.into_iter()
+        .filter(|w| config.is_enabled(w.rule))
+        .filter(|w| {
+            let (line, _) = lines.position(w.span.start);
+            match suppressions.get(&line) {
+                Some(rules) => !rules.is_empty() && !rules.iter().any(|r| r == w.rule),
+                None => true,
+            }
+        })
+        .collect();
+    warnings.sort_by_key(|w| w.span.start);
+    warnings
+}
+
+// Rules suppressed on each line; an empty list suppresses them all.
+fn suppressions(source: &str) -> HashMap<usize, Vec<String>> {
+    let mut tokenizer = JackTokenizer::new(io::Cursor::new(source.as_bytes()));
+    tokenizer.set_keep_comments(true);
+    let mut comments = vec![];
+    while tokenizer.next().is_some() {
+        comments.append(&mut tokenizer.take_comments());
+    }
+    comments.append(&mut tokenizer.take_comments());
+
+    let lines = LineIndex::new(source);
+    let mut suppressed = HashMap::new();
+    for c in comments {
+        let text = c.text.trim_start_matches('/').trim();
+        let (line, rest) = if let Some(rest) = text.strip_prefix("lint-ignore-next-line") {
+            (lines.position(c.span.start).0 + 1, rest)
+        }
+        else if let Some(rest) = text.strip_prefix("lint-ignore") {
+            (lines.position(c.span.start).0, rest)
+        }
+        else {
+            continue;
+        };
+        let rules = rest.trim_start_matches(':').split(',').map(|r| r.trim().to_string()).filter(|r| !r.is_empty()).collect();
+        suppressed.insert(line, rules);
+    }
+    suppressed
+}
+
+fn children(node: &AstNode) -> &[AstNode] {
+    match node {
+        AstNode::Node { children, .. } => children,
+        _ => &[],
+    }
+}
+
+fn kind(node: &AstNode) -> &str {
+    match node {
+        AstNode::Node { kind, .. } => kind,
+        AstNode::Token { kind, .. } => kind,
+        AstNode::Identifier { .. } => "identifier",
+    }
+}
+
+fn text(node: &AstNode) -> String {
+    match node {
+        AstNode::Token { value, .. } => value.clone(),
+        AstNode::Identifier { name, .. } => name.clone(),
+        _ => String::new(),
+    }
+}
+
+fn is_symbol(node: Option<&AstNode>, s: &str) -> bool {
+    match node {
+        Some(AstNode::Token { kind, value, .. }) => kind == "symbol" && value == s,
+        _ => false,
+    }
+}
+
+// Declarations directly in a classVarDec, varDec or parameterList.
+fn definitions(node: &AstNode) -> Vec<Definition> {
+    let c = children(node);
+    let mut defs = vec![];
+    for (i, child) in c.iter().enumerate() {
+        if let AstNode::Identifier { name, usage, var_kind: Some(k), span, .. } = child {
+            if usage != "defined" {
+                continue;
+            }
+            // type name, or [static|field|var] type name (, name)*
+            let type_name = if kind(node) == "parameterList" { text(&c[i - 1]) } else { text(&c[1]) };
+            defs.push(Definition { name: name.clone(), var_kind: k.clone(), type_name: type_name, span: *span });
+        }
+    }
+    defs
+}
+
+fn occurrences(node: &AstNode, out: &mut Vec<Occurrence>) {
+    let c = children(node);
+    let mut target = None;
+    for (i, child) in c.iter().enumerate() {
+        match child {
+            AstNode::Identifier { name, usage, var_kind: Some(k), span, .. } if usage == "used" => {
+                let o = Occurrence { name: name.clone(), var_kind: k.clone(), assigned: is_symbol(c.get(i + 1), "="), span: *span };
+                if o.assigned {
+                    // assigned after the right-hand side is evaluated
+                    target = Some(o);
+                }
+                else {
+                    out.push(o);
+                }
+            },
+            AstNode::Node { .. } => occurrences(child, out),
+            _ => {},
+        }
+    }
+    if let Some(o) = target {
+        out.push(o);
+    }
+}
+
+fn find_nodes<'a>(node: &'a AstNode, k: &str, out: &mut Vec<&'a AstNode>) {
+    for child in children(node) {
+        if kind(child) == k {
+            out.push(child);
+        }
+        find_nodes(child, k, out);
+    }
+}
+
+impl<'a> Linter<'a> {
+    fn warn(&mut self, rule: &'static str, span: Span, message: String) {
+        self.warnings.push(LintWarning { rule: rule, message: message, span: span });
+    }
+
+    fn class(&mut self, ast: &AstNode) {
+        let c = children(ast);
+        self.class_name = c.get(1).map(text).unwrap_or_default();
+        for child in c.iter().filter(|n| kind(n) == "classVarDec") {
+            self.class_vars.append(&mut definitions(child));
+        }
+
+        let mut used_class_vars = HashSet::new();
+        for sr in c.iter().filter(|n| kind(n) == "subroutineDec") {
+            let mut used = vec![];
+            occurrences(sr, &mut used);
+            for o in used.iter().filter(|o| o.var_kind == "field" || o.var_kind == "static") {
+                used_class_vars.insert(o.name.clone());
+            }
+            self.subroutine(sr, &used);
+        }
+
+        let unused: Vec<(Span, String)> = self.class_vars.iter()
+            .filter(|v| !used_class_vars.contains(&v.name))
+            .map(|v| (v.span, format!("{} {} is never used", v.var_kind, v.name)))
+            .collect();
+        for (span, message) in unused {
+            self.warn("unused-field", span, message);
+        }
+    }
+
+    fn subroutine(&mut self, sr: &AstNode, occurrences: &[Occurrence]) {
+        let c = children(sr);
+        let sr_kind = c.get(0).map(text).unwrap_or_default();
+        let mut locals = vec![];
+        for n in c.iter().filter(|n| kind(n) == "parameterList") {
+            locals.append(&mut definitions(n));
+        }
+        let mut var_decs = vec![];
+        if let Some(body) = c.iter().find(|n| kind(n) == "subroutineBody") {
+            find_nodes(body, "varDec", &mut var_decs);
+        }
+        for n in var_decs {
+            locals.append(&mut definitions(n));
+        }
+
+        for v in &locals {
+            let (rule, what) = if v.var_kind == "argument" { ("unused-parameter", "parameter") } else { ("unused-variable", "variable") };
+            if !occurrences.iter().any(|o| o.name == v.name && o.var_kind == v.var_kind) {
+                self.warn(rule, v.span, format!("{} {} is never used", what, v.name));
+            }
+            if let Some(f) = self.class_vars.iter().find(|f| f.name == v.name) {
+                let message = format!("{} {} shadows {} {}", what, v.name, f.var_kind, f.name);
+                self.warn("shadowed-field", v.span, message);
+            }
+            if v.var_kind == "var" {
+                let first = occurrences.iter().find(|o| o.name == v.name && o.var_kind == "var");
+                if let Some(o) = first.filter(|o| !o.assigned) {
+                    self.warn("uninitialized-read", o.span, format!("{} is read before it is assigned", v.name));
+                }
+            }
+        }
+
+        let mut statements = vec![];
+        find_nodes(sr, "statements", &mut statements);
+        for s in statements {
+            let c = children(s);
+            if let Some(i) = c.iter().position(|n| kind(n) == "returnStatement") {
+                if let Some(next) = c.get(i + 1).and_then(|n| n.span()) {
+                    self.warn("unreachable-code", next, "statement after return is never run".to_string());
+                }
+            }
+        }
+
+        if sr_kind == "constructor" {
+            let mut returns = vec![];
+            find_nodes(sr, "returnStatement", &mut returns);
+            for r in returns {
+                if !is_this(r) {
+                    self.warn("constructor-return", r.span().unwrap(), "a constructor must return this".to_string());
+                }
+            }
+        }
+
+        let mut lets = vec![];
+        find_nodes(sr, "letStatement", &mut lets);
+        for l in lets {
+            // let x = x;
+            let c = children(l);
+            if c.len() == 5 && is_symbol(c.get(2), "=") && is_variable(&c[3], &c[1]) {
+                self.warn("self-assignment", l.span().unwrap(), format!("{} is assigned to itself", text(&c[1])));
+            }
+        }
+
+        let mut dos = vec![];
+        find_nodes(sr, "doStatement", &mut dos);
+        for d in dos {
+            self.discarded_result(d, &locals);
+        }
+    }
+
+    fn discarded_result(&mut self, d: &AstNode, locals: &[Definition]) {
+        let index = match self.index {
+            Some(i) => i,
+            None => return,
+        };
+        // do f(...) or do x.f(...)
+        let c = children(d);
+        let (class_name, sr_name) = if is_symbol(c.get(2), ".") {
+            let receiver = text(&c[1]);
+            let var = locals.iter().chain(self.class_vars.iter()).find(|v| v.name == receiver);
+            (var.map_or(receiver, |v| v.type_name.clone()), text(&c[3]))
+        }
+        else {
+            (self.class_name.clone(), text(&c[1]))
+        };
+        let return_type = index.get(&class_name).and_then(|i| i.subroutine(&sr_name)).map(|s| s.return_type.clone());
+        if let Some(t) = return_type.filter(|t| t != "void") {
+            let message = format!("the {} returned by {}.{} is discarded", t, class_name, sr_name);
+            self.warn("discarded-result", d.span().unwrap(), message);
+        }
+    }
+}
+
+// return this;
+fn is_this(r: &AstNode) -> bool {
+    let term = children(r).get(1).map(children).and_then(|e| if e.len() == 1 { Some(&e[0]) } else { None });
+    match term.map(children) {
+        Some([AstNode::Token { kind, value, .. }]) => kind == "keyword" && value == "this",
+        _ => false,
+    }
+}
+
+// An expression that is just the variable v.
+fn is_variable(expression: &AstNode, v: &AstNode) -> bool {
+    let e = children(expression);
+    if e.len() != 1 {
+        return false;
+    }
+    match (children(&e[0]), v) {
+        ([AstNode::Identifier { name, var_kind, .. }], AstNode::Identifier { name: n, var_kind: k, .. }) => name == n && var_kind == k,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::sync::Arc;
+    use super::super::CompilationEngine::CompilationEngine;
+
+    fn lint_source(source: &str, config: &Config) -> Vec<(&'static str, usize)> {
+        let mut index = ClassIndex::new();
+        index.add(scan_class(io::Cursor::new(source.as_bytes())).unwrap()).unwrap();
+        let index = Arc::new(index);
+        let mut c = CompilationEngine::new(io::Cursor::new(source.as_bytes()), io::sink(), None);
+        c.set_ast(true);
+        c.set_class_index(index.clone());
+        c.compileClass();
+        let lines = LineIndex::new(source);
+        lint(c.ast().unwrap(), source, Some(&index), config).iter().map(|w| (w.rule, lines.position(w.span.start).0)).collect()
+    }
+
+    const SOURCE: &str = r#"class Main {
+    field int f, g;
+    constructor Main new(int a, int b) {
+        var int x, f, y;
+        let y = x;
+        let x = x;
+        do Main.two(a);
+        return x;
+        let x = 1;
+    }
+    function int two(int a) { return a + 2; }
+    method void loop() {
+        var int i;
+        while (i < 10) { let i = i + 1; } // lint-ignore: unused-parameter
+        let f = i;
+        // lint-ignore-next-line
+        do Main.two(i);
+        return;
+    }
+}
+"#;
+
+    #[test]
+    fn lint_rules() {
+        assert_eq!(lint_source(SOURCE, &Config::new()), vec![
+            ("unused-field", 2),
+            ("unused-parameter", 3),
+            ("unused-variable", 4),
+            ("shadowed-field", 4),
+            ("uninitialized-read", 5),
+            ("self-assignment", 6),
+            ("discarded-result", 7),
+            ("constructor-return", 8),
+            ("unreachable-code", 9),
+            ("uninitialized-read", 14),
+        ]);
+    }
+
+    #[test]
+    fn config() {
+        let config = Config::parse("# quieter\nunused-field = off\nuninitialized-read=off\n").unwrap();
+        assert_eq!(lint_source(SOURCE, &config), vec![
+            ("unused-parameter", 3),
+            ("unused-variable", 4),
+            ("shadowed-field", 4),
+            ("self-assignment", 6),
+            ("discarded-result", 7),
+            ("constructor-return", 8),
+            ("unreachable-code", 9),
+        ]);
+        assert!(Config::parse("unused = off").is_err());
+        assert!(Config::parse("unused-field = no").is_err());
+    }
+}
@@ -0,0 +1,489 @@
+use super::VMWriter::*;
+
+// temp register used to duplicate the top of the stack.
+// temp 0 belongs to array assignment and do statements.
+const SCRATCH_TEMP: i32 = 1;
+
+// Peephole optimizer sitting between CompilationEngine and VMWriter.
+// Pushes are held back until the next command shows whether they can be
+// folded, so "push constant 2 / push constant 3 / call Math.multiply 2"
+// never reaches the output.
+pub struct Optimizer {
+    pending: Vec<Operand>,
+    unary: Option<Command>,
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+enum Operand {
+    Const(i32),
+    Push(Segment, i32),
+}
+
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+enum BinaryOp {
+    ADD,
+    SUB,
+    AND,
+    OR,
+    EQ,
+    GT,
+    LT,
+    MULTIPLY,
+    DIVIDE,
+}
+
+impl BinaryOp {
+    fn from_command(command: &VMCommand) -> Option<BinaryOp> {
+        match command {
+            VMCommand::Arithmetic(Command::ADD) => Some(BinaryOp::ADD),
+            VMCommand::Arithmetic(Command::SUB) => Some(BinaryOp::SUB),
+            VMCommand::Arithmetic(Command::AND) => Some(BinaryOp::AND),
+            VMCommand::Arithmetic(Command::OR) => Some(BinaryOp::OR),
+            VMCommand::Arithmetic(Command::EQ) => Some(BinaryOp::EQ),
+            VMCommand::Arithmetic(Command::GT) => Some(BinaryOp::GT),
+            VMCommand::Arithmetic(Command::LT) => Some(BinaryOp::LT),
+            VMCommand::Call(name, 2) if name == "Math.multiply" => Some(BinaryOp::MULTIPLY),
+            VMCommand::Call(name, 2) if name == "Math.divide" => Some(BinaryOp::DIVIDE),
+            _ => None,
+        }
+    }
+
+    fn to_command(&self) -> VMCommand {
+        match self {
+            BinaryOp::ADD => VMCommand::Arithmetic(Command::ADD),
+            BinaryOp::SUB => VMCommand::Arithmetic(Command::SUB),
+            BinaryOp::AND => VMCommand::Arithmetic(Command::AND),
+            BinaryOp::OR => VMCommand::Arithmetic(Command::OR),
+            BinaryOp::EQ => VMCommand::Arithmetic(Command::EQ),
+            BinaryOp::GT => VMCommand::Arithmetic(Command::GT),
+            BinaryOp::LT => VMCommand::Arithmetic(Command::LT),
+            BinaryOp::MULTIPLY => VMCommand::Call("Math.multiply".to_string(), 2),
+            BinaryOp::DIVIDE => VMCommand::Call("Math.divide".to_string(), 2),
+        }
+    }
+
+    fn is_commutative(&self) -> bool {
+        match self {
+            BinaryOp::SUB | BinaryOp::GT | BinaryOp::LT | BinaryOp::DIVIDE => false,
+            _ => true,
+        }
+    }
+
+    // Evaluate with the 16 bit semantics of the Hack VM and Jack OS.
+    // Division by zero is left to Math.divide so it still reports Sys.error.
+    fn fold(&self, a: i32, b: i32) -> Option<i32> {
+        let (x, y) = (a as i16, b as i16);
+        let r = match self {
+            BinaryOp::ADD => x.wrapping_add(y),
+            BinaryOp::SUB => x.wrapping_sub(y),
+            BinaryOp::AND => x & y,
+            BinaryOp::OR => x | y,
+            BinaryOp::EQ => to_bool(x == y),
+            BinaryOp::GT => to_bool(x > y),
+            BinaryOp::LT => to_bool(x < y),
+            BinaryOp::MULTIPLY => x.wrapping_mul(y),
+            BinaryOp::DIVIDE => if y == 0 { return None } else { x.wrapping_div(y) },
+        };
+        Some(r as i32)
+    }
+
+    // Right operand which leaves the left operand unchanged.
+    fn is_identity(&self, b: i32) -> bool {
+        match self {
+            BinaryOp::ADD | BinaryOp::SUB | BinaryOp::OR => b == 0,
+            BinaryOp::AND => b == -1,
+            BinaryOp::MULTIPLY | BinaryOp::DIVIDE => b == 1,
+            _ => false,
+        }
+    }
+}
+
+fn to_bool(b: bool) -> i16 {
+    if b { -1 } else { 0 }
+}
+
+impl Optimizer {
+    pub fn new() -> Self {
+        Optimizer {
+            pending: vec![],
+            unary: None,
+        }
+    }
+
+    // Takes the next command and returns whatever can be written out now.
+    pub fn feed(&mut self, command: VMCommand) -> Vec<VMCommand> {
+        let mut out = vec![];
+        if let Some(op) = BinaryOp::from_command(&command) {
+            self.binary(op, &mut out);
+            return out;
+        }
+        match command {
+            VMCommand::Push(Segment::CONST, i) => {
+                self.release_unary(&mut out);
+                self.pending.push(Operand::Const(i));
+            },
+            VMCommand::Push(seg, i) => {
+                self.release_unary(&mut out);
+                self.pending.push(Operand::Push(seg, i));
+            },
+            VMCommand::Arithmetic(Command::NEG) | VMCommand::Arithmetic(Command::NOT) => {
+                let c = if let VMCommand::Arithmetic(c) = command { c } else { unreachable!() };
+                self.unary(c, &mut out);
+            },
+            _ => {
+                out.append(&mut self.flush());
+                out.push(command);
+            },
+        }
+        out
+    }
+
+    pub fn flush(&mut self) -> Vec<VMCommand> {
+        let mut out = vec![];
+        self.release_unary(&mut out);
+        for operand in self.pending.drain(..) {
+            materialize(&operand, &mut out);
+        }
+        out
+    }
+
+    fn release_unary(&mut self, out: &mut Vec<VMCommand>) {
+        if let Some(c) = self.unary.take() {
+            out.push(VMCommand::Arithmetic(c));
+        }
+    }
+
+    fn unary(&mut self, c: Command, out: &mut Vec<VMCommand>) {
+        if let Some(Operand::Const(n)) = self.pending.last().cloned() {
+            let r = if c == Command::NOT { !(n as i16) } else { (n as i16).wrapping_neg() };
+            self.pending.pop();
+            self.pending.push(Operand::Const(r as i32));
+            return;
+        }
+        // ~~x and --x cancel out.
+        if self.unary.as_ref() == Some(&c) {
+            self.unary = None;
+            return;
+        }
+        out.append(&mut self.flush());
+        self.unary = Some(c);
+    }
+
+    fn binary(&mut self, mut op: BinaryOp, out: &mut Vec<VMCommand>) {
+        let n = self.pending.len();
+        if n >= 2 {
+            if let (Operand::Const(a), Operand::Const(b)) = (self.pending[n - 2].clone(), self.pending[n - 1].clone()) {
+                if let Some(r) = op.fold(a, b) {
+                    self.pending.truncate(n - 2);
+                    self.pending.push(Operand::Const(r));
+                    return;
+                }
+            }
+            // bring the constant to the right where the rules below can see it
+            if op.is_commutative() {
+                if let (Operand::Const(_), Operand::Push(_, _)) = (&self.pending[n - 2], &self.pending[n - 1]) {
+                    self.pending.swap(n - 2, n - 1);
+                }
+            }
+        }
+
+        if let Some(Operand::Const(b)) = self.pending.last().cloned() {
+            if op.is_identity(b) {
+                self.pending.pop();
+                return;
+            }
+            // x + -5 is x - 5, which saves the neg.
+            if (op == BinaryOp::ADD || op == BinaryOp::SUB) && b < 0 && b != i16::MIN as i32 {
+                self.pending.pop();
+                self.pending.push(Operand::Const(-b));
+                op = if op == BinaryOp::ADD { BinaryOp::SUB } else { BinaryOp::ADD };
+            }
+            else if op == BinaryOp::MULTIPLY && b == 0 && n >= 2 {
+                if let Operand::Push(_, _) = self.pending[n - 2] {
+                    self.pending.truncate(n - 2);
+                    self.pending.push(Operand::Const(0));
+                    return;
+                }
+            }
+            // x * 2^k as k doublings, x / 2^k by its bits: longer than the
+            // call, but without Math.multiply's 16 rounds or Math.divide's
+            // recursion.
+            else if (op == BinaryOp::MULTIPLY || op == BinaryOp::DIVIDE) && b > 1 && (b & (b - 1)) == 0 {
+                self.pending.pop();
+                if op == BinaryOp::MULTIPLY {
+                    self.write_shift_left(b.trailing_zeros(), out);
+                }
+                else {
+                    self.write_shift_right(b.trailing_zeros(), out);
+                }
+                return;
+            }
+        }
+
+        out.append(&mut self.flush());
+        out.push(op.to_command());
+    }
+
+    // x * 2^k as k doublings.
+    fn write_shift_left(&mut self, k: u32, out: &mut Vec<VMCommand>) {
+        let mut remaining = k;
+        if let Some(Operand::Push(seg, i)) = self.pending.last().cloned() {
+            // a plain push can simply be repeated
+            self.pending.pop();
+            out.append(&mut self.flush());
+            out.push(VMCommand::Push(seg.clone(), i));
+            out.push(VMCommand::Push(seg, i));
+            out.push(VMCommand::Arithmetic(Command::ADD));
+            remaining -= 1;
+        }
+        else {
+            out.append(&mut self.flush());
+        }
+        for _ in 0..remaining {
+            out.push(VMCommand::Pop(Segment::TEMP, SCRATCH_TEMP));
+            out.push(VMCommand::Push(Segment::TEMP, SCRATCH_TEMP));
+            out.push(VMCommand::Push(Segment::TEMP, SCRATCH_TEMP));
+            out.push(VMCommand::Arithmetic(Command::ADD));
+        }
+    }
+
+    // x / 2^k, rounded toward zero like Math.divide. The ALU cannot shift
+    // right, so bits k to 15 are picked out one at a time.
+    fn write_shift_right(&mut self, k: u32, out: &mut Vec<VMCommand>) {
+        let temp = || VMCommand::Push(Segment::TEMP, SCRATCH_TEMP);
+        out.append(&mut self.flush());
+        out.push(VMCommand::Pop(Segment::TEMP, SCRATCH_TEMP));
+        // y = x + 2^k - 1 when x is negative, so that an arithmetic shift
+        // of y rounds toward zero
+        out.push(temp());
+        out.push(temp());
+        out.push(VMCommand::Push(Segment::CONST, 0));
+        out.push(VMCommand::Arithmetic(Command::LT));
+        out.push(VMCommand::Push(Segment::CONST, (1 << k) - 1));
+        out.push(VMCommand::Arithmetic(Command::AND));
+        out.push(VMCommand::Arithmetic(Command::ADD));
+        out.push(VMCommand::Pop(Segment::TEMP, SCRATCH_TEMP));
+        // bit i of y is worth 2^(i-k) in y >> k, and the sign bit -2^(15-k);
+        // together they add up to -1, so the result is -1 less the worth
+        // of the bits that are clear
+        materialize(&Operand::Const(-1), out);
+        for i in k..16 {
+            let mask = if i == 15 { i16::MIN as i32 } else { 1 << i };
+            let worth = if i == 15 { -(1 << (15 - k)) } else { 1 << (i - k) };
+            out.push(temp());
+            materialize(&Operand::Const(mask), out);
+            out.push(VMCommand::Arithmetic(Command::AND));
+            out.push(VMCommand::Push(Segment::CONST, 0));
+            out.push(VMCommand::Arithmetic(Command::EQ));
+            materialize(&Operand::Const(worth), out);
+            out.push(VMCommand::Arithmetic(Command::AND));
+            out.push(VMCommand::Arithmetic(Command::SUB));
+        }
+    }
+}
+
+// "push constant" only takes 0..32767, so negative values are rebuilt.
+fn materialize(operand: &Operand, out: &mut Vec<VMCommand>) {
+    match operand {
+        Operand::Const(n) if *n >= 0 => out.push(VMCommand::Push(Segment::CONST, *n)),
+        Operand::Const(n) if *n == -1 || *n == i16::MIN as i32 => {
+            out.push(VMCommand::Push(Segment::CONST, !*n));
+            out.push(VMCommand::Arithmetic(Command::NOT));
+        },
+        Operand::Const(n) => {
+            out.push(VMCommand::Push(Segment::CONST, -*n));
+            out.push(VMCommand::Arithmetic(Command::NEG));
+        },
+        Operand::Push(seg, i) => out.push(VMCommand::Push(seg.clone(), *i)),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn run(commands: Vec<VMCommand>) -> Vec<String> {
+        let mut o = Optimizer::new();
+        let mut out = vec![];
+        for c in commands {
+            out.append(&mut o.feed(c));
+        }
+        out.append(&mut o.flush());
+        out.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn fold_constants() {
+        let r = run(vec![
+            VMCommand::Push(Segment::CONST, 1),
+            VMCommand::Push(Segment::CONST, 2),
+            VMCommand::Push(Segment::CONST, 3),
+            VMCommand::Call("Math.multiply".to_string(), 2),
+            VMCommand::Arithmetic(Command::ADD),
+        ]);
+        assert_eq!(r, vec!["push constant 7"]);
+    }
+
+    #[test]
+    fn fold_negative() {
+        let r = run(vec![
+            VMCommand::Push(Segment::CONST, 2),
+            VMCommand::Push(Segment::CONST, 5),
+            VMCommand::Arithmetic(Command::SUB),
+        ]);
+        assert_eq!(r, vec!["push constant 3", "neg"]);
+
+        let r = run(vec![
+            VMCommand::Push(Segment::CONST, 0),
+            VMCommand::Arithmetic(Command::NOT),
+        ]);
+        assert_eq!(r, vec!["push constant 0", "not"]);
+    }
+
+    #[test]
+    fn keep_divide_by_zero() {
+        let r = run(vec![
+            VMCommand::Push(Segment::CONST, 4),
+            VMCommand::Push(Segment::CONST, 0),
+            VMCommand::Call("Math.divide".to_string(), 2),
+        ]);
+        assert_eq!(r, vec!["push constant 4", "push constant 0", "call Math.divide 2"]);
+    }
+
+    #[test]
+    fn identity() {
+        let r = run(vec![
+            VMCommand::Push(Segment::LOCAL, 0),
+            VMCommand::Push(Segment::CONST, 0),
+            VMCommand::Arithmetic(Command::ADD),
+            VMCommand::Pop(Segment::LOCAL, 1),
+        ]);
+        assert_eq!(r, vec!["push local 0", "pop local 1"]);
+    }
+
+    #[test]
+    fn double_not() {
+        let r = run(vec![
+            VMCommand::Push(Segment::ARG, 0),
+            VMCommand::Arithmetic(Command::NOT),
+            VMCommand::Arithmetic(Command::NOT),
+            VMCommand::Return,
+        ]);
+        assert_eq!(r, vec!["push argument 0", "return"]);
+    }
+
+    #[test]
+    fn add_negative_constant() {
+        let r = run(vec![
+            VMCommand::Push(Segment::ARG, 0),
+            VMCommand::Push(Segment::CONST, 3),
+            VMCommand::Arithmetic(Command::NEG),
+            VMCommand::Arithmetic(Command::ADD),
+        ]);
+        assert_eq!(r, vec!["push argument 0", "push constant 3", "sub"]);
+    }
+
+    #[test]
+    fn multiply_power_of_two() {
+        let r = run(vec![
+            VMCommand::Push(Segment::CONST, 2),
+            VMCommand::Push(Segment::ARG, 0),
+            VMCommand::Call("Math.multiply".to_string(), 2),
+        ]);
+        assert_eq!(r, vec!["push argument 0", "push argument 0", "add"]);
+
+        let r = run(vec![
+            VMCommand::Call("Foo.bar".to_string(), 0),
+            VMCommand::Push(Segment::CONST, 4),
+            VMCommand::Call("Math.multiply".to_string(), 2),
+        ]);
+        assert_eq!(r, vec![
+            "call Foo.bar 0",
+            "pop temp 1", "push temp 1", "push temp 1", "add",
+            "pop temp 1", "push temp 1", "push temp 1", "add",
+        ]);
+
+        let r = run(vec![
+            VMCommand::Push(Segment::LOCAL, 1),
+            VMCommand::Push(Segment::CONST, 16),
+            VMCommand::Call("Math.multiply".to_string(), 2),
+        ]);
+        assert_eq!(r.len(), 3 + 3 * 4);
+        assert!(!r.iter().any(|c| c.starts_with("call")));
+    }
+
+    // Runs commands that only use constants, temp and argument 0 = x.
+    fn evaluate(commands: &[String], x: i16) -> i16 {
+        let mut stack: Vec<i16> = vec![];
+        let mut temp = [0i16; 8];
+        for c in commands {
+            let words: Vec<&str> = c.split(' ').collect();
+            match words[..] {
+                ["push", "constant", n] => stack.push(n.parse().unwrap()),
+                ["push", "argument", "0"] => stack.push(x),
+                ["push", "temp", n] => stack.push(temp[n.parse::<usize>().unwrap()]),
+                ["pop", "temp", n] => temp[n.parse::<usize>().unwrap()] = stack.pop().unwrap(),
+                ["neg"] => { let a = stack.pop().unwrap(); stack.push(a.wrapping_neg()); },
+                ["not"] => { let a = stack.pop().unwrap(); stack.push(!a); },
+                [op] => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(match op {
+                        "add" => a.wrapping_add(b),
+                        "sub" => a.wrapping_sub(b),
+                        "and" => a & b,
+                        "or" => a | b,
+                        "eq" => -((a == b) as i16),
+                        "lt" => -((a < b) as i16),
+                        "gt" => -((a > b) as i16),
+                        _ => panic!("{}", c),
+                    });
+                },
+                _ => panic!("{}", c),
+            }
+        }
+        assert_eq!(stack.len(), 1);
+        stack[0]
+    }
+
+    #[test]
+    fn divide_power_of_two() {
+        for k in 1..15 {
+            let r = run(vec![
+                VMCommand::Push(Segment::ARG, 0),
+                VMCommand::Push(Segment::CONST, 1 << k),
+                VMCommand::Call("Math.divide".to_string(), 2),
+            ]);
+            assert!(!r.iter().any(|c| c.starts_with("call")));
+            let xs = (i16::MIN..=i16::MAX).step_by(61).chain(vec![i16::MIN, -1, 0, 1, i16::MAX]);
+            for x in xs {
+                assert_eq!(evaluate(&r, x), x.wrapping_div(1 << k), "{} / {}", x, 1 << k);
+            }
+        }
+        // and the shifts agree with Math.multiply
+        for k in 1..15 {
+            let r = run(vec![
+                VMCommand::Push(Segment::ARG, 0),
+                VMCommand::Push(Segment::CONST, 1 << k),
+                VMCommand::Call("Math.multiply".to_string(), 2),
+            ]);
+            for x in (i16::MIN..=i16::MAX).step_by(61) {
+                assert_eq!(evaluate(&r, x), x.wrapping_mul(1 << k));
+            }
+        }
+    }
+
+    #[test]
+    fn keep_non_commutative_order() {
+        let r = run(vec![
+            VMCommand::Push(Segment::CONST, 2),
+            VMCommand::Push(Segment::ARG, 0),
+            VMCommand::Arithmetic(Command::SUB),
+        ]);
+        assert_eq!(r, vec!["push constant 2", "push argument 0", "sub"]);
+    }
+}
@@ -1,7 +1,16 @@
 use std::io::{self, Write};
+use super::Optimizer::*;
 
 pub struct VMWriter<W: Write> {
-    fs: io::BufWriter<W>
+    fs: io::BufWriter<W>,
+    optimizer: Option<Optimizer>,
+    capture: Option<Vec<VMCommand>>,
+    // lines written so far
+    lines: usize,
+    // Jack line and column of the commands being written
+    position: (usize, usize),
+    // (VM line, Jack line, Jack column) of each command
+    source_map: Option<Vec<(usize, usize, usize)>>,
 } 
 
 #[derive(Clone)]
@@ -66,58 +75,171 @@ impl std::fmt::Display for Command {
     }
 }
 
+#[derive(Clone)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum VMCommand {
+    Push(Segment, i32),
+    Pop(Segment, i32),
+    Arithmetic(Command),
+    Label(String),
+    Goto(String),
+    If(String),
+    Call(String, i32),
+    Function(String, i32),
+    Return,
+}
+
+impl std::fmt::Display for VMCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VMCommand::Push(seg, index) => write!(f, "push {0} {1}", seg, index),
+            VMCommand::Pop(seg, index) => write!(f, "pop {0} {1}", seg, index),
+            VMCommand::Arithmetic(command) => write!(f, "{}", command),
+            VMCommand::Label(label) => write!(f, "label {}", label),
+            VMCommand::Goto(label) => write!(f, "goto {}", label),
+            VMCommand::If(label) => write!(f, "if-goto {}", label),
+            VMCommand::Call(name, nArgs) => write!(f, "call {0} {1}", name, nArgs),
+            VMCommand::Function(name, nLocals) => write!(f, "function {0} {1}", name, nLocals),
+            VMCommand::Return => write!(f, "return"),
+        }
+    }
+}
+
 impl<W: Write> VMWriter<W> {
     pub fn new(writer: W) -> Self {
         VMWriter {
             fs: io::BufWriter::new(writer),
+            optimizer: None,
+            capture: None,
+            lines: 0,
+            position: (0, 0),
+            source_map: None,
         }
     }
 
-    pub fn writePush(&mut self, seg: Segment, index: i32) {
-        let s = format!("push {0} {1}\r\n", seg.to_string(), index);
+    // Route every command through the peephole optimizer.
+    pub fn set_optimize(&mut self, on: bool) {
+        self.flush_pending();
+        self.optimizer = if on { Some(Optimizer::new()) } else { None };
+    }
+
+    fn emit(&mut self, command: VMCommand) {
+        let commands = match &mut self.optimizer {
+            Some(o) => o.feed(command),
+            None => vec![command],
+        };
+        for c in commands {
+            self.write_command(&c);
+        }
+    }
+
+    fn write_command(&mut self, command: &VMCommand) {
+        if let Some(c) = &mut self.capture {
+            c.push(command.clone());
+            return;
+        }
+        let s = format!("{}\r\n", command);
         self.fs.write_all(s.as_bytes());
+        self.lines += 1;
+        if let Some(m) = &mut self.source_map {
+            m.push((self.lines, self.position.0, self.position.1));
+        }
     }
 
-    pub fn writePop(&mut self, seg: Segment, index: i32) {
-        let s = format!("pop {0} {1}\r\n", seg.to_string(), index);
+    // Record where in the Jack source each command comes from.
+    pub fn set_source_map(&mut self, on: bool) {
+        self.source_map = if on { Some(vec![]) } else { None };
+    }
+
+    pub fn source_map(&self) -> Option<&Vec<(usize, usize, usize)>> {
+        self.source_map.as_ref()
+    }
+
+    // The commands written from now on come from this Jack line and column.
+    pub fn set_position(&mut self, line: usize, column: usize) {
+        self.flush_pending();
+        self.position = (line, column);
+    }
+
+    // Comments are dropped while commands are captured.
+    pub fn writeComment(&mut self, text: &str) {
+        self.flush_pending();
+        if self.capture.is_some() {
+            return;
+        }
+        let s = format!("// {}\r\n", text);
         self.fs.write_all(s.as_bytes());
+        self.lines += 1;
+    }
+
+    // Write out commands the optimizer is still holding back.
+    pub fn flush_pending(&mut self) {
+        let commands = match &mut self.optimizer {
+            Some(o) => o.flush(),
+            None => vec![],
+        };
+        for c in commands {
+            self.write_command(&c);
+        }
+    }
+
+    // Hold back commands instead of writing them, e.g. the step of a for
+    // loop which is written after the body.
+    pub fn begin_capture(&mut self) {
+        self.flush_pending();
+        self.capture = Some(vec![]);
+    }
+
+    pub fn end_capture(&mut self) -> Vec<VMCommand> {
+        self.flush_pending();
+        self.capture.take().unwrap_or_default()
+    }
+
+    pub fn write_commands(&mut self, commands: Vec<VMCommand>) {
+        for c in commands {
+            self.emit(c);
+        }
+    }
+
+    pub fn writePush(&mut self, seg: Segment, index: i32) {
+        self.emit(VMCommand::Push(seg, index));
+    }
+
+    pub fn writePop(&mut self, seg: Segment, index: i32) {
+        self.emit(VMCommand::Pop(seg, index));
     }
 
     pub fn writeArithmetic(&mut self, command: Command) {
-        let s = format!("{}\r\n", command.to_string());
-        self.fs.write_all(s.as_bytes());
+        self.emit(VMCommand::Arithmetic(command));
     }
 
     pub fn writeLabel(&mut self, label: &str) {
-        let s = format!("label {}\r\n", label);
-        self.fs.write_all(s.as_bytes());
+        self.emit(VMCommand::Label(label.to_string()));
     }
 
     pub fn writeGoto(&mut self, label: &str) {
-        let s = format!("goto {}\r\n", label);
-        self.fs.write_all(s.as_bytes());
+        self.emit(VMCommand::Goto(label.to_string()));
     }
 
     pub fn writeIf(&mut self, label: &str) {
-        let s = format!("if-goto {}\r\n", label);
-        self.fs.write_all(s.as_bytes());
+        self.emit(VMCommand::If(label.to_string()));
     }
 
     pub fn writeCall(&mut self, name: &str, nArgs :i32) {
-        let s = format!("call {0} {1}\r\n", name, nArgs);
-        self.fs.write_all(s.as_bytes());
+        self.emit(VMCommand::Call(name.to_string(), nArgs));
     }
 
     pub fn writeFunction(&mut self, name: &str, nLocals :i32) {
-        let s = format!("function {0} {1}\r\n", name, nLocals);
-        self.fs.write_all(s.as_bytes());
+        self.emit(VMCommand::Function(name.to_string(), nLocals));
     }
 
     pub fn writeReturn(&mut self) {
-        self.fs.write_all("return\r\n".as_bytes());
+        self.emit(VMCommand::Return);
     }
 
     pub fn dump_string(&mut self) -> String {
+        self.flush_pending();
         String::from_utf8(self.fs.buffer().to_vec()).unwrap()
     }
 }
\ No newline at end of file
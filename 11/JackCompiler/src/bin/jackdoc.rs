@@ -0,0 +1,42 @@
+use std::fs;
+use std::env;
+use std::path;
+
+use JackCompiler::{ApiDoc, collect_jack_files};
+
+// jackdoc [--html] [-o output] (file.jack | directory)...
+// Writes an API reference of the classes, from their /** */ comments, as
+// Markdown or as a single HTML page.
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let html = args.iter().any(|a| a == "--html");
+    let output = args.iter().position(|a| a == "-o").and_then(|i| args.get(i + 1));
+    let inputs: Vec<&String> = args.iter().enumerate().skip(1)
+        .filter(|(i, a)| !a.starts_with('-') && args[i - 1] != "-o")
+        .map(|(_, a)| a)
+        .collect();
+    if inputs.is_empty() {
+        println!("not enough arguments");
+        return Ok(());
+    }
+
+    let mut classes = vec![];
+    for input in inputs {
+        for p in collect_jack_files(path::Path::new(input))? {
+            match ApiDoc::extract(&fs::read_to_string(&p)?) {
+                Ok(c) => classes.push(c),
+                Err(e) => eprintln!("{}: {}", p.display(), e),
+            }
+        }
+    }
+    classes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let doc = if html { ApiDoc::to_html(&classes) } else { ApiDoc::to_markdown(&classes) };
+    match output {
+        Some(o) => fs::write(o, doc),
+        None => {
+            print!("{}", doc);
+            Ok(())
+        },
+    }
+}
@@ -0,0 +1,41 @@
+use std::fs;
+use std::env;
+use std::path;
+use std::process;
+
+use JackCompiler::{Formatter, collect_jack_files};
+
+// jackfmt [--check] (file.jack | directory)...
+// Formats the files in place. With --check the files are left alone and
+// the ones that would change are listed.
+fn main() -> Result<(), std::io::Error> {
+    let args: Vec<String> = env::args().collect();
+    let check = args.iter().any(|a| a == "--check");
+    let inputs: Vec<&String> = args.iter().skip(1).filter(|a| !a.starts_with('-')).collect();
+    if inputs.is_empty() {
+        println!("not enough arguments");
+        return Ok(());
+    }
+
+    let mut unformatted = false;
+    for input in inputs {
+        for p in collect_jack_files(path::Path::new(input))? {
+            let source = fs::read_to_string(&p)?;
+            let formatted = Formatter::format(&source);
+            if formatted == source {
+                continue;
+            }
+            if check {
+                println!("{}", p.display());
+                unformatted = true;
+            }
+            else {
+                fs::write(&p, formatted)?;
+            }
+        }
+    }
+    if unformatted {
+        process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use std::io::{self, BufRead, Write};
+
+use JackCompiler::Json::Json;
+use JackCompiler::LanguageServer::LanguageServer;
+
+// jacklsp
+// A language server for Jack over stdin and stdout. The project of a
+// document is the directory it is in.
+fn main() -> Result<(), std::io::Error> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let mut server = LanguageServer::new();
+    let mut shutdown = false;
+    while let Some(body) = read_message(&mut input)? {
+        let message = match Json::parse(&body) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("jacklsp: {}", e);
+                continue;
+            }
+        };
+        match message.get("method").and_then(|m| m.as_str()) {
+            Some("exit") => std::process::exit(if shutdown { 0 } else { 1 }),
+            Some("shutdown") => shutdown = true,
+            _ => {},
+        }
+        for response in server.handle(&message) {
+            let body = response.to_string();
+            write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        }
+        output.flush()?;
+    }
+    Ok(())
+}
+
+// Reads a message framed by a Content-Length header. None at the end of input.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(v) = line.strip_prefix("Content-Length:") {
+            length = v.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).to_string()))
+}
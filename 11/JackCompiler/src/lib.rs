@@ -0,0 +1,30 @@
+pub mod JackTokenizer;
+pub mod CompilationEngine;
+pub mod SymbolTable;
+pub mod VMWriter;
+pub mod Optimizer;
+pub mod ClassIndex;
+pub mod Ast;
+pub mod Formatter;
+pub mod ApiDoc;
+pub mod Linter;
+pub mod Json;
+pub mod LanguageServer;
+pub mod CWriter;
+
+use std::io;
+use std::path;
+
+// The .jack files of a directory, sorted, or the file itself.
+pub fn collect_jack_files(input: &path::Path) -> io::Result<Vec<path::PathBuf>> {
+    if input.is_file() {
+        return Ok(vec![input.to_path_buf()]);
+    }
+    let mut jack_files = input.read_dir()?
+        .filter_map(|d| d.ok())
+        .map(|d| d.path())
+        .filter(|p| p.extension().map_or(false, |e| e == "jack"))
+        .collect::<Vec<path::PathBuf>>();
+    jack_files.sort();
+    Ok(jack_files)
+}
@@ -1,50 +1,223 @@
 use std::fs;
 use std::env;
+use std::io::{self, Write};
 use std::path;
+use std::process;
+use std::sync::Arc;
+use std::thread;
 
-mod JackTokenizer;
-mod CompilationEngine;
-mod SymbolTable;
-mod VMWriter;
+use JackCompiler::{CompilationEngine, ClassIndex, Ast, Linter, CWriter, collect_jack_files};
+
+#[derive(Clone)]
+struct Options {
+    // -O0 keeps the output identical to the reference compiler.
+    optimize: bool,
+    // --pool-strings
+    pool_strings: bool,
+    // --std rejects the language extensions
+    std: bool,
+    // --precedence
+    precedence: bool,
+    // --checked
+    checked: bool,
+    // --annotate
+    annotate: bool,
+    // --emit vm,vm-map,ast-json,ast-sexp,c
+    emit_vm: bool,
+    emit_vm_map: bool,
+    emit_ast_json: bool,
+    emit_ast_sexp: bool,
+    emit_c: bool,
+    // --lint, with the rules of --lint-config or of a jacklint.conf next
+    // to each class
+    lint: bool,
+    lint_config: Option<Linter::Config>,
+}
 
 fn main() -> Result<(), std::io::Error> {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
+    let emit_pos = args.iter().position(|a| a == "--emit");
+    let emit: Vec<&str> = match emit_pos {
+        Some(i) => args.get(i + 1).map_or(vec![], |e| e.split(',').collect()),
+        None => vec!["vm"],
+    };
+    if let Some(e) = emit.iter().find(|e| !["vm", "vm-map", "ast-json", "ast-sexp", "c"].contains(e)) {
+        exit_on_errors(&[format!("unknown --emit kind: {}", e)]);
+    }
+    let lint_config_pos = args.iter().position(|a| a == "--lint-config");
+    let lint_config = match lint_config_pos.and_then(|i| args.get(i + 1)) {
+        Some(p) => match Linter::Config::parse(&fs::read_to_string(p)?) {
+            Ok(c) => Some(c),
+            Err(e) => {
+                println!("{}: {}", p, e);
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    let options = Options {
+        optimize: !args.iter().any(|a| a == "-O0"),
+        pool_strings: args.iter().any(|a| a == "--pool-strings"),
+        std: args.iter().any(|a| a == "--std"),
+        precedence: args.iter().any(|a| a == "--precedence"),
+        checked: args.iter().any(|a| a == "--checked"),
+        annotate: args.iter().any(|a| a == "--annotate"),
+        emit_vm: emit.contains(&"vm"),
+        emit_vm_map: emit.contains(&"vm-map"),
+        emit_ast_json: emit.contains(&"ast-json"),
+        emit_ast_sexp: emit.contains(&"ast-sexp"),
+        emit_c: emit.contains(&"c"),
+        lint: args.iter().any(|a| a == "--lint") || lint_config.is_some(),
+        lint_config: lint_config,
+    };
+    if options.std && options.precedence {
+        exit_on_errors(&["--precedence cannot be used with --std".to_string()]);
+    }
+    // every input is a .jack file or a directory, e.g. "Pong ../12"
+    let inputs: Vec<&String> = args.iter().enumerate().skip(1)
+        .filter(|(i, a)| !a.starts_with('-') && [emit_pos, lint_config_pos].iter().all(|p| p.map_or(true, |p| *i != p + 1)))
+        .map(|(_, a)| a)
+        .collect();
+    if inputs.is_empty() {
         println!("not enough arguments");
         return Ok(());
     }
 
-    let input = path::Path::new(&args[1]);
-    if input.is_file() {
-        let file_name = input;
+    // --emit c writes one program, named after the first input
+    let c_path = c_program_path(path::Path::new(inputs[0]));
+    let mut jack_files = vec![];
+    for input in inputs {
+        jack_files.append(&mut collect_jack_files(path::Path::new(input))?);
+    }
 
-        let f = fs::File::open(file_name)?;
-    
-        let mut path = std::path::PathBuf::from(file_name);
-        path.set_extension("vm");
-        let w_file_name = path.to_string_lossy().to_string();
-        let f_w = fs::File::create(&w_file_name)?;
-        let mut c = CompilationEngine::CompilationEngine::new(f, f_w, None);
-        c.compileClass();
-    
-        return Ok(());
+    // 1st pass: declarations of every class
+    let mut index = ClassIndex::ClassIndex::new();
+    let mut errors = vec![];
+    for p in &jack_files {
+        let r = ClassIndex::scan_class(fs::File::open(p)?).and_then(|info| index.add(info));
+        if let Err(e) = r {
+            errors.push(format!("{}: {}", p.display(), e));
+        }
     }
-    else if input.is_dir() {
-        let jack_files = input.read_dir()?
-            .filter(|d| d.as_ref().unwrap().path().extension().unwrap() == "jack" && !d.as_ref().unwrap().path().ends_with("Sys.vm"))
-            .map(|d| d.unwrap().path())
-            .collect::<Vec<path::PathBuf>>();
-        
-        for p in jack_files {
-            print!("{:?}", p);
-            let mut path = std::path::PathBuf::from(p.to_string_lossy().to_string());
-            path.set_extension("vm");
-            let f = fs::File::open(p)?;
-            let w_file_name = path.to_string_lossy().to_string();
-            let f_w = fs::File::create(&w_file_name)?;
-            let mut c = CompilationEngine::CompilationEngine::new(f, f_w, None);
-            c.compileClass();
+    exit_on_errors(&errors);
+
+    // 2nd pass: compile each class against the index
+    let index = Arc::new(index);
+    let handles = jack_files.into_iter()
+        .map(|p| {
+            let index = index.clone();
+            let options = options.clone();
+            thread::spawn(move || {
+                let r = compile_file(&p, index, &options);
+                (p, r)
+            })
+        })
+        .collect::<Vec<thread::JoinHandle<(path::PathBuf, io::Result<(Vec<String>, Option<CWriter::CClass>)>)>>>();
+
+    let mut c_classes = vec![];
+    for h in handles {
+        match h.join() {
+            Ok((p, r)) => {
+                let (class_errors, c_class) = r?;
+                for e in class_errors {
+                    errors.push(format!("{}: {}", p.display(), e));
+                }
+                c_classes.extend(c_class);
+            },
+            Err(_) => errors.push("compilation aborted by a syntax error".to_string()),
         }
     }
+    exit_on_errors(&errors);
+    if options.emit_c {
+        fs::write(&c_path, CWriter::program(&c_classes))?;
+    }
     Ok(())
 }
+
+// Dir/Dir.c for a directory, Main.c for Main.jack.
+fn c_program_path(input: &path::Path) -> path::PathBuf {
+    if input.is_dir() {
+        let name = input.canonicalize().ok()
+            .and_then(|d| d.file_name().map(|n| n.to_os_string()))
+            .unwrap_or_else(|| "Main".into());
+        return input.join(name).with_extension("c");
+    }
+    input.with_extension("c")
+}
+
+fn compile_file(p: &path::Path, index: Arc<ClassIndex::ClassIndex>, options: &Options) -> io::Result<(Vec<String>, Option<CWriter::CClass>)> {
+    let f = fs::File::open(p)?;
+    let f_w: Box<dyn Write> = if options.emit_vm {
+        Box::new(fs::File::create(p.with_extension("vm"))?)
+    }
+    else {
+        Box::new(io::sink())
+    };
+    let mut c = CompilationEngine::CompilationEngine::new(f, f_w, None);
+    c.set_optimize(options.optimize);
+    c.set_string_pool(options.pool_strings);
+    c.set_std(options.std);
+    c.set_precedence(options.precedence);
+    c.set_checked(options.checked);
+    if options.annotate || options.emit_vm_map {
+        let file_name = p.file_name().unwrap().to_string_lossy().to_string();
+        c.set_source(&file_name, &fs::read_to_string(p)?);
+        c.set_annotate(options.annotate);
+        c.set_source_map(options.emit_vm_map);
+    }
+    c.set_class_index(index.clone());
+    c.set_ast(options.emit_ast_json || options.emit_ast_sexp || options.lint || options.emit_c);
+    c.compileClass();
+    for w in c.warnings() {
+        eprintln!("{}: warning: {}", p.display(), w);
+    }
+    if let Some(map) = c.source_map() {
+        fs::write(p.with_extension("vm.map"), map)?;
+    }
+
+    let mut c_class = None;
+    if let Some(ast) = c.ast() {
+        let source = fs::read_to_string(p)?;
+        let lines = Ast::LineIndex::new(&source);
+        if options.lint {
+            let config = match &options.lint_config {
+                Some(c) => c.clone(),
+                None => lint_config_for(p)?,
+            };
+            for w in Linter::lint(ast, &source, Some(&index), &config) {
+                let (line, column) = lines.position(w.span.start);
+                eprintln!("{}:{}:{}: warning: {} [{}]", p.display(), line, column, w.message, w.rule);
+            }
+        }
+        if options.emit_ast_json {
+            fs::write(p.with_extension("ast.json"), ast.to_json(&lines))?;
+        }
+        if options.emit_ast_sexp {
+            fs::write(p.with_extension("ast.sexp"), ast.to_sexp(&lines))?;
+        }
+        if options.emit_c {
+            c_class = Some(CWriter::translate(ast, Some(&index), options.precedence));
+        }
+    }
+    Ok((c.errors().clone(), c_class))
+}
+
+// jacklint.conf in the directory of the class, if there is one.
+fn lint_config_for(p: &path::Path) -> io::Result<Linter::Config> {
+    let conf = p.with_file_name("jacklint.conf");
+    if !conf.is_file() {
+        return Ok(Linter::Config::new());
+    }
+    Linter::Config::parse(&fs::read_to_string(&conf)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", conf.display(), e)))
+}
+
+fn exit_on_errors(errors: &[String]) {
+    if errors.is_empty() {
+        return;
+    }
+    for e in errors {
+        eprintln!("{}", e);
+    }
+    process::exit(1);
+}